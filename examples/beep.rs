@@ -1,14 +1,15 @@
 use anyhow;
 use clap::Parser;
 use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
+    traits::{DeviceTrait, StreamTrait},
     FromSample, Sample, SizedSample,
 };
+use pika_pulse::audio_setup::{DeviceSelector, Direction};
 
 #[derive(Parser, Debug)]
 #[command(version, about = "CPAL beep example", long_about = None)]
 struct Opt {
-    /// The audio device to use
+    /// The audio device to use, by name or part of its name
     #[arg(short, long, default_value_t = String::from("default"))]
     device: String,
 
@@ -64,13 +65,16 @@ fn main() -> anyhow::Result<()> {
     ))]
     let host = cpal::default_host();
 
-    let device = if opt.device == "default" {
-        host.default_output_device()
+    // "default" honours OUTPUT_DEVICE_INDEX / OUTPUT_DEVICE like the player does
+    let selector = if opt.device == "default" {
+        DeviceSelector::from_env(Direction::Output)?
     } else {
-        host.output_devices()?
-            .find(|x| x.name().map(|y| y == opt.device).unwrap_or(false))
-    }
-    .expect("failed to find output device");
+        DeviceSelector::new()
+            .with_name(&opt.device)
+            .with_pattern(&opt.device)
+            .with_fallback(false)
+    };
+    let device = selector.select_on(&host, Direction::Output)?;
     println!("Output device: {}", device.name()?);
 
     let config = device.default_output_config().unwrap();
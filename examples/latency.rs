@@ -0,0 +1,21 @@
+use pika_pulse::audio_setup::{
+    measure_latency, DeviceLoopback, DeviceSelector, Direction, LatencyConfig,
+};
+
+// Measures the round-trip latency with the output wired or held to the input:
+// cargo run --example latency
+// Pick the devices with DEVICE / DEVICE_INDEX and OUTPUT_DEVICE / OUTPUT_DEVICE_INDEX.
+fn main() -> Result<(), anyhow::Error> {
+    let input = DeviceSelector::from_env(Direction::Input)?.select_input()?;
+    let output = DeviceSelector::from_env(Direction::Output)?.select_output()?;
+    let mut loopback = DeviceLoopback::new(&input, &output);
+
+    let measurement = measure_latency(&mut loopback, &LatencyConfig::default())?;
+    println!(
+        "Latency: {:.2} ms ({:.1} frames), confidence {:.2}",
+        measurement.latency.as_secs_f64() * 1000.0,
+        measurement.frames,
+        measurement.confidence
+    );
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use pika_pulse::audio_setup::{DeviceSelector, Direction, LatencyConfig};
+use pika_pulse::recorder::monitor::MonitorConfig;
+use pika_pulse::recorder::Recorder;
+use std::time::Duration;
+
+// Plays the microphone on the headphones to check its placement:
+// cargo run --example monitor
+// Pick the devices with DEVICE / DEVICE_INDEX and OUTPUT_DEVICE / OUTPUT_DEVICE_INDEX.
+// Pass --calibrate with the headphones held to the microphone to measure the
+// full round trip.
+fn main() -> Result<(), anyhow::Error> {
+    let input = DeviceSelector::from_env(Direction::Input)?.select_input()?;
+    let output = DeviceSelector::from_env(Direction::Output)?.select_output()?;
+    let mut recorder = Recorder::with_device(&input)?;
+    recorder.start_monitor(&output, &MonitorConfig::default())?;
+    if std::env::args().any(|arg| arg == "--calibrate") {
+        let measured = recorder.calibrate_monitor(&LatencyConfig::default())?;
+        println!(
+            "Devices add {:.1} ms (confidence {:.2})",
+            measured.latency.as_secs_f64() * 1000.0,
+            measured.confidence
+        );
+    }
+
+    // Report the latency until feedback mutes the output.
+    loop {
+        std::thread::sleep(Duration::from_secs(1));
+        let Some(status) = recorder.monitor_status() else {
+            break;
+        };
+        if let Some(frequency) = status.howl_frequency {
+            println!("Feedback at {:.0} Hz, muted", frequency);
+            break;
+        }
+        if let Some(latency) = status.latency {
+            if let Some(round_trip) = latency.round_trip() {
+                print!("{:.1} ms round trip, ", round_trip.as_secs_f64() * 1000.0);
+            }
+            println!(
+                "{:.1} ms buffering (input {:.1}, queued {:.1}, output {:.1}), {} underruns",
+                latency.buffering().as_secs_f64() * 1000.0,
+                latency.input.as_secs_f64() * 1000.0,
+                latency.buffered.as_secs_f64() * 1000.0,
+                latency.output.as_secs_f64() * 1000.0,
+                status.underruns
+            );
+        }
+    }
+    Ok(())
+}
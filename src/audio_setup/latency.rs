@@ -0,0 +1,516 @@
+use crate::dsp::{cross_correlate, db_to_gain, Resampler};
+use crate::player::{CpalSink, OutputSink};
+use anyhow::anyhow;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::Device;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Fade applied to both ends of a chirp so it starts and stops without a click.
+const CHIRP_FADE: Duration = Duration::from_millis(5);
+
+/// Lags around the correlation peak ignored when looking for the next highest
+/// one, as they belong to the peak itself.
+const PEAK_WIDTH: Duration = Duration::from_millis(2);
+
+/// Known signal played through the loopback to find the delay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TestSignal {
+    /// Maximum length sequence of `2^order - 1` samples, from 10 to 20. Rides
+    /// out background noise best.
+    Mls { order: u32 },
+    /// Exponential sine sweep between two frequencies in Hz. Copes best with
+    /// speakers and microphones that distort or roll off.
+    Chirp {
+        start_hz: f32,
+        end_hz: f32,
+        length: Duration,
+    },
+}
+
+impl Default for TestSignal {
+    fn default() -> Self {
+        TestSignal::Chirp {
+            start_hz: 100.0,
+            end_hz: 10_000.0,
+            length: Duration::from_millis(500),
+        }
+    }
+}
+
+impl TestSignal {
+    /// Renders the signal at `sample_rate`, peaking at full scale.
+    pub fn generate(&self, sample_rate: u32) -> Vec<f32> {
+        match *self {
+            TestSignal::Mls { order } => mls(order),
+            TestSignal::Chirp {
+                start_hz,
+                end_hz,
+                length,
+            } => chirp(start_hz, end_hz, length, sample_rate),
+        }
+    }
+}
+
+/// How a latency measurement is run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyConfig {
+    pub signal: TestSignal,
+    /// Level of the test signal, in dBFS.
+    pub level_db: f32,
+    /// Silence played before the signal, while the devices settle.
+    pub lead: Duration,
+    /// Longest delay looked for; recording goes on this long after the signal.
+    pub max_latency: Duration,
+}
+
+impl Default for LatencyConfig {
+    fn default() -> Self {
+        LatencyConfig {
+            signal: TestSignal::default(),
+            level_db: -12.0,
+            lead: Duration::from_millis(200),
+            max_latency: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The delay found by [`measure_latency`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyMeasurement {
+    /// Time from a sample being handed to the output to it arriving at the input.
+    pub latency: Duration,
+    /// The same delay in frames at the loopback's sample rate, to a fraction
+    /// of a frame.
+    pub frames: f64,
+    /// How clearly the signal stood out, from 0 to 1: one minus the ratio of
+    /// the next highest correlation peak to the one chosen. Echoes and noise
+    /// lower it; below about 0.5 the result should not be trusted.
+    pub confidence: f32,
+}
+
+/// Plays a signal and records what comes back.
+pub trait Loopback {
+    /// Returns the sample rate signals are played and recorded at.
+    fn sample_rate(&self) -> u32;
+
+    /// Plays `signal` and records for `tail` longer.
+    ///
+    /// # Returns
+    /// * `Vec<f32>` - The mono recording, where index `n` was captured the
+    ///   latency after `signal[n]` was handed to the output.
+    fn run(&mut self, signal: &[f32], tail: Duration) -> Result<Vec<f32>, anyhow::Error>;
+}
+
+/// Plays on an output device while recording from an input device, such as
+/// the headphone jack wired or held to the microphone.
+///
+/// The two streams are lined up by when their callbacks run, so the measured
+/// delay covers both devices' buffers as well as the converters and whatever
+/// lies between them: the offset between rendering a sample and receiving it.
+pub struct DeviceLoopback {
+    input: Device,
+    output: Device,
+}
+
+impl DeviceLoopback {
+    /// Creates a loopback from `output` to `input`.
+    pub fn new(input: &Device, output: &Device) -> DeviceLoopback {
+        DeviceLoopback {
+            input: input.clone(),
+            output: output.clone(),
+        }
+    }
+}
+
+/// Audio captured by the input stream, with estimates of when it started.
+#[derive(Default)]
+struct Capture {
+    samples: Vec<f32>,
+    /// When the stream's first frame was captured, in seconds after the
+    /// measurement began, as estimated from each block.
+    starts: Vec<f64>,
+}
+
+impl Loopback for DeviceLoopback {
+    fn sample_rate(&self) -> u32 {
+        self.output
+            .default_output_config()
+            .map_or(0, |config| config.sample_rate().0)
+    }
+
+    fn run(&mut self, signal: &[f32], tail: Duration) -> Result<Vec<f32>, anyhow::Error> {
+        let input_config: cpal::StreamConfig = self.input.default_input_config()?.into();
+        let input_rate = input_config.sample_rate.0;
+        let input_channels = input_config.channels as usize;
+        let began = Instant::now();
+        let capture = Arc::new(Mutex::new(Capture::default()));
+
+        let input_capture = capture.clone();
+        let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+        let input = self.input.build_input_stream(
+            &input_config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                let now = began.elapsed().as_secs_f64();
+                let mut capture = input_capture.lock().unwrap();
+                for frame in data.chunks_exact(input_channels) {
+                    capture
+                        .samples
+                        .push(frame.iter().sum::<f32>() / input_channels as f32);
+                }
+                // The last frame of the block was captured just before the callback.
+                let end = capture.samples.len() as f64 / input_rate as f64;
+                capture.starts.push(now - end);
+            },
+            err_fn,
+            None,
+        )?;
+        input.play()?;
+
+        let mut sink = CpalSink::new(self.output.clone())?;
+        let output_rate = sink.sample_rate();
+        let output_channels = sink.channels() as usize;
+        let played = Arc::new(AtomicUsize::new(0));
+        let output_starts = Arc::new(Mutex::new(Vec::new()));
+        let render_signal = signal.to_vec();
+        let render_played = played.clone();
+        let render_starts = output_starts.clone();
+        sink.start(Box::new(move |data: &mut [f32]| {
+            let now = began.elapsed().as_secs_f64();
+            let first = render_played.load(Ordering::Relaxed);
+            // The first frame of the block is handed over as the callback runs.
+            if let Ok(mut starts) = render_starts.try_lock() {
+                starts.push(now - first as f64 / output_rate as f64);
+            }
+            for (i, frame) in data.chunks_exact_mut(output_channels).enumerate() {
+                frame.fill(render_signal.get(first + i).copied().unwrap_or(0.0));
+            }
+            render_played.fetch_add(data.len() / output_channels, Ordering::Relaxed);
+            true
+        }))?;
+
+        let length = Duration::from_secs_f64(signal.len() as f64 / output_rate as f64);
+        let deadline = Instant::now() + length * 2 + Duration::from_secs(1);
+        while played.load(Ordering::Relaxed) < signal.len() {
+            if Instant::now() > deadline {
+                return Err(anyhow!("the output device stopped requesting audio"));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(tail);
+        drop(sink);
+        drop(input);
+
+        // Callbacks run late under load, and output ones early while the
+        // device fills its buffer, so take the median of the estimates.
+        let mut capture = std::mem::take(&mut *capture.lock().unwrap());
+        let input_start = median(&mut capture.starts)
+            .ok_or_else(|| anyhow!("the input device delivered no audio"))?;
+        let output_start = median(&mut output_starts.lock().unwrap())
+            .ok_or_else(|| anyhow!("the output device requested no audio"))?;
+
+        // Line the recording up with the signal: drop what was captured before
+        // the first output frame, or pad if the input started later.
+        let offset = ((output_start - input_start) * input_rate as f64).round() as isize;
+        let mut recording = if offset >= 0 {
+            capture.samples[(offset as usize).min(capture.samples.len())..].to_vec()
+        } else {
+            let mut padded = vec![0.0; offset.unsigned_abs()];
+            padded.extend_from_slice(&capture.samples);
+            padded
+        };
+        if input_rate != output_rate {
+            let mut resampler = Resampler::new(input_rate, output_rate, 1);
+            let mut resampled = Vec::new();
+            resampler.process(&recording, &mut resampled);
+            resampler.finish(&mut resampled);
+            recording = resampled;
+        }
+        Ok(recording)
+    }
+}
+
+/// Returns the middle of `values`, sorting them.
+fn median(values: &mut [f64]) -> Option<f64> {
+    values.sort_by(f64::total_cmp);
+    values.get(values.len() / 2).copied()
+}
+
+/// Delays signals by a known amount, to check measurements without hardware.
+pub struct SimulatedLoopback {
+    sample_rate: u32,
+    delay: Duration,
+    gain: f32,
+    noise: f32,
+    seed: u32,
+}
+
+impl SimulatedLoopback {
+    /// Creates a loopback that returns signals `delay` late and unchanged.
+    pub fn new(sample_rate: u32, delay: Duration) -> SimulatedLoopback {
+        SimulatedLoopback {
+            sample_rate,
+            delay,
+            gain: 1.0,
+            noise: 0.0,
+            seed: 1,
+        }
+    }
+
+    /// Scales the returned signal by the linear `gain`.
+    pub fn with_gain(mut self, gain: f32) -> SimulatedLoopback {
+        self.gain = gain;
+        self
+    }
+
+    /// Adds white noise with an RMS level of `level_db` dBFS.
+    pub fn with_noise(mut self, level_db: f32) -> SimulatedLoopback {
+        self.noise = db_to_gain(level_db);
+        self
+    }
+}
+
+impl Loopback for SimulatedLoopback {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn run(&mut self, signal: &[f32], tail: Duration) -> Result<Vec<f32>, anyhow::Error> {
+        let frames = |d: Duration| (d.as_secs_f64() * self.sample_rate as f64).round() as usize;
+        let delay = frames(self.delay);
+        let mut recording = vec![0.0; signal.len() + frames(tail)];
+        for (out, s) in recording.iter_mut().skip(delay).zip(signal) {
+            *out = s * self.gain;
+        }
+        // Uniform noise has an RMS of its half-width over the square root of 3.
+        let width = self.noise * 3f32.sqrt();
+        for sample in recording.iter_mut() {
+            self.seed = self
+                .seed
+                .wrapping_mul(1_664_525)
+                .wrapping_add(1_013_904_223);
+            let uniform = (self.seed >> 8) as f32 / (1u32 << 24) as f32;
+            *sample += (uniform * 2.0 - 1.0) * width;
+        }
+        Ok(recording)
+    }
+}
+
+/// Measures the delay of `loopback` by playing a known signal through it and
+/// finding it in the recording by cross-correlation.
+///
+/// # Arguments
+/// * `loopback` - Where the signal is played and recorded.
+/// * `config` - The signal, its level and how long a delay to look for.
+///
+/// # Returns
+/// * `LatencyMeasurement` - The delay to a fraction of a frame, and how
+///   confident the match is.
+pub fn measure_latency(
+    loopback: &mut dyn Loopback,
+    config: &LatencyConfig,
+) -> Result<LatencyMeasurement, anyhow::Error> {
+    let rate = loopback.sample_rate();
+    if rate == 0 {
+        return Err(anyhow!("the loopback has no sample rate"));
+    }
+    let frames = |d: Duration| (d.as_secs_f64() * rate as f64).round() as usize;
+    let gain = db_to_gain(config.level_db.min(0.0));
+    let reference: Vec<f32> = config
+        .signal
+        .generate(rate)
+        .into_iter()
+        .map(|s| s * gain)
+        .collect();
+    let lead = frames(config.lead);
+    let mut played = vec![0.0; lead];
+    played.extend_from_slice(&reference);
+
+    let recording = loopback.run(&played, config.max_latency)?;
+    let input = &recording[lead.min(recording.len())..];
+    let correlation = cross_correlate(input, &reference, frames(config.max_latency));
+
+    let (peak, &height) = correlation
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .ok_or_else(|| anyhow!("nothing was recorded"))?;
+    if height <= 0.0 {
+        return Err(anyhow!(
+            "the test signal was not found; check the loopback connection and levels"
+        ));
+    }
+    let width = frames(PEAK_WIDTH).max(1);
+    let runner_up = correlation
+        .iter()
+        .enumerate()
+        .filter(|(lag, _)| lag.abs_diff(peak) > width)
+        .map(|(_, c)| c.abs())
+        .fold(0.0, f32::max);
+
+    let frames = peak as f64 + refine_peak(&correlation, peak);
+    Ok(LatencyMeasurement {
+        latency: Duration::from_secs_f64(frames / rate as f64),
+        frames,
+        confidence: (1.0 - runner_up / height).clamp(0.0, 1.0),
+    })
+}
+
+/// Returns the offset of the true peak from `peak`, within half a lag, by
+/// fitting a parabola through it and its neighbours.
+fn refine_peak(correlation: &[f32], peak: usize) -> f64 {
+    if peak == 0 || peak + 1 >= correlation.len() {
+        return 0.0;
+    }
+    let (a, b, c) = (
+        correlation[peak - 1] as f64,
+        correlation[peak] as f64,
+        correlation[peak + 1] as f64,
+    );
+    let curvature = a - 2.0 * b + c;
+    if curvature >= 0.0 {
+        return 0.0;
+    }
+    (0.5 * (a - c) / curvature).clamp(-0.5, 0.5)
+}
+
+/// Feedback taps of a maximal-length shift register for each order from 10 to 20.
+const MLS_TAPS: [&[u32]; 11] = [
+    &[10, 7],
+    &[11, 9],
+    &[12, 6, 4, 1],
+    &[13, 4, 3, 1],
+    &[14, 5, 3, 1],
+    &[15, 14],
+    &[16, 15, 13, 4],
+    &[17, 14],
+    &[18, 11],
+    &[19, 6, 2, 1],
+    &[20, 17],
+];
+
+/// Generates a maximum length sequence of ±1 samples.
+fn mls(order: u32) -> Vec<f32> {
+    let order = order.clamp(10, 20);
+    let taps = MLS_TAPS[order as usize - 10];
+    let mask = (1u32 << order) - 1;
+    let mut state = mask;
+    (0..mask)
+        .map(|_| {
+            let bit = taps
+                .iter()
+                .fold(0, |acc, tap| acc ^ ((state >> (tap - 1)) & 1));
+            state = ((state << 1) | bit) & mask;
+            if bit == 1 {
+                1.0
+            } else {
+                -1.0
+            }
+        })
+        .collect()
+}
+
+/// Generates an exponential sine sweep, faded in and out.
+fn chirp(start_hz: f32, end_hz: f32, length: Duration, sample_rate: u32) -> Vec<f32> {
+    let rate = sample_rate as f64;
+    let nyquist = rate / 2.0;
+    let f0 = (start_hz as f64).clamp(1.0, nyquist);
+    let f1 = (end_hz as f64).clamp(1.0, nyquist);
+    let secs = length.as_secs_f64();
+    let frames = (secs * rate) as usize;
+    let fade = ((CHIRP_FADE.as_secs_f64() * rate) as usize)
+        .min(frames / 2)
+        .max(1);
+    let ratio = (f1 / f0).ln();
+    (0..frames)
+        .map(|n| {
+            let t = n as f64 / rate;
+            let phase = if ratio.abs() < 1e-9 {
+                2.0 * PI * f0 * t
+            } else {
+                2.0 * PI * f0 * secs / ratio * ((t / secs * ratio).exp() - 1.0)
+            };
+            let edge = n.min(frames - 1 - n) as f64 / fade as f64;
+            (phase.sin() * edge.min(1.0)) as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    fn measure(signal: TestSignal, delay_ms: f64, noise_db: Option<f32>) -> LatencyMeasurement {
+        let delay = Duration::from_secs_f64(delay_ms / 1000.0);
+        let mut loopback = SimulatedLoopback::new(RATE, delay).with_gain(0.3);
+        if let Some(noise_db) = noise_db {
+            loopback = loopback.with_noise(noise_db);
+        }
+        let config = LatencyConfig {
+            signal,
+            ..LatencyConfig::default()
+        };
+        measure_latency(&mut loopback, &config).unwrap()
+    }
+
+    fn check(signal: TestSignal, noise_db: Option<f32>) {
+        for delay_ms in [0.0, 3.7, 12.345, 250.0] {
+            let measurement = measure(signal, delay_ms, noise_db);
+            let expected = (delay_ms / 1000.0 * RATE as f64).round();
+            assert!(
+                (measurement.frames - expected).abs() <= 0.5,
+                "{:?} at {} ms: {} frames",
+                signal,
+                delay_ms,
+                measurement.frames
+            );
+            assert!(measurement.confidence > 0.7, "{:?}", measurement);
+        }
+    }
+
+    #[test]
+    fn mls_finds_known_delay() {
+        check(TestSignal::Mls { order: 14 }, None);
+    }
+
+    #[test]
+    fn mls_finds_known_delay_in_noise() {
+        check(TestSignal::Mls { order: 15 }, Some(-10.0));
+    }
+
+    #[test]
+    fn chirp_finds_known_delay() {
+        check(TestSignal::default(), None);
+    }
+
+    #[test]
+    fn chirp_finds_known_delay_in_noise() {
+        check(TestSignal::default(), Some(-20.0));
+    }
+
+    #[test]
+    fn noise_alone_has_no_confidence() {
+        let mut loopback = SimulatedLoopback::new(RATE, Duration::ZERO)
+            .with_gain(0.0)
+            .with_noise(-20.0);
+        match measure_latency(&mut loopback, &LatencyConfig::default()) {
+            Ok(measurement) => assert!(measurement.confidence < 0.5, "{:?}", measurement),
+            Err(e) => assert!(e.to_string().contains("not found"), "{:#}", e),
+        }
+    }
+
+    #[test]
+    fn mls_has_maximal_length() {
+        for order in 10..=20 {
+            let sequence = TestSignal::Mls { order }.generate(RATE);
+            assert_eq!(sequence.len(), (1 << order) - 1);
+            // A maximal sequence has one more of one sign than the other.
+            assert_eq!(sequence.iter().sum::<f32>().abs(), 1.0, "order {}", order);
+        }
+    }
+}
@@ -1,59 +1,58 @@
+mod latency;
+mod selector;
+
 use audio_visualizer::dynamic::live_input::{setup_audio_input_loop, AudioDevAndCfg};
 use cpal::{Device, Stream};
 use ringbuffer::AllocRingBuffer;
-use std::env;
 use std::sync::{Arc, Mutex};
-use cpal::traits::{HostTrait, DeviceTrait};
+
+pub use latency::{
+    measure_latency, DeviceLoopback, LatencyConfig, LatencyMeasurement, Loopback,
+    SimulatedLoopback, TestSignal,
+};
+pub use selector::{list_devs, DeviceMatch, DeviceSelector, Direction};
 
 pub fn select_input_dev() -> Device {
-    let mut devs = list_input_devs();
+    let devs = list_input_devs();
     assert!(!devs.is_empty(), "no input devices found!");
-    // If there is only one input device, use it
-    if devs.len() == 1 {
-        return devs.remove(0).1;
-    }
 
     // Display available input devices
-    println!("Available input devices:");
-    for (index, (name, _)) in devs.iter().enumerate() {
-        println!("  [{}] {}", index, name);
+    if devs.len() > 1 {
+        println!("Available input devices:");
+        for (index, (name, _)) in devs.iter().enumerate() {
+            println!("  [{}] {}", index, name);
+        }
     }
 
-    // Grab device index from environment variable
-    let index = env::var("DEVICE_INDEX")
-        .expect("Please set the DEVICE_INDEX environment variable")
-        .parse::<usize>()
-        .expect("Invalid DEVICE_INDEX environment variable");
-    devs.remove(index).1
+    // Pick with DEVICE_INDEX or DEVICE, or the default device if neither is set
+    DeviceSelector::from_env(Direction::Input)
+        .and_then(|selector| selector.select_input())
+        .expect("failed to select an input device")
 }
 
 pub fn setup_input_config() -> AudioDevAndCfg {
     let in_dev = select_input_dev();
-    let input_dev_and_cfg = AudioDevAndCfg::new(Some(in_dev), None);
-    input_dev_and_cfg
+    AudioDevAndCfg::new(Some(in_dev), None)
 }
 
 pub fn setup_live_input(
     latest_audio_data: Arc<Mutex<AllocRingBuffer<f32>>>,
     input_dev_and_cfg: AudioDevAndCfg,
 ) -> Stream {
-    let stream = setup_audio_input_loop(latest_audio_data.clone(), input_dev_and_cfg);
-    stream
+    setup_audio_input_loop(latest_audio_data.clone(), input_dev_and_cfg)
 }
 
 pub fn list_input_devs() -> Vec<(String, cpal::Device)> {
-    let host = cpal::default_host();
-    type DeviceName = String;
-    let mut devs: Vec<(DeviceName, Device)> = host
-        .input_devices()
-        .unwrap()
-        .map(|dev| {
-            (
-                dev.name().unwrap_or_else(|_| String::from("<unknown>")),
-                dev,
-            )
-        })
-        .collect();
-    devs.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
-    devs
+    list_devs(&cpal::default_host(), Direction::Input)
+}
+
+/// Picks the playback device with `OUTPUT_DEVICE_INDEX` or `OUTPUT_DEVICE`,
+/// as `DEVICE_INDEX` and `DEVICE` do for capture. Without either, the host's
+/// default is used, and then the first device.
+pub fn select_output_dev() -> Result<Device, anyhow::Error> {
+    DeviceSelector::from_env(Direction::Output)?.select_output()
+}
+
+pub fn list_output_devs() -> Vec<(String, cpal::Device)> {
+    list_devs(&cpal::default_host(), Direction::Output)
 }
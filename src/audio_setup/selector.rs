@@ -0,0 +1,220 @@
+use anyhow::anyhow;
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host};
+use std::env;
+use std::fmt;
+
+/// Whether a device records or plays audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+impl Direction {
+    /// Prefix of the environment variables that pick a device of this direction.
+    fn env_prefix(self) -> &'static str {
+        match self {
+            Direction::Input => "",
+            Direction::Output => "OUTPUT_",
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Input => write!(f, "input"),
+            Direction::Output => write!(f, "output"),
+        }
+    }
+}
+
+/// One way of recognizing a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceMatch {
+    /// The device with exactly this name.
+    Name(String),
+    /// The first device, in name order, whose name contains this text,
+    /// ignoring case.
+    Pattern(String),
+    /// The device at this position in the list sorted by name, as printed by
+    /// [`list_input_devs`](super::list_input_devs) and
+    /// [`list_output_devs`](super::list_output_devs).
+    Index(usize),
+    /// The host's default device.
+    Default,
+}
+
+impl DeviceMatch {
+    fn find(&self, mut devs: Vec<(String, Device)>, default: Option<Device>) -> Option<Device> {
+        match self {
+            DeviceMatch::Name(name) => devs
+                .into_iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, dev)| dev),
+            DeviceMatch::Pattern(pattern) => {
+                let pattern = pattern.to_lowercase();
+                devs.into_iter()
+                    .find(|(n, _)| n.to_lowercase().contains(&pattern))
+                    .map(|(_, dev)| dev)
+            }
+            DeviceMatch::Index(index) => (*index < devs.len()).then(|| devs.remove(*index).1),
+            DeviceMatch::Default => default,
+        }
+    }
+}
+
+impl fmt::Display for DeviceMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceMatch::Name(name) => write!(f, "named '{}'", name),
+            DeviceMatch::Pattern(pattern) => write!(f, "matching '{}'", pattern),
+            DeviceMatch::Index(index) => write!(f, "with index {}", index),
+            DeviceMatch::Default => write!(f, "set as default"),
+        }
+    }
+}
+
+/// Picks an input or output device by name, name pattern or index, trying
+/// each preference in turn.
+///
+/// If nothing matches, the host's default device is used, then the first
+/// device, unless the fallback is turned off. The same selector works for
+/// both directions, so the capture and playback devices are chosen alike and
+/// can differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceSelector {
+    preferences: Vec<DeviceMatch>,
+    fallback: bool,
+}
+
+impl Default for DeviceSelector {
+    fn default() -> Self {
+        DeviceSelector {
+            preferences: Vec::new(),
+            fallback: true,
+        }
+    }
+}
+
+impl DeviceSelector {
+    /// Creates a selector with no preferences, which picks the default device.
+    pub fn new() -> DeviceSelector {
+        DeviceSelector::default()
+    }
+
+    /// Builds a selector from the environment.
+    ///
+    /// For capture `DEVICE_INDEX` picks a device by index and `DEVICE` by name
+    /// pattern; for playback `OUTPUT_DEVICE_INDEX` and `OUTPUT_DEVICE` do the
+    /// same. An index takes precedence over a pattern. When either is set
+    /// there is no fallback, so a device that is not there is an error rather
+    /// than a surprise.
+    pub fn from_env(direction: Direction) -> Result<DeviceSelector, anyhow::Error> {
+        let prefix = direction.env_prefix();
+        let mut selector = DeviceSelector::new();
+        let index_var = format!("{}DEVICE_INDEX", prefix);
+        if let Ok(index) = env::var(&index_var) {
+            let index = index
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| anyhow!("Invalid {} environment variable", index_var))?;
+            selector = selector.prefer(DeviceMatch::Index(index));
+        }
+        if let Ok(pattern) = env::var(format!("{}DEVICE", prefix)) {
+            if !pattern.trim().is_empty() {
+                selector = selector.prefer(DeviceMatch::Pattern(pattern.trim().to_string()));
+            }
+        }
+        // With no variable set there are no preferences, and the default is used.
+        Ok(selector.with_fallback(false))
+    }
+
+    /// Adds a preference, tried after those added before it.
+    pub fn prefer(mut self, preference: DeviceMatch) -> DeviceSelector {
+        self.preferences.push(preference);
+        self
+    }
+
+    /// Prefers the device with exactly this name.
+    pub fn with_name(self, name: impl Into<String>) -> DeviceSelector {
+        self.prefer(DeviceMatch::Name(name.into()))
+    }
+
+    /// Prefers the first device whose name contains `pattern`, ignoring case.
+    pub fn with_pattern(self, pattern: impl Into<String>) -> DeviceSelector {
+        self.prefer(DeviceMatch::Pattern(pattern.into()))
+    }
+
+    /// Prefers the device at `index` in the list sorted by name.
+    pub fn with_index(self, index: usize) -> DeviceSelector {
+        self.prefer(DeviceMatch::Index(index))
+    }
+
+    /// Sets whether to fall back to the default device, then the first one,
+    /// when no preference matches. On by default.
+    pub fn with_fallback(mut self, fallback: bool) -> DeviceSelector {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Returns the preferences in the order they are tried.
+    pub fn preferences(&self) -> &[DeviceMatch] {
+        &self.preferences
+    }
+
+    /// Picks a capture device of the default host.
+    pub fn select_input(&self) -> Result<Device, anyhow::Error> {
+        self.select_on(&cpal::default_host(), Direction::Input)
+    }
+
+    /// Picks a playback device of the default host.
+    pub fn select_output(&self) -> Result<Device, anyhow::Error> {
+        self.select_on(&cpal::default_host(), Direction::Output)
+    }
+
+    /// Picks a device of `host` for the given direction.
+    pub fn select_on(&self, host: &Host, direction: Direction) -> Result<Device, anyhow::Error> {
+        let default = || match direction {
+            Direction::Input => host.default_input_device(),
+            Direction::Output => host.default_output_device(),
+        };
+        for preference in &self.preferences {
+            if let Some(dev) = preference.find(list_devs(host, direction), default()) {
+                return Ok(dev);
+            }
+        }
+        if self.fallback || self.preferences.is_empty() {
+            if let Some(dev) = default() {
+                return Ok(dev);
+            }
+            if let Some((_, dev)) = list_devs(host, direction).into_iter().next() {
+                return Ok(dev);
+            }
+            return Err(anyhow!("no {} devices found", direction));
+        }
+        let tried: Vec<String> = self.preferences.iter().map(|p| p.to_string()).collect();
+        Err(anyhow!("no {} device {}", direction, tried.join(" or ")))
+    }
+}
+
+/// Lists the devices of `host` for the given direction, sorted by name.
+pub fn list_devs(host: &Host, direction: Direction) -> Vec<(String, Device)> {
+    let devices = match direction {
+        Direction::Input => host.input_devices().map(|d| d.collect::<Vec<_>>()),
+        Direction::Output => host.output_devices().map(|d| d.collect::<Vec<_>>()),
+    };
+    let mut devs: Vec<(String, Device)> = devices
+        .unwrap_or_default()
+        .into_iter()
+        .map(|dev| {
+            (
+                dev.name().unwrap_or_else(|_| String::from("<unknown>")),
+                dev,
+            )
+        })
+        .collect();
+    devs.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
+    devs
+}
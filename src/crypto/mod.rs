@@ -0,0 +1,212 @@
+mod stream;
+
+pub use stream::{DecryptingReader, EncryptingWriter};
+
+use anyhow::anyhow;
+use argon2::Argon2;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Marks the start of every encrypted file.
+const MAGIC: &[u8; 8] = b"PKPKENC1";
+
+/// Where the secret an encryption key is derived from comes from.
+#[derive(Clone)]
+pub enum KeySource {
+    Passphrase(String),
+    /// A file whose whole contents are the secret.
+    KeyFile(PathBuf),
+}
+
+/// A symmetric key for encrypting files with ChaCha20-Poly1305.
+#[derive(Clone)]
+pub struct Key {
+    cipher: ChaCha20Poly1305,
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+impl Key {
+    /// Derives a key from `source` with Argon2id.
+    ///
+    /// # Arguments
+    /// * `source` - The passphrase or key file.
+    /// * `salt` - A random salt of at least 8 bytes, stored alongside the encrypted data.
+    pub fn derive(source: &KeySource, salt: &[u8]) -> Result<Key, anyhow::Error> {
+        let secret = match source {
+            KeySource::Passphrase(passphrase) => passphrase.as_bytes().to_vec(),
+            KeySource::KeyFile(path) => fs::read(path)?,
+        };
+        if secret.is_empty() {
+            return Err(anyhow!("the passphrase or key file is empty"));
+        }
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(&secret, salt, &mut key)
+            .map_err(|e| anyhow!("key derivation failed: {}", e))?;
+        Ok(Key {
+            cipher: ChaCha20Poly1305::new(&key.into()),
+        })
+    }
+
+    /// Encrypts `data` into a self-contained encrypted blob.
+    pub fn seal(&self, data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = EncryptingWriter::new(&mut out, self)?;
+        writer.write_all(data)?;
+        writer.finish()?;
+        drop(writer);
+        Ok(out.into_inner())
+    }
+
+    /// Decrypts a blob made by [`Key::seal`] or an [`EncryptingWriter`].
+    pub fn open(&self, data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut reader = DecryptingReader::new(Cursor::new(data), self)?;
+        let mut out = Vec::with_capacity(reader.len() as usize);
+        reader.read_to_end(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// Returns `true` if the file at `path` starts with the encrypted file marker.
+pub fn is_encrypted(path: impl AsRef<Path>) -> Result<bool, anyhow::Error> {
+    let mut magic = [0u8; MAGIC.len()];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A file read as plaintext, whether or not it is encrypted on disk.
+pub enum FileReader {
+    Plain(File),
+    Encrypted(DecryptingReader<File>),
+}
+
+impl FileReader {
+    /// Opens the file at `path`, decrypting it with `key` if it is encrypted.
+    ///
+    /// Fails if the file is encrypted and no key is given.
+    pub fn open(path: impl AsRef<Path>, key: Option<&Key>) -> Result<FileReader, anyhow::Error> {
+        let path = path.as_ref();
+        if !is_encrypted(path)? {
+            return Ok(FileReader::Plain(File::open(path)?));
+        }
+        let key = key.ok_or_else(|| {
+            anyhow!(
+                "{} is encrypted; a key is needed to read it",
+                path.display()
+            )
+        })?;
+        Ok(FileReader::Encrypted(DecryptingReader::new(
+            File::open(path)?,
+            key,
+        )?))
+    }
+
+    /// Returns the length of the plaintext.
+    pub fn plaintext_len(&self) -> Result<u64, anyhow::Error> {
+        Ok(match self {
+            FileReader::Plain(file) => file.metadata()?.len(),
+            FileReader::Encrypted(reader) => reader.len(),
+        })
+    }
+}
+
+impl Read for FileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            FileReader::Plain(file) => file.read(buf),
+            FileReader::Encrypted(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for FileReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            FileReader::Plain(file) => file.seek(pos),
+            FileReader::Encrypted(reader) => reader.seek(pos),
+        }
+    }
+}
+
+/// A new file written as plaintext, or encrypted as it is written.
+///
+/// Encrypted files follow the seeking rules of [`EncryptingWriter`], and are
+/// completed by [`FileWriter::finish`] or flushing.
+pub enum FileWriter {
+    Plain(File),
+    Encrypted(EncryptingWriter<File>),
+}
+
+impl FileWriter {
+    /// Creates the file at `path`, encrypting it with `key` if one is given.
+    pub fn create(path: impl AsRef<Path>, key: Option<&Key>) -> Result<FileWriter, anyhow::Error> {
+        let file = File::create(path)?;
+        Ok(match key {
+            Some(key) => FileWriter::Encrypted(EncryptingWriter::new(file, key)?),
+            None => FileWriter::Plain(file),
+        })
+    }
+
+    /// Writes out everything written so far, completing an encrypted file.
+    pub fn finish(&mut self) -> Result<(), anyhow::Error> {
+        match self {
+            FileWriter::Plain(file) => file.flush()?,
+            FileWriter::Encrypted(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FileWriter::Plain(file) => file.write(buf),
+            FileWriter::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FileWriter::Plain(file) => file.flush(),
+            FileWriter::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+impl Seek for FileWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            FileWriter::Plain(file) => file.seek(pos),
+            FileWriter::Encrypted(writer) => writer.seek(pos),
+        }
+    }
+}
+
+/// Reads a whole file, decrypting it with `key` if it is encrypted.
+pub fn read(path: impl AsRef<Path>, key: Option<&Key>) -> Result<Vec<u8>, anyhow::Error> {
+    let mut reader = FileReader::open(path, key)?;
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Writes a whole file, encrypting it with `key` if one is given.
+pub fn write(path: impl AsRef<Path>, data: &[u8], key: Option<&Key>) -> Result<(), anyhow::Error> {
+    match key {
+        Some(key) => fs::write(path, key.seal(data)?)?,
+        None => fs::write(path, data)?,
+    }
+    Ok(())
+}
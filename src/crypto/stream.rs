@@ -0,0 +1,294 @@
+use super::{Key, MAGIC};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{AeadInPlace, OsRng};
+use chacha20poly1305::Nonce;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Plaintext bytes sealed together; every chunk but the last holds exactly this many.
+pub(super) const CHUNK_BYTES: usize = 64 * 1024;
+const TAG_BYTES: usize = 16;
+const PREFIX_BYTES: usize = 7;
+/// Magic followed by the random nonce prefix.
+const HEADER_BYTES: u64 = (MAGIC.len() + PREFIX_BYTES) as u64;
+const SEALED_CHUNK_BYTES: u64 = (CHUNK_BYTES + TAG_BYTES) as u64;
+
+/// Builds the nonce of a chunk: the file's prefix, the chunk index and a flag
+/// marking the last chunk, so chunks cannot be reordered, dropped or appended.
+fn nonce(prefix: &[u8; PREFIX_BYTES], index: u64, last: bool) -> io::Result<Nonce> {
+    let index = u32::try_from(index)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "encrypted file too large"))?;
+    let mut nonce = Nonce::default();
+    nonce[..PREFIX_BYTES].copy_from_slice(prefix);
+    nonce[PREFIX_BYTES..PREFIX_BYTES + 4].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    Ok(nonce)
+}
+
+fn unsupported(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message.to_string())
+}
+
+/// Encrypts a stream in sealed chunks as it is written.
+///
+/// Sealed chunks cannot be changed, so the writer only seeks within the chunk
+/// being filled and within the first chunk, whose plaintext is kept in memory
+/// until the stream is complete. That is enough for WAV writers, which go back
+/// to the header to fill in the data length when they finish. The first chunk
+/// is also written out as soon as the stream moves past it, so an interrupted
+/// stream keeps everything up to the chunk being filled, and is sealed again
+/// with any changes when the stream completes.
+///
+/// [`EncryptingWriter::finish`], or flushing, completes the stream: the
+/// remaining chunks are sealed and written, and any later write fails.
+/// Dropping an unfinished writer completes it too, but errors are lost.
+pub struct EncryptingWriter<W: Write + Seek> {
+    inner: W,
+    key: Key,
+    prefix: [u8; PREFIX_BYTES],
+    /// Plaintext of chunk 0.
+    first: Vec<u8>,
+    /// Whether chunk 0 has been written out before the stream completed.
+    first_written: bool,
+    /// Plaintext of the chunk being filled, when past chunk 0.
+    tail: Vec<u8>,
+    tail_index: u64,
+    pos: u64,
+    len: u64,
+    finished: bool,
+}
+
+impl<W: Write + Seek> EncryptingWriter<W> {
+    /// Starts an encrypted stream at the current position of `inner`.
+    pub fn new(mut inner: W, key: &Key) -> io::Result<EncryptingWriter<W>> {
+        let mut prefix = [0u8; PREFIX_BYTES];
+        OsRng.fill_bytes(&mut prefix);
+        inner.write_all(MAGIC)?;
+        inner.write_all(&prefix)?;
+        Ok(EncryptingWriter {
+            inner,
+            key: key.clone(),
+            prefix,
+            first: Vec::with_capacity(CHUNK_BYTES),
+            first_written: false,
+            tail: Vec::new(),
+            tail_index: 1,
+            pos: 0,
+            len: 0,
+            finished: false,
+        })
+    }
+
+    fn seal(&mut self, index: u64, last: bool) -> io::Result<()> {
+        let mut data = if index == 0 {
+            self.first.clone()
+        } else {
+            std::mem::take(&mut self.tail)
+        };
+        self.key
+            .cipher
+            .encrypt_in_place(&nonce(&self.prefix, index, last)?, b"", &mut data)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        self.inner
+            .seek(SeekFrom::Start(HEADER_BYTES + index * SEALED_CHUNK_BYTES))?;
+        self.inner.write_all(&data)
+    }
+
+    /// Seals and writes the remaining chunks, completing the stream.
+    ///
+    /// Any later write fails; finishing again does nothing.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let last = self.len.saturating_sub(1) / CHUNK_BYTES as u64;
+        if last > 0 {
+            self.seal(last, true)?;
+        }
+        self.seal(0, last == 0)?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write + Seek> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(unsupported("encrypted stream is already complete"));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk = (self.pos / CHUNK_BYTES as u64) as usize;
+        let offset = (self.pos % CHUNK_BYTES as u64) as usize;
+        let n = buf.len().min(CHUNK_BYTES - offset);
+        let target = if chunk == 0 {
+            &mut self.first
+        } else {
+            if !self.first_written {
+                // Until it is sealed again as the stream completes, chunk 0 is
+                // stored as one that more chunks follow.
+                self.seal(0, false)?;
+                self.first_written = true;
+            }
+            if chunk as u64 != self.tail_index {
+                // Moving past a full chunk seals it; it can no longer be the last one.
+                if chunk as u64 == self.tail_index + 1 && self.tail.len() == CHUNK_BYTES {
+                    self.seal(self.tail_index, false)?;
+                    self.tail_index += 1;
+                } else {
+                    return Err(unsupported("cannot write into sealed encrypted data"));
+                }
+            }
+            &mut self.tail
+        };
+        let end = offset + n;
+        if target.len() < end {
+            target.resize(end, 0);
+        }
+        target[offset..end].copy_from_slice(&buf[..n]);
+        self.pos += n as u64;
+        self.len = self.len.max(self.pos);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+impl<W: Write + Seek> Seek for EncryptingWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek"))?;
+        let in_first = target <= (self.first.len() as u64).min(CHUNK_BYTES as u64);
+        let in_tail = target >= self.tail_index * CHUNK_BYTES as u64 && target <= self.len;
+        if !in_first && !in_tail {
+            return Err(unsupported("cannot seek into sealed encrypted data"));
+        }
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+impl<W: Write + Seek> Drop for EncryptingWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Decrypts a stream written by [`EncryptingWriter`], with random access.
+///
+/// Chunks are authenticated as they are read, so tampering, truncation and bit
+/// rot all surface as read errors.
+pub struct DecryptingReader<R: Read + Seek> {
+    inner: R,
+    key: Key,
+    prefix: [u8; PREFIX_BYTES],
+    chunks: u64,
+    len: u64,
+    /// Index and plaintext of the most recently decrypted chunk.
+    current: Option<(u64, Vec<u8>)>,
+    pos: u64,
+}
+
+impl<R: Read + Seek> DecryptingReader<R> {
+    /// Opens an encrypted stream that starts at the beginning of `inner`.
+    pub fn new(mut inner: R, key: &Key) -> io::Result<DecryptingReader<R>> {
+        let size = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        let mut header = [0u8; HEADER_BYTES as usize];
+        inner.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an encrypted file",
+            ));
+        }
+        let mut prefix = [0u8; PREFIX_BYTES];
+        prefix.copy_from_slice(&header[MAGIC.len()..]);
+
+        let body = size - HEADER_BYTES;
+        let chunks = body.div_ceil(SEALED_CHUNK_BYTES).max(1);
+        let last = body - (chunks - 1) * SEALED_CHUNK_BYTES;
+        if last < TAG_BYTES as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "encrypted file is truncated",
+            ));
+        }
+        Ok(DecryptingReader {
+            inner,
+            key: key.clone(),
+            prefix,
+            chunks,
+            len: (chunks - 1) * CHUNK_BYTES as u64 + last - TAG_BYTES as u64,
+            current: None,
+            pos: 0,
+        })
+    }
+
+    /// Returns the length of the plaintext.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the plaintext is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn load(&mut self, index: u64) -> io::Result<()> {
+        if self.current.as_ref().is_some_and(|(i, _)| *i == index) {
+            return Ok(());
+        }
+        let start = index * SEALED_CHUNK_BYTES;
+        let end = (start + SEALED_CHUNK_BYTES).min(self.len + self.chunks * TAG_BYTES as u64);
+        let mut data = vec![0u8; (end - start) as usize];
+        self.inner.seek(SeekFrom::Start(HEADER_BYTES + start))?;
+        self.inner.read_exact(&mut data)?;
+        let last = index + 1 == self.chunks;
+        self.key
+            .cipher
+            .decrypt_in_place(&nonce(&self.prefix, index, last)?, b"", &mut data)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "encrypted data is damaged or the key is wrong",
+                )
+            })?;
+        self.current = Some((index, data));
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.pos / CHUNK_BYTES as u64;
+        self.load(index)?;
+        let chunk = &self.current.as_ref().expect("chunk is loaded").1;
+        let offset = (self.pos % CHUNK_BYTES as u64) as usize;
+        let n = buf.len().min(chunk.len() - offset);
+        buf[..n].copy_from_slice(&chunk[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for DecryptingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek"))?;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,356 @@
+use anyhow::anyhow;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{
+    Decoder as CodecDecoder, DecoderOptions, CODEC_TYPE_FLAC, CODEC_TYPE_MP3, CODEC_TYPE_NULL,
+    CODEC_TYPE_PCM_ALAW, CODEC_TYPE_PCM_F32LE, CODEC_TYPE_PCM_F64LE, CODEC_TYPE_PCM_MULAW,
+    CODEC_TYPE_PCM_S16LE, CODEC_TYPE_PCM_S24LE, CODEC_TYPE_PCM_S32LE, CODEC_TYPE_PCM_U8,
+    CODEC_TYPE_VORBIS,
+};
+use symphonia::core::errors::{Error as SymphoniaError, SeekErrorKind};
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+/// The container/codec a decoded file was stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Flac,
+    Mp3,
+    Vorbis,
+    /// Any other codec the decoder supports.
+    Other,
+}
+
+/// Returns `true` if `error` came from the file being unreadable, such as a
+/// failing disk or missing permissions, rather than from what it contains.
+///
+/// A file that ends early counts as its contents being broken.
+pub(crate) fn is_io_error(error: &anyhow::Error) -> bool {
+    let io = error.chain().find_map(|cause| {
+        cause.downcast_ref::<std::io::Error>().or(match cause.downcast_ref() {
+            Some(SymphoniaError::IoError(e)) => Some(e),
+            _ => None,
+        })
+    });
+    io.is_some_and(|e| !matches!(e.kind(), ErrorKind::UnexpectedEof | ErrorKind::InvalidData))
+}
+
+/// Stream properties and tags of a decoded source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioInfo {
+    pub format: AudioFormat,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Total length in frames, if the container reports it.
+    pub total_frames: Option<u64>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+/// A streaming source of interleaved `f32` audio frames.
+///
+/// Implemented by [`Decoder`] for files on disk and by the clip library's reader,
+/// so playback and analysis code can consume either without caring where the
+/// audio comes from.
+pub trait FrameSource {
+    /// Returns the stream properties of the source.
+    fn info(&self) -> &AudioInfo;
+
+    /// Fills `buf` with interleaved frames.
+    ///
+    /// `buf` should hold a whole number of frames; only complete frames are written.
+    ///
+    /// # Returns
+    /// * `usize` - The number of frames written, `0` once the source is exhausted.
+    fn read_frames(&mut self, buf: &mut [f32]) -> Result<usize, anyhow::Error>;
+
+    /// Moves the read position to `frame`, clamped to the end of the source.
+    ///
+    /// # Returns
+    /// * `u64` - The frame the next read will start at.
+    fn seek(&mut self, frame: u64) -> Result<u64, anyhow::Error>;
+
+    /// Returns the frame the next read will start at.
+    fn position(&self) -> u64;
+
+    /// Turns the source into an iterator yielding one frame at a time.
+    fn frames(self) -> Frames<Self>
+    where
+        Self: Sized,
+    {
+        Frames::new(self)
+    }
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for Box<S> {
+    fn info(&self) -> &AudioInfo {
+        (**self).info()
+    }
+
+    fn read_frames(&mut self, buf: &mut [f32]) -> Result<usize, anyhow::Error> {
+        (**self).read_frames(buf)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<u64, anyhow::Error> {
+        (**self).seek(frame)
+    }
+
+    fn position(&self) -> u64 {
+        (**self).position()
+    }
+}
+
+/// Number of frames [`Frames`] reads from its source at a time.
+const FRAMES_CHUNK: usize = 1024;
+
+/// Iterator over the frames of a [`FrameSource`], created by [`FrameSource::frames`].
+pub struct Frames<S> {
+    source: S,
+    buf: Vec<f32>,
+    pos: usize,
+    len: usize,
+}
+
+impl<S: FrameSource> Frames<S> {
+    fn new(source: S) -> Frames<S> {
+        let channels = source.info().channels as usize;
+        Frames {
+            source,
+            buf: vec![0.0; FRAMES_CHUNK * channels],
+            pos: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<S: FrameSource> Iterator for Frames<S> {
+    type Item = Result<Vec<f32>, anyhow::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let channels = self.source.info().channels as usize;
+        if self.pos == self.len {
+            match self.source.read_frames(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(frames) => {
+                    self.pos = 0;
+                    self.len = frames * channels;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let frame = self.buf[self.pos..self.pos + channels].to_vec();
+        self.pos += channels;
+        Some(Ok(frame))
+    }
+}
+
+/// Decodes WAV, FLAC, MP3 and Ogg Vorbis files into interleaved `f32` frames.
+///
+/// The file is decoded one packet at a time, so memory use is independent of its
+/// length. Corrupt packets are reported as errors rather than skipped.
+pub struct Decoder {
+    format: Box<dyn FormatReader>,
+    codec: Box<dyn CodecDecoder>,
+    track_id: u32,
+    info: AudioInfo,
+    decoded: Option<SampleBuffer<f32>>,
+    pos: usize,
+    len: usize,
+    position: u64,
+}
+
+impl Decoder {
+    /// Opens and probes the audio file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Decoder, anyhow::Error> {
+        let path = path.as_ref();
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        let mss = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+        let mut probed = symphonia::default::get_probe()
+            .format(
+                &hint,
+                mss,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(|e| anyhow!("unsupported audio file {}: {}", path.display(), e))?;
+
+        let mut format = probed.format;
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow!("no audio track in {}", path.display()))?;
+        let params = &track.codec_params;
+        let codec =
+            symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+
+        let audio_format = match params.codec {
+            CODEC_TYPE_FLAC => AudioFormat::Flac,
+            CODEC_TYPE_MP3 => AudioFormat::Mp3,
+            CODEC_TYPE_VORBIS => AudioFormat::Vorbis,
+            // The sample formats a WAV file can hold.
+            CODEC_TYPE_PCM_U8 | CODEC_TYPE_PCM_S16LE | CODEC_TYPE_PCM_S24LE
+            | CODEC_TYPE_PCM_S32LE | CODEC_TYPE_PCM_F32LE | CODEC_TYPE_PCM_F64LE
+            | CODEC_TYPE_PCM_ALAW | CODEC_TYPE_PCM_MULAW => AudioFormat::Wav,
+            _ => AudioFormat::Other,
+        };
+        let mut info = AudioInfo {
+            format: audio_format,
+            sample_rate: params
+                .sample_rate
+                .ok_or_else(|| anyhow!("unknown sample rate in {}", path.display()))?,
+            channels: params
+                .channels
+                .map(|c| c.count() as u16)
+                .ok_or_else(|| anyhow!("unknown channel layout in {}", path.display()))?,
+            total_frames: params.n_frames,
+            title: None,
+            artist: None,
+        };
+        let track_id = track.id;
+
+        // Tags may live in the container or ahead of it (e.g. ID3 on MP3).
+        if let Some(rev) = format.metadata().current() {
+            Decoder::apply_tags(&mut info, rev);
+        }
+        if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+            Decoder::apply_tags(&mut info, rev);
+        }
+
+        Ok(Decoder {
+            format,
+            codec,
+            track_id,
+            info,
+            decoded: None,
+            pos: 0,
+            len: 0,
+            position: 0,
+        })
+    }
+
+    fn apply_tags(info: &mut AudioInfo, rev: &MetadataRevision) {
+        for tag in rev.tags() {
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) if info.title.is_none() => {
+                    info.title = Some(tag.value.to_string())
+                }
+                Some(StandardTagKey::Artist) if info.artist.is_none() => {
+                    info.artist = Some(tag.value.to_string())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Decodes the next packet of the track into the internal buffer.
+    ///
+    /// # Returns
+    /// * `bool` - `false` once the end of the stream is reached.
+    fn decode_next(&mut self) -> Result<bool, anyhow::Error> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                    return Ok(false)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = self.codec.decode(&packet)?;
+            if decoded.frames() == 0 {
+                continue;
+            }
+            let buf = match self.decoded.as_mut() {
+                Some(buf) if buf.capacity() >= decoded.capacity() * decoded.spec().channels.count() => buf,
+                _ => self.decoded.insert(SampleBuffer::new(
+                    decoded.capacity() as u64,
+                    *decoded.spec(),
+                )),
+            };
+            buf.copy_interleaved_ref(decoded);
+            self.pos = 0;
+            self.len = buf.len();
+            return Ok(true);
+        }
+    }
+}
+
+impl FrameSource for Decoder {
+    fn info(&self) -> &AudioInfo {
+        &self.info
+    }
+
+    fn read_frames(&mut self, buf: &mut [f32]) -> Result<usize, anyhow::Error> {
+        let channels = self.info.channels as usize;
+        let wanted = buf.len() / channels * channels;
+        let mut written = 0;
+        while written < wanted {
+            if self.pos == self.len && !self.decode_next()? {
+                break;
+            }
+            let decoded = self.decoded.as_ref().expect("decoded buffer").samples();
+            let n = (self.len - self.pos).min(wanted - written);
+            buf[written..written + n].copy_from_slice(&decoded[self.pos..self.pos + n]);
+            self.pos += n;
+            written += n;
+        }
+        self.position += (written / channels) as u64;
+        Ok(written / channels)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<u64, anyhow::Error> {
+        let frame = match self.info.total_frames {
+            Some(total) => frame.min(total),
+            None => frame,
+        };
+        let seeked = self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::TimeStamp {
+                ts: frame,
+                track_id: self.track_id,
+            },
+        );
+        match seeked {
+            Ok(seeked) => {
+                self.codec.reset();
+                self.pos = 0;
+                self.len = 0;
+                self.position = seeked.actual_ts;
+            }
+            // Without a known length a target past the end is only found out
+            // here; read on from the current position until the source ends.
+            Err(SymphoniaError::SeekError(SeekErrorKind::OutOfRange))
+                if self.info.total_frames.is_none() && frame > self.position => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        // Seeking lands on a packet boundary at or before the target; decode and
+        // discard up to the exact frame.
+        let channels = self.info.channels as usize;
+        let mut skip = vec![0.0; 1024 * channels];
+        while self.position < frame {
+            let n = ((frame - self.position) as usize).min(1024);
+            if self.read_frames(&mut skip[..n * channels])? == 0 {
+                // The source ended first, so this is where it stops.
+                self.info.total_frames.get_or_insert(self.position);
+                break;
+            }
+        }
+        Ok(self.position)
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::anyhow;
+
+/// How the channels of a source are mixed into the channels of an output.
+///
+/// Each output channel is a weighted sum of the input channels, so the map
+/// covers upmixing, downmixing and arbitrary routing alike.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelMap {
+    inputs: usize,
+    outputs: usize,
+    /// Gains indexed by `output * inputs + input`.
+    gains: Vec<f32>,
+}
+
+impl ChannelMap {
+    /// Returns the conventional map between two channel counts.
+    ///
+    /// Equal counts pass straight through. Mono is copied to the first two
+    /// outputs (front left and right), and anything is mixed down to mono by
+    /// averaging. Otherwise channels are matched by index, dropping inputs
+    /// without a matching output and leaving extra outputs silent.
+    pub fn auto(inputs: u16, outputs: u16) -> ChannelMap {
+        let (inputs, outputs) = (inputs.max(1) as usize, outputs.max(1) as usize);
+        let mut map = ChannelMap::silent(inputs, outputs);
+        if inputs == 1 {
+            for output in 0..outputs.min(2) {
+                map.gains[output] = 1.0;
+            }
+        } else if outputs == 1 {
+            map.gains.fill(1.0 / inputs as f32);
+        } else {
+            for channel in 0..inputs.min(outputs) {
+                map.gains[channel * inputs + channel] = 1.0;
+            }
+        }
+        map
+    }
+
+    /// Builds a map from explicit routes.
+    ///
+    /// # Arguments
+    /// * `inputs` - Number of source channels.
+    /// * `outputs` - Number of output channels.
+    /// * `routes` - `(input, output, gain)` triples; outputs without a route are silent.
+    pub fn routing(
+        inputs: u16,
+        outputs: u16,
+        routes: &[(u16, u16, f32)],
+    ) -> Result<ChannelMap, anyhow::Error> {
+        let mut map = ChannelMap::silent(inputs.max(1) as usize, outputs.max(1) as usize);
+        for &(input, output, gain) in routes {
+            if input >= inputs || output >= outputs {
+                return Err(anyhow!(
+                    "route {} -> {} is outside {} inputs and {} outputs",
+                    input,
+                    output,
+                    inputs,
+                    outputs
+                ));
+            }
+            map.gains[output as usize * map.inputs + input as usize] += gain;
+        }
+        Ok(map)
+    }
+
+    /// Returns the number of input channels.
+    pub fn inputs(&self) -> u16 {
+        self.inputs as u16
+    }
+
+    /// Returns the number of output channels.
+    pub fn outputs(&self) -> u16 {
+        self.outputs as u16
+    }
+
+    /// Mixes interleaved input frames into interleaved output frames.
+    ///
+    /// Processes as many whole frames as both buffers hold.
+    ///
+    /// # Returns
+    /// * `usize` - The number of frames written.
+    pub fn apply(&self, input: &[f32], output: &mut [f32]) -> usize {
+        let mut frames = 0;
+        for (out, frame) in output
+            .chunks_exact_mut(self.outputs)
+            .zip(input.chunks_exact(self.inputs))
+        {
+            for (sample, gains) in out.iter_mut().zip(self.gains.chunks_exact(self.inputs)) {
+                *sample = gains.iter().zip(frame).map(|(g, s)| g * s).sum();
+            }
+            frames += 1;
+        }
+        frames
+    }
+
+    fn silent(inputs: usize, outputs: usize) -> ChannelMap {
+        ChannelMap {
+            inputs,
+            outputs,
+            gains: vec![0.0; inputs * outputs],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_upmixes_mono_to_front_pair() {
+        let map = ChannelMap::auto(1, 4);
+        let mut output = [9.0; 8];
+        assert_eq!(map.apply(&[0.5, -0.25], &mut output), 2);
+        assert_eq!(output, [0.5, 0.5, 0.0, 0.0, -0.25, -0.25, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn auto_downmixes_by_averaging() {
+        let map = ChannelMap::auto(2, 1);
+        let mut output = [0.0; 2];
+        assert_eq!(map.apply(&[1.0, 0.0, 0.5, -0.5], &mut output), 2);
+        assert_eq!(output, [0.5, 0.0]);
+    }
+
+    #[test]
+    fn routing_swaps_and_scales_channels() {
+        let map = ChannelMap::routing(2, 2, &[(0, 1, 1.0), (1, 0, 0.5)]).unwrap();
+        let mut output = [0.0; 2];
+        map.apply(&[0.8, 0.4], &mut output);
+        assert_eq!(output, [0.2, 0.8]);
+        assert!(ChannelMap::routing(2, 2, &[(2, 0, 1.0)]).is_err());
+    }
+}
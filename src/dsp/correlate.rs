@@ -0,0 +1,60 @@
+use super::fft::{fft, ifft};
+
+/// Cross-correlates `input` with `reference` at lags from 0 to `max_lag`.
+///
+/// Computed through the FFT, so long signals are cheap. Samples past the end of
+/// `input` count as silence.
+///
+/// # Returns
+/// * `Vec<f32>` - For each lag `k`, the sum of `input[n + k] * reference[n]`.
+pub fn cross_correlate(input: &[f32], reference: &[f32], max_lag: usize) -> Vec<f32> {
+    if input.is_empty() || reference.is_empty() {
+        return vec![0.0; max_lag + 1];
+    }
+    let size = (input.len().max(max_lag + 1) + reference.len()).next_power_of_two();
+    let mut input_re = vec![0.0; size];
+    let mut input_im = vec![0.0; size];
+    let mut ref_re = vec![0.0; size];
+    let mut ref_im = vec![0.0; size];
+    input_re[..input.len()].copy_from_slice(input);
+    ref_re[..reference.len()].copy_from_slice(reference);
+    fft(&mut input_re, &mut input_im);
+    fft(&mut ref_re, &mut ref_im);
+
+    // Multiply by the conjugate of the reference to correlate rather than convolve.
+    for k in 0..size {
+        let (a, b) = (input_re[k], input_im[k]);
+        let (c, d) = (ref_re[k], -ref_im[k]);
+        input_re[k] = a * c - b * d;
+        input_im[k] = a * d + b * c;
+    }
+    ifft(&mut input_re, &mut input_im);
+    input_re.truncate(max_lag + 1);
+    input_re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_shifted_copy() {
+        let reference = [1.0, -2.0, 3.0];
+        let mut input = vec![0.0; 10];
+        input[4..7].copy_from_slice(&reference);
+        let correlation = cross_correlate(&input, &reference, 8);
+        assert_eq!(correlation.len(), 9);
+        for (lag, value) in correlation.iter().enumerate() {
+            let expected = (0..3)
+                .map(|n| input.get(n + lag).copied().unwrap_or(0.0) * reference[n])
+                .sum::<f32>();
+            assert!((value - expected).abs() < 1e-4, "lag {}", lag);
+        }
+        assert!((correlation[4] - 14.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn empty_input_correlates_to_zero() {
+        assert_eq!(cross_correlate(&[], &[1.0], 3), vec![0.0; 4]);
+    }
+}
@@ -0,0 +1,161 @@
+use super::db_to_gain;
+use super::fft::fft;
+use std::f32::consts::PI;
+use std::time::Duration;
+
+/// Thresholds used to decide when a signal is howling from acoustic feedback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeedbackConfig {
+    /// Windows whose RMS level is below this never count as howling, in dBFS.
+    pub threshold_db: f32,
+    /// Share of a window's energy that must sit in its strongest peak, from 0
+    /// to 1. Feedback is close to a pure tone; speech spreads over many
+    /// harmonics.
+    pub peak_ratio: f32,
+    /// How long the same peak must last before it counts as howling.
+    pub hold: Duration,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        FeedbackConfig {
+            threshold_db: -30.0,
+            peak_ratio: 0.8,
+            hold: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Streaming detector for the sustained tone of acoustic feedback.
+///
+/// Frames are analyzed in windows of about 20 ms. A window is tonal when it is
+/// loud enough and most of its energy lies within a couple of bins of one
+/// spectral peak; once tonal windows at the same frequency have lasted
+/// [`FeedbackConfig::hold`], the signal is howling until [`FeedbackDetector::reset`].
+/// A long, steady whistle looks the same and is reported too.
+pub struct FeedbackDetector {
+    threshold: f32,
+    peak_ratio: f32,
+    hold_windows: usize,
+    sample_rate: u32,
+    channels: usize,
+    /// Hann window, one weight per frame of the analysis window.
+    window: Vec<f32>,
+    /// Mono frames of the window being filled.
+    frames: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    /// Bin of the peak in the previous window, if it was tonal.
+    peak_bin: Option<usize>,
+    /// Consecutive tonal windows with the same peak.
+    run: usize,
+    howling: Option<f32>,
+}
+
+impl FeedbackDetector {
+    /// Creates a detector for a stream with the given sample rate and channel count.
+    pub fn new(config: &FeedbackConfig, sample_rate: u32, channels: u16) -> FeedbackDetector {
+        let size = ((sample_rate as f32 * 0.02) as usize)
+            .next_power_of_two()
+            .max(64);
+        let window_secs = size as f32 / sample_rate.max(1) as f32;
+        let window = (0..size)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / size as f32).cos())
+            .collect();
+        FeedbackDetector {
+            threshold: db_to_gain(config.threshold_db),
+            peak_ratio: config.peak_ratio.clamp(0.0, 1.0),
+            hold_windows: ((config.hold.as_secs_f32() / window_secs).ceil() as usize).max(1),
+            sample_rate,
+            channels: channels.max(1) as usize,
+            window,
+            frames: Vec::with_capacity(size),
+            re: vec![0.0; size],
+            im: vec![0.0; size],
+            peak_bin: None,
+            run: 0,
+            howling: None,
+        }
+    }
+
+    /// Feeds interleaved frames to the detector.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the signal is howling.
+    pub fn push(&mut self, samples: &[f32]) -> bool {
+        for frame in samples.chunks_exact(self.channels) {
+            self.frames
+                .push(frame.iter().sum::<f32>() / self.channels as f32);
+            if self.frames.len() == self.window.len() {
+                self.analyze();
+                self.frames.clear();
+            }
+        }
+        self.is_howling()
+    }
+
+    /// Returns `true` once howling has been detected.
+    pub fn is_howling(&self) -> bool {
+        self.howling.is_some()
+    }
+
+    /// Returns the frequency of the howl in Hz, if one has been detected.
+    pub fn frequency(&self) -> Option<f32> {
+        self.howling
+    }
+
+    /// Forgets everything seen so far, including a detected howl.
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.peak_bin = None;
+        self.run = 0;
+        self.howling = None;
+    }
+
+    /// Classifies the full window in `frames` and updates the run of tonal windows.
+    fn analyze(&mut self) {
+        let size = self.window.len();
+        let rms = (self.frames.iter().map(|s| s * s).sum::<f32>() / size as f32).sqrt();
+        let peak = if rms >= self.threshold {
+            self.tonal_peak()
+        } else {
+            None
+        };
+
+        match (peak, self.peak_bin) {
+            (Some(bin), Some(previous)) if bin.abs_diff(previous) <= 1 => self.run += 1,
+            (Some(_), _) => self.run = 1,
+            (None, _) => self.run = 0,
+        }
+        self.peak_bin = peak;
+        if self.run >= self.hold_windows && self.howling.is_none() {
+            self.howling = peak.map(|bin| bin as f32 * self.sample_rate as f32 / size as f32);
+        }
+    }
+
+    /// Returns the bin of the spectral peak if it holds enough of the energy.
+    fn tonal_peak(&mut self) -> Option<usize> {
+        for (i, (s, w)) in self.frames.iter().zip(&self.window).enumerate() {
+            self.re[i] = s * w;
+            self.im[i] = 0.0;
+        }
+        fft(&mut self.re, &mut self.im);
+
+        let half = self.window.len() / 2;
+        for k in 0..=half {
+            self.re[k] = self.re[k] * self.re[k] + self.im[k] * self.im[k];
+        }
+        let power = &self.re[..=half];
+        // Skip DC, which says nothing about feedback.
+        let total: f32 = power[1..].iter().sum();
+        let (bin, _) = power
+            .iter()
+            .enumerate()
+            .skip(1)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        let around: f32 = power[bin.saturating_sub(2).max(1)..(bin + 3).min(half + 1)]
+            .iter()
+            .sum();
+        (total > 0.0 && around / total >= self.peak_ratio).then_some(bin)
+    }
+}
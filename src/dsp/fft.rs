@@ -0,0 +1,46 @@
+use std::f32::consts::PI;
+
+/// In-place radix-2 FFT; the length must be a power of two.
+pub(super) fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// In-place inverse of [`fft`], scaled so a round trip returns the input.
+pub(super) fn ifft(re: &mut [f32], im: &mut [f32]) {
+    im.iter_mut().for_each(|x| *x = -*x);
+    fft(re, im);
+    let scale = 1.0 / re.len() as f32;
+    re.iter_mut().for_each(|x| *x *= scale);
+    im.iter_mut().for_each(|x| *x *= -scale);
+}
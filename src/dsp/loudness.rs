@@ -0,0 +1,150 @@
+use std::f64::consts::PI;
+
+/// Tracks the highest absolute sample value of a signal.
+#[derive(Debug, Clone, Default)]
+pub struct PeakMeter {
+    peak: f32,
+}
+
+impl PeakMeter {
+    /// Feeds interleaved samples into the meter.
+    pub fn push(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            self.peak = self.peak.max(sample.abs());
+        }
+    }
+
+    /// Returns the peak as a linear value.
+    pub fn peak(&self) -> f32 {
+        self.peak
+    }
+}
+
+/// Second-order IIR filter section in direct form I.
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The two-stage K-weighting filter from ITU-R BS.1770, designed for any sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    // Stage 1: high shelf modelling the acoustic effect of the head.
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        x: [0.0; 2],
+        y: [0.0; 2],
+    };
+
+    // Stage 2: the RLB high-pass.
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        x: [0.0; 2],
+        y: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+/// Measures integrated loudness (LUFS) following ITU-R BS.1770-4.
+///
+/// Audio is K-weighted and split into 400 ms blocks overlapping by 75%, which are
+/// then gated at -70 LUFS and 10 LU below the ungated mean. All channels are
+/// weighted equally.
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    step_frames: usize,
+    step_filled: usize,
+    step_energy: f64,
+    /// Mean square energy of each 100 ms step, summed over channels.
+    steps: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    /// Creates a meter for a stream with the given sample rate and channel count.
+    pub fn new(sample_rate: u32, channels: u16) -> LoudnessMeter {
+        LoudnessMeter {
+            channels: channels as usize,
+            filters: vec![k_weighting(sample_rate); channels as usize],
+            step_frames: (sample_rate as usize / 10).max(1),
+            step_filled: 0,
+            step_energy: 0.0,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Feeds interleaved frames into the meter.
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (sample, [shelf, high_pass]) in frame.iter().zip(self.filters.iter_mut()) {
+                let y = high_pass.process(shelf.process(*sample as f64));
+                self.step_energy += y * y;
+            }
+            self.step_filled += 1;
+            if self.step_filled == self.step_frames {
+                self.steps.push(self.step_energy / self.step_frames as f64);
+                self.step_energy = 0.0;
+                self.step_filled = 0;
+            }
+        }
+    }
+
+    /// Returns the gated integrated loudness in LUFS.
+    ///
+    /// # Returns
+    /// * `Option<f64>` - The loudness, or `None` if the signal is shorter than one
+    ///   block or entirely below the absolute gate.
+    pub fn integrated(&self) -> Option<f64> {
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(4)
+            .map(|w| w.iter().sum::<f64>() / 4.0)
+            .collect();
+        let loudness = |energy: f64| -0.691 + 10.0 * energy.log10();
+        let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+
+        let above_absolute: Vec<f64> = blocks
+            .into_iter()
+            .filter(|&e| loudness(e) > -70.0)
+            .collect();
+        if above_absolute.is_empty() {
+            return None;
+        }
+        let relative_gate = loudness(mean(&above_absolute)) - 10.0;
+        let gated: Vec<f64> = above_absolute
+            .into_iter()
+            .filter(|&e| loudness(e) > relative_gate)
+            .collect();
+        Some(loudness(mean(&gated)))
+    }
+}
@@ -0,0 +1,26 @@
+mod channels;
+mod correlate;
+mod feedback;
+mod fft;
+mod loudness;
+mod resample;
+mod silence;
+mod stretch;
+
+pub use channels::ChannelMap;
+pub use correlate::cross_correlate;
+pub use feedback::{FeedbackConfig, FeedbackDetector};
+pub use loudness::{LoudnessMeter, PeakMeter};
+pub use resample::Resampler;
+pub use silence::{SilenceConfig, SilenceDetector};
+pub use stretch::{TimeStretch, MAX_SPEED, MIN_SPEED};
+
+/// Converts a level in decibels to a linear gain.
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Converts a linear gain to decibels. Silence maps to negative infinity.
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
@@ -0,0 +1,145 @@
+use std::f64::consts::PI;
+
+/// Number of input frames on each side of the output position the filter looks at.
+const HALF_TAPS: usize = 16;
+
+/// Streaming sample-rate converter using a windowed sinc filter.
+///
+/// Interleaved frames go in with [`Resampler::process`] in blocks of any size
+/// and come out at the target rate, delayed by nothing: output frame `n` is the
+/// input signal at time `n / to_rate`. When downsampling, the filter cutoff is
+/// lowered to the target Nyquist frequency so nothing aliases.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    from_rate: u32,
+    to_rate: u32,
+    channels: usize,
+    /// Input frames consumed per output frame.
+    step: f64,
+    cutoff: f64,
+    /// Interleaved input still needed by the filter, starting `HALF_TAPS` frames
+    /// before the current position.
+    history: Vec<f32>,
+    /// Position of the next output frame, in frames of `history`.
+    position: f64,
+    frames_in: u64,
+    frames_out: u64,
+    weights: Vec<f64>,
+}
+
+impl Resampler {
+    /// Creates a converter from `from_rate` to `to_rate` for the given channel count.
+    pub fn new(from_rate: u32, to_rate: u32, channels: u16) -> Resampler {
+        let channels = channels.max(1) as usize;
+        Resampler {
+            from_rate,
+            to_rate,
+            channels,
+            step: from_rate as f64 / to_rate as f64,
+            cutoff: (to_rate as f64 / from_rate as f64).min(1.0),
+            history: vec![0.0; HALF_TAPS * channels],
+            position: HALF_TAPS as f64,
+            frames_in: 0,
+            frames_out: 0,
+            weights: vec![0.0; 2 * HALF_TAPS],
+        }
+    }
+
+    /// Returns the input sample rate.
+    pub fn from_rate(&self) -> u32 {
+        self.from_rate
+    }
+
+    /// Returns the output sample rate.
+    pub fn to_rate(&self) -> u32 {
+        self.to_rate
+    }
+
+    /// Returns `true` if the rates are equal and samples pass through unchanged.
+    pub fn is_passthrough(&self) -> bool {
+        self.from_rate == self.to_rate
+    }
+
+    /// Feeds interleaved frames in and appends the converted frames to `out`.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        self.frames_in += (input.len() / self.channels) as u64;
+        if self.is_passthrough() {
+            self.frames_out += (input.len() / self.channels) as u64;
+            out.extend_from_slice(input);
+            return;
+        }
+        self.history.extend_from_slice(input);
+        self.run(out, u64::MAX);
+    }
+
+    /// Flushes the frames still held back by the filter once the input has ended.
+    ///
+    /// The total output is the input length scaled by the rate ratio, rounded up.
+    pub fn finish(&mut self, out: &mut Vec<f32>) {
+        if self.is_passthrough() {
+            return;
+        }
+        let expected = (self.frames_in * self.to_rate as u64).div_ceil(self.from_rate as u64);
+        self.history
+            .resize(self.history.len() + (HALF_TAPS + 1) * self.channels, 0.0);
+        self.run(out, expected);
+    }
+
+    /// Clears all state, as if the converter had just been created.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.history.resize(HALF_TAPS * self.channels, 0.0);
+        self.position = HALF_TAPS as f64;
+        self.frames_in = 0;
+        self.frames_out = 0;
+    }
+
+    fn run(&mut self, out: &mut Vec<f32>, limit: u64) {
+        let frames = self.history.len() / self.channels;
+        while self.frames_out < limit && self.position as usize + HALF_TAPS < frames {
+            let base = self.position.floor() as usize;
+            let frac = self.position - base as f64;
+            for (i, weight) in self.weights.iter_mut().enumerate() {
+                // Distance from the output position to input frame `base + 1 - HALF_TAPS + i`.
+                let t = frac + HALF_TAPS as f64 - 1.0 - i as f64;
+                *weight = kernel(t, self.cutoff);
+            }
+            let first = base + 1 - HALF_TAPS;
+            for ch in 0..self.channels {
+                let sum: f64 = self
+                    .weights
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| w * self.history[(first + i) * self.channels + ch] as f64)
+                    .sum();
+                out.push(sum as f32);
+            }
+            self.position += self.step;
+            self.frames_out += 1;
+        }
+
+        // Drop the input no later output frame can reach.
+        let keep_from = (self.position.floor() as usize + 1).saturating_sub(HALF_TAPS);
+        let drop = keep_from.min(frames);
+        if drop > 0 {
+            self.history.drain(..drop * self.channels);
+            self.position -= drop as f64;
+        }
+    }
+}
+
+/// Blackman-windowed sinc, low-passed at `cutoff` times the input Nyquist frequency.
+fn kernel(t: f64, cutoff: f64) -> f64 {
+    let x = t / HALF_TAPS as f64;
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let window = 0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos();
+    let arg = PI * cutoff * t;
+    let sinc = if arg.abs() < 1e-9 {
+        1.0
+    } else {
+        arg.sin() / arg
+    };
+    cutoff * sinc * window
+}
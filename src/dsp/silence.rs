@@ -0,0 +1,129 @@
+use super::db_to_gain;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::time::Duration;
+
+/// Thresholds used to decide which parts of a recording are silent.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SilenceConfig {
+    /// Windows whose RMS level is below this are silent, in dBFS.
+    pub threshold_db: f32,
+    /// Length of the analysis window.
+    pub window: Duration,
+    /// Audio kept on either side of the audible part when trimming.
+    pub padding: Duration,
+}
+
+impl Default for SilenceConfig {
+    fn default() -> Self {
+        SilenceConfig {
+            threshold_db: -50.0,
+            window: Duration::from_millis(20),
+            padding: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Streaming detector that finds the audible part of a signal.
+///
+/// Frames are fed in with [`SilenceDetector::push`] and classified in windows of
+/// [`SilenceConfig::window`] by their RMS level across all channels.
+pub struct SilenceDetector {
+    threshold: f32,
+    window_frames: u64,
+    padding_frames: u64,
+    channels: usize,
+    sum_squares: f64,
+    window_filled: u64,
+    position: u64,
+    audible: Option<Range<u64>>,
+    /// Audible stretches, merged when the silence between them is shorter than
+    /// twice the padding.
+    regions: Vec<Range<u64>>,
+}
+
+impl SilenceDetector {
+    /// Creates a detector for a stream with the given sample rate and channel count.
+    pub fn new(config: &SilenceConfig, sample_rate: u32, channels: u16) -> SilenceDetector {
+        let frames = |d: Duration| (d.as_secs_f64() * sample_rate as f64) as u64;
+        SilenceDetector {
+            threshold: db_to_gain(config.threshold_db),
+            window_frames: frames(config.window).max(1),
+            padding_frames: frames(config.padding),
+            channels: channels as usize,
+            sum_squares: 0.0,
+            window_filled: 0,
+            position: 0,
+            audible: None,
+            regions: Vec::new(),
+        }
+    }
+
+    /// Feeds interleaved frames into the detector.
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            self.sum_squares += frame.iter().map(|&s| (s * s) as f64).sum::<f64>();
+            self.window_filled += 1;
+            self.position += 1;
+            if self.window_filled == self.window_frames {
+                self.close_window();
+            }
+        }
+    }
+
+    /// Returns `true` if a window of the given mean square level counts as silence.
+    pub fn is_silent_level(&self, mean_square: f64) -> bool {
+        (mean_square.sqrt() as f32) < self.threshold
+    }
+
+    /// Finishes analysis and returns the audible range with padding applied.
+    ///
+    /// # Returns
+    /// * `Option<Range<u64>>` - The frames to keep, or `None` if everything was silent.
+    pub fn finish(mut self) -> Option<Range<u64>> {
+        if self.window_filled > 0 {
+            self.close_window();
+        }
+        let total = self.position;
+        self.audible.map(|range| {
+            range.start.saturating_sub(self.padding_frames)
+                ..(range.end + self.padding_frames).min(total)
+        })
+    }
+
+    /// Finishes analysis and returns every audible stretch with padding applied.
+    ///
+    /// Stretches separated by less than twice the padding are merged, so the
+    /// result is a list of non-overlapping regions such as spoken phrases.
+    pub fn finish_regions(mut self) -> Vec<Range<u64>> {
+        if self.window_filled > 0 {
+            self.close_window();
+        }
+        let total = self.position;
+        let padding = self.padding_frames;
+        self.regions
+            .into_iter()
+            .map(|range| range.start.saturating_sub(padding)..(range.end + padding).min(total))
+            .collect()
+    }
+
+    fn close_window(&mut self) {
+        let mean_square =
+            self.sum_squares / (self.window_filled as f64 * self.channels as f64);
+        if !self.is_silent_level(mean_square) {
+            let start = self.position - self.window_filled;
+            self.audible = Some(match self.audible.take() {
+                Some(range) => range.start..self.position,
+                None => start..self.position,
+            });
+            match self.regions.last_mut() {
+                Some(last) if start <= last.end + 2 * self.padding_frames => {
+                    last.end = self.position
+                }
+                _ => self.regions.push(start..self.position),
+            }
+        }
+        self.sum_squares = 0.0;
+        self.window_filled = 0;
+    }
+}
@@ -0,0 +1,222 @@
+use std::f64::consts::PI;
+
+/// Slowest supported speed.
+pub const MIN_SPEED: f64 = 0.5;
+/// Fastest supported speed.
+pub const MAX_SPEED: f64 = 3.0;
+
+/// Streaming time stretcher using WSOLA (waveform similarity overlap-add).
+///
+/// Changes the speed of interleaved audio without changing its pitch. The
+/// output is built from overlapping windows of the input taken at the chosen
+/// speed, each nudged to the offset where it best continues the waveform
+/// already written, so voices stay natural without the phasiness of a plain
+/// overlap-add. The speed can change between calls to [`TimeStretch::process`]
+/// without clicks: only the spacing of the next windows changes.
+///
+/// At speed 1.0 the input comes out unchanged.
+#[derive(Debug, Clone)]
+pub struct TimeStretch {
+    channels: usize,
+    speed: f64,
+    /// Window length in frames.
+    frame: usize,
+    /// Output frames per window; windows overlap by half.
+    hop: usize,
+    /// How far a window may move from its nominal position, in frames.
+    search: usize,
+    window: Vec<f32>,
+    /// Interleaved input not yet used up.
+    input: Vec<f32>,
+    /// Nominal start of the next window, in frames of `input`.
+    nominal: f64,
+    /// Where the audio of the previous window continues, in frames of `input`.
+    natural: Option<usize>,
+    /// Second half of the previous window, to overlap with the next one.
+    tail: Vec<f32>,
+    /// Mono mix of the search range, reused between windows.
+    mono: Vec<f32>,
+}
+
+impl TimeStretch {
+    /// Creates a stretcher for audio at `sample_rate` with the given channel
+    /// count, running at speed 1.0.
+    pub fn new(sample_rate: u32, channels: u16) -> TimeStretch {
+        // 30 ms windows resolve voice pitch periods; an 8 ms search covers them.
+        let frame = ((sample_rate as f64 * 0.030) as usize / 2 * 2).max(32);
+        let window = (0..frame)
+            .map(|i| (0.5 - 0.5 * (2.0 * PI * i as f64 / frame as f64).cos()) as f32)
+            .collect();
+        TimeStretch {
+            channels: channels.max(1) as usize,
+            speed: 1.0,
+            frame,
+            hop: frame / 2,
+            search: ((sample_rate as f64 * 0.008) as usize).max(4),
+            window,
+            input: Vec::new(),
+            nominal: 0.0,
+            natural: None,
+            tail: Vec::new(),
+            mono: Vec::new(),
+        }
+    }
+
+    /// Returns the speed; 2.0 plays twice as fast.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Sets the speed, clamped to [`MIN_SPEED`]..=[`MAX_SPEED`]. Takes effect
+    /// from the next window.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = if speed.is_finite() {
+            speed.clamp(MIN_SPEED, MAX_SPEED)
+        } else {
+            1.0
+        };
+    }
+
+    /// Returns `true` if input is buffered or a window is half written, so the
+    /// stretcher must keep being fed to avoid losing audio.
+    pub fn is_primed(&self) -> bool {
+        self.natural.is_some() || !self.input.is_empty()
+    }
+
+    /// Feeds interleaved frames in and appends the stretched frames to `out`.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        self.input.extend_from_slice(input);
+        while self.step(out) {}
+    }
+
+    /// Flushes the buffered input once it has ended, and resets the stretcher.
+    pub fn finish(&mut self, out: &mut Vec<f32>) {
+        let ch = self.channels;
+        let buffered = self.input.len() / ch;
+        self.input
+            .resize((buffered + self.search + self.frame) * ch, 0.0);
+        // End of the real input in frames of `input`; negative once drained past.
+        let mut end = buffered as isize;
+        while self.nominal < end as f64 {
+            let before = self.input.len();
+            if !self.step(out) {
+                break;
+            }
+            end -= ((before - self.input.len()) / ch) as isize;
+        }
+        if let Some(natural) = self.natural {
+            let natural = natural as isize;
+            if natural > end {
+                // The last window ran into the padding.
+                let past = (natural - end).min(self.hop as isize) as usize;
+                out.truncate(out.len() - past * ch);
+            } else {
+                let keep = (end - natural).min(self.hop as isize) as usize;
+                out.extend_from_slice(&self.tail[..keep * ch]);
+            }
+        }
+        self.reset();
+    }
+
+    /// Drops all buffered audio, as after a seek.
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.nominal = 0.0;
+        self.natural = None;
+        self.tail.clear();
+    }
+
+    /// Writes one window's worth of output if enough input is buffered.
+    fn step(&mut self, out: &mut Vec<f32>) -> bool {
+        let ch = self.channels;
+        let nominal = self.nominal as usize;
+        if self.input.len() / ch < nominal + self.search + self.frame {
+            return false;
+        }
+        let start = match self.natural {
+            Some(natural) => self.best_start(natural, nominal),
+            None => nominal,
+        };
+
+        let x = &self.input[start * ch..(start + self.frame) * ch];
+        if self.natural.is_some() {
+            for i in 0..self.hop {
+                for c in 0..ch {
+                    out.push(self.tail[i * ch + c] + self.window[i] * x[i * ch + c]);
+                }
+            }
+        } else {
+            // Nothing to overlap with yet: the first half goes out as is.
+            out.extend_from_slice(&x[..self.hop * ch]);
+        }
+        self.tail.clear();
+        for i in 0..self.hop {
+            for c in 0..ch {
+                let j = (self.hop + i) * ch + c;
+                self.tail.push(self.window[self.hop + i] * x[j]);
+            }
+        }
+
+        self.nominal += self.hop as f64 * self.speed;
+
+        // Keep what the next window may still read.
+        let keep_from = (start + self.hop).min((self.nominal as usize).saturating_sub(self.search));
+        self.input.drain(..keep_from * ch);
+        self.nominal -= keep_from as f64;
+        self.natural = Some(start + self.hop - keep_from);
+        true
+    }
+
+    /// Finds the window start near `nominal` whose first half best matches the
+    /// audio at `natural`, the continuation of the previous window.
+    fn best_start(&mut self, natural: usize, nominal: usize) -> usize {
+        let ch = self.channels;
+        let lo = nominal.saturating_sub(self.search);
+        let hi = nominal + self.search;
+        let len = self.hop;
+
+        let mono = |input: &[f32], frame: usize| -> f32 {
+            input[frame * ch..(frame + 1) * ch].iter().sum()
+        };
+        let reference: Vec<f32> = (natural..natural + len)
+            .map(|f| mono(&self.input, f))
+            .collect();
+        self.mono.clear();
+        self.mono
+            .extend((lo..hi + len).map(|f| mono(&self.input, f)));
+
+        let score = |mono: &[f32], offset: usize, stride: usize| -> f64 {
+            let (mut dot, mut energy) = (0.0f64, 1e-9f64);
+            for i in (0..len).step_by(stride) {
+                let s = mono[offset + i] as f64;
+                dot += reference[i] as f64 * s;
+                energy += s * s;
+            }
+            dot / energy.sqrt()
+        };
+
+        // Coarse search over every fourth offset, then refine around the best.
+        let span = hi - lo;
+        let mut best = (nominal - lo, f64::MIN);
+        for offset in (0..=span).step_by(4) {
+            let s = score(&self.mono, offset, 2);
+            if s > best.1 {
+                best = (offset, s);
+            }
+        }
+        let coarse = best.0;
+        // The natural continuation goes first so it wins ties, which keeps
+        // speed 1.0 sample exact.
+        best.1 = f64::MIN;
+        if (lo..=hi).contains(&natural) {
+            best = (natural - lo, score(&self.mono, natural - lo, 1));
+        }
+        for offset in coarse.saturating_sub(3)..=(coarse + 3).min(span) {
+            let s = score(&self.mono, offset, 1);
+            if s > best.1 {
+                best = (offset, s);
+            }
+        }
+        lo + best.0
+    }
+}
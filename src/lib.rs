@@ -1,4 +1,10 @@
 pub mod audio_setup;
+pub mod crypto;
+pub mod decode;
+pub mod dsp;
+pub mod library;
+pub mod player;
 pub mod recorder;
+pub mod riff;
 pub mod utils;
 pub mod visualizer;
@@ -0,0 +1,349 @@
+use super::{embed, write_wav, Clip, ClipLibrary, ClipQuery, Normalization, PostProcess};
+use crate::decode::FrameSource;
+use crate::dsp::{gain_to_db, LoudnessMeter, PeakMeter, SilenceConfig};
+use anyhow::{anyhow, Context};
+use hound::SampleFormat;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// One stage of a batch pipeline, applied to each clip in order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum BatchStep {
+    /// Convert the clip to `sample_rate`; clips already at that rate are left alone.
+    Resample { sample_rate: u32 },
+    /// Normalize the clip in place, see [`ClipLibrary::post_process`].
+    Normalize { target: Normalization },
+    /// Trim leading and trailing silence in place, see [`ClipLibrary::post_process`].
+    /// Clips with edits fail rather than lose their place.
+    TrimSilence { config: SilenceConfig },
+    /// Write the clip as `<id>.wav` into `dir`, as float for 32 bits and PCM otherwise.
+    Encode { dir: PathBuf, bits_per_sample: u16 },
+    /// Measure the clip's level and loudness.
+    Analyze,
+}
+
+/// Measurements taken by [`BatchStep::Analyze`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Analysis {
+    pub duration_secs: f64,
+    pub peak_dbfs: f32,
+    /// Integrated loudness, if the clip is long and loud enough to measure.
+    pub loudness_lufs: Option<f64>,
+}
+
+/// What happened to one clip of a batch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ClipOutcome {
+    Done { analysis: Option<Analysis> },
+    Failed { reason: String },
+}
+
+/// Progress reported after each clip of a batch finishes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchProgress {
+    /// Clips matching the query, including those finished by an earlier run.
+    pub total: usize,
+    /// Clips finished so far, including those finished by an earlier run.
+    pub finished: usize,
+    pub clip: String,
+    pub outcome: ClipOutcome,
+}
+
+/// The outcome of a batch run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchReport {
+    /// Outcome of every clip processed in this run.
+    pub outcomes: BTreeMap<String, ClipOutcome>,
+    /// Clips skipped because an earlier run already finished them.
+    pub skipped: usize,
+    /// `true` if the run was stopped before every clip was processed.
+    pub interrupted: bool,
+}
+
+impl BatchReport {
+    /// Returns the ids and reasons of the clips that failed.
+    pub fn failures(&self) -> Vec<(&str, &str)> {
+        self.outcomes
+            .iter()
+            .filter_map(|(id, outcome)| match outcome {
+                ClipOutcome::Failed { reason } => Some((id.as_str(), reason.as_str())),
+                ClipOutcome::Done { .. } => None,
+            })
+            .collect()
+    }
+}
+
+/// The job state file: the pipeline and every clip finished so far.
+#[derive(Debug, Serialize, Deserialize)]
+struct BatchState {
+    pipeline: Vec<BatchStep>,
+    clips: BTreeMap<String, ClipOutcome>,
+}
+
+impl BatchState {
+    fn load(path: &Path, pipeline: &[BatchStep]) -> Result<BatchState, anyhow::Error> {
+        if !path.is_file() {
+            return Ok(BatchState {
+                pipeline: pipeline.to_vec(),
+                clips: BTreeMap::new(),
+            });
+        }
+        let state: BatchState = serde_json::from_str(&fs::read_to_string(path)?)
+            .with_context(|| format!("invalid batch state in {}", path.display()))?;
+        if state.pipeline != pipeline {
+            return Err(anyhow!(
+                "{} belongs to a batch with a different pipeline",
+                path.display()
+            ));
+        }
+        Ok(state)
+    }
+
+    fn store(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Runs a pipeline of steps over every clip matching a query.
+///
+/// Clips are processed in parallel, one per worker thread. With a state file
+/// the outcome of each clip is recorded as soon as it finishes, so a batch that
+/// is stopped or killed can be run again and continues with the clips it had
+/// not finished; clips that failed are retried.
+pub struct BatchRunner {
+    pipeline: Vec<BatchStep>,
+    query: ClipQuery,
+    state_file: Option<PathBuf>,
+    threads: usize,
+    stop: Arc<AtomicBool>,
+}
+
+impl BatchRunner {
+    /// Creates a runner applying `pipeline` to every finished clip, on as many
+    /// threads as there are cores.
+    pub fn new(pipeline: Vec<BatchStep>) -> BatchRunner {
+        BatchRunner {
+            pipeline,
+            query: ClipQuery::default(),
+            state_file: None,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Limits the batch to the clips matching `query`.
+    pub fn with_query(mut self, query: ClipQuery) -> BatchRunner {
+        self.query = query;
+        self
+    }
+
+    /// Records progress in `path`, and resumes from it if it already exists.
+    pub fn with_state_file(mut self, path: impl AsRef<Path>) -> BatchRunner {
+        self.state_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the number of worker threads.
+    pub fn with_threads(mut self, threads: usize) -> BatchRunner {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Returns a flag that stops the batch once the clips in progress finish.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Runs the batch.
+    ///
+    /// # Arguments
+    /// * `library` - The library holding the clips.
+    /// * `on_progress` - Called after each clip finishes, from the worker thread.
+    ///
+    /// # Returns
+    /// * `BatchReport` - The outcome of each clip processed in this run.
+    pub fn run(
+        &self,
+        library: &ClipLibrary,
+        on_progress: impl Fn(&BatchProgress) + Sync,
+    ) -> Result<BatchReport, anyhow::Error> {
+        self.stop.store(false, Ordering::Relaxed);
+        let state = match &self.state_file {
+            Some(path) => BatchState::load(path, &self.pipeline)?,
+            None => BatchState {
+                pipeline: self.pipeline.clone(),
+                clips: BTreeMap::new(),
+            },
+        };
+        let clips = library.query(&self.query)?;
+        let total = clips.len();
+        let pending: Vec<Clip> = clips
+            .into_iter()
+            .filter(|clip| !matches!(state.clips.get(clip.id()), Some(ClipOutcome::Done { .. })))
+            .collect();
+        let skipped = total - pending.len();
+
+        let next = AtomicUsize::new(0);
+        let shared = Mutex::new((state, BatchReport::default(), skipped));
+        let workers = self.threads.min(pending.len()).max(1);
+        let results: Vec<Result<(), anyhow::Error>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| -> Result<(), anyhow::Error> {
+                        while !self.stop.load(Ordering::Relaxed) {
+                            let Some(clip) = pending.get(next.fetch_add(1, Ordering::Relaxed))
+                            else {
+                                break;
+                            };
+                            let outcome = match self.process(library, clip) {
+                                Ok(analysis) => ClipOutcome::Done { analysis },
+                                Err(e) => ClipOutcome::Failed {
+                                    reason: format!("{:#}", e),
+                                },
+                            };
+
+                            let mut shared = shared.lock().expect("batch state lock poisoned");
+                            let (state, report, finished) = &mut *shared;
+                            state.clips.insert(clip.id().to_string(), outcome.clone());
+                            report
+                                .outcomes
+                                .insert(clip.id().to_string(), outcome.clone());
+                            *finished += 1;
+                            if let Some(path) = &self.state_file {
+                                if let Err(e) = state.store(path) {
+                                    self.stop.store(true, Ordering::Relaxed);
+                                    return Err(e);
+                                }
+                            }
+                            on_progress(&BatchProgress {
+                                total,
+                                finished: *finished,
+                                clip: clip.id().to_string(),
+                                outcome,
+                            });
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .unwrap_or_else(|_| Err(anyhow!("batch worker panicked")))
+                })
+                .collect()
+        });
+        results.into_iter().collect::<Result<(), _>>()?;
+
+        let (_, mut report, _) = shared.into_inner().expect("batch state lock poisoned");
+        report.skipped = skipped;
+        report.interrupted = report.outcomes.len() < pending.len();
+        Ok(report)
+    }
+
+    /// Applies the pipeline to one clip.
+    fn process(
+        &self,
+        library: &ClipLibrary,
+        clip: &Clip,
+    ) -> Result<Option<Analysis>, anyhow::Error> {
+        let mut clip = clip.clone();
+        let mut changed = false;
+        let mut analysis = None;
+        for step in &self.pipeline {
+            match step {
+                BatchStep::Resample { sample_rate } => {
+                    if clip.meta.sample_rate != *sample_rate {
+                        clip = library.resample(&clip, *sample_rate)?;
+                        changed = true;
+                    }
+                }
+                BatchStep::Normalize { target } => {
+                    let options = PostProcess {
+                        normalize: Some(*target),
+                        ..PostProcess::default()
+                    };
+                    clip = library.post_process(&clip, &options)?;
+                    changed = true;
+                }
+                BatchStep::TrimSilence { config } => {
+                    let options = PostProcess {
+                        trim_silence: Some(*config),
+                        ..PostProcess::default()
+                    };
+                    clip = library.post_process(&clip, &options)?;
+                    changed = true;
+                }
+                BatchStep::Encode {
+                    dir,
+                    bits_per_sample,
+                } => encode(library, &clip, dir, *bits_per_sample)?,
+                BatchStep::Analyze => analysis = Some(analyze(library, &clip)?),
+            }
+        }
+        if changed {
+            library.build_peaks(&clip)?;
+        }
+        Ok(analysis)
+    }
+}
+
+/// Writes `clip` into `dir` as a WAV file with the given sample size.
+fn encode(
+    library: &ClipLibrary,
+    clip: &Clip,
+    dir: &Path,
+    bits_per_sample: u16,
+) -> Result<(), anyhow::Error> {
+    let mut reader = library.reader(clip)?;
+    let mut spec = reader.spec();
+    spec.bits_per_sample = bits_per_sample;
+    spec.sample_format = match bits_per_sample {
+        32 => SampleFormat::Float,
+        8 | 16 | 24 => SampleFormat::Int,
+        _ => {
+            return Err(anyhow!(
+                "unsupported sample size of {} bits",
+                bits_per_sample
+            ))
+        }
+    };
+    fs::create_dir_all(dir)?;
+    let dest = dir.join(format!("{}.wav", clip.id()));
+    write_wav(&mut reader, spec, &dest)?;
+    embed::write_file_chunks(&clip.meta, &dest)
+}
+
+/// Measures the peak level and integrated loudness of `clip`.
+fn analyze(library: &ClipLibrary, clip: &Clip) -> Result<Analysis, anyhow::Error> {
+    let (rate, channels) = (clip.meta.sample_rate, clip.meta.channels);
+    let mut reader = library.reader(clip)?;
+    let mut peak = PeakMeter::default();
+    let mut loudness = LoudnessMeter::new(rate, channels);
+    let mut buf = vec![0.0; 4096 * channels as usize];
+    loop {
+        let frames = reader.read_frames(&mut buf)?;
+        if frames == 0 {
+            break;
+        }
+        let samples = &buf[..frames * channels as usize];
+        peak.push(samples);
+        loudness.push(samples);
+    }
+    Ok(Analysis {
+        duration_secs: clip.meta.duration_secs(),
+        peak_dbfs: gain_to_db(peak.peak()),
+        loudness_lufs: loudness.integrated(),
+    })
+}
@@ -0,0 +1,210 @@
+use super::{embed, write_wav, Clip, ClipLibrary, ClipMetadata};
+use crate::crypto;
+use anyhow::{anyhow, Context};
+use hound::SampleFormat;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Name of the optional transcript stored in a clip directory.
+const TRANSCRIPT_FILE: &str = "transcript.txt";
+/// Version of the bundle layout written by this build.
+const BUNDLE_VERSION: u32 = 1;
+
+const MANIFEST_ENTRY: &str = "manifest.json";
+const AUDIO_ENTRY: &str = "audio.wav";
+const TRANSCRIPT_ENTRY: &str = "transcript.txt";
+
+/// Sample format of the audio written into a bundle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BundleAudio {
+    /// 32-bit float, exactly as stored in the library.
+    #[default]
+    Original,
+    /// 16-bit PCM, half the size, for sharing.
+    Pcm16,
+}
+
+/// The `manifest.json` at the start of every bundle.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    clip: ClipMetadata,
+}
+
+impl ClipLibrary {
+    /// Returns the transcript of `clip`, if one was stored.
+    pub fn transcript(&self, clip: &Clip) -> Result<Option<String>, anyhow::Error> {
+        let path = clip.dir.join(TRANSCRIPT_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let text = String::from_utf8(crypto::read(path, self.key.as_ref())?)
+            .context("transcript is not valid UTF-8")?;
+        Ok(Some(text))
+    }
+
+    /// Stores `text` as the transcript of `clip`, replacing any earlier one.
+    pub fn set_transcript(&self, clip: &Clip, text: &str) -> Result<(), anyhow::Error> {
+        crypto::write(
+            clip.dir.join(TRANSCRIPT_FILE),
+            text.as_bytes(),
+            self.key.as_ref(),
+        )
+    }
+
+    /// Packs `clip` into a single tar archive at `dest` for sharing.
+    ///
+    /// The bundle holds the clip's audio as one WAV file with its metadata and
+    /// markers embedded, a `manifest.json` with the full clip metadata, and the
+    /// transcript if there is one. Audio from an encrypted library is decrypted,
+    /// and staged next to `dest` rather than in the library while it is packed.
+    ///
+    /// # Arguments
+    /// * `clip` - The clip to pack.
+    /// * `dest` - Path of the archive to create.
+    /// * `audio` - Whether to keep the stored sample format or re-encode the audio.
+    pub fn export_bundle(
+        &self,
+        clip: &Clip,
+        dest: impl AsRef<Path>,
+        audio: BundleAudio,
+    ) -> Result<(), anyhow::Error> {
+        let wav = staging_path(dest.as_ref());
+        let result = self.write_bundle(clip, dest.as_ref(), audio, &wav);
+        let _ = fs::remove_file(&wav);
+        result.with_context(|| format!("failed to export bundle {}", dest.as_ref().display()))
+    }
+
+    /// Restores a bundle made by [`ClipLibrary::export_bundle`] as a new clip.
+    ///
+    /// The clip gets a fresh id, so a bundle never overwrites a clip already in
+    /// the library, even one it was exported from. Title, tags, recording time,
+    /// star, markers and transcript are carried over; the audio goes through the
+    /// library's post-processing like any import. The audio is staged next to
+    /// the bundle while it is imported, so an interrupted import never leaves it
+    /// unencrypted in an encrypted library.
+    pub fn import_bundle(&self, path: impl AsRef<Path>) -> Result<Clip, anyhow::Error> {
+        let path = path.as_ref();
+        let wav = staging_path(path);
+        let result = self.read_bundle(path, &wav);
+        let _ = fs::remove_file(&wav);
+        result.with_context(|| format!("failed to import bundle {}", path.display()))
+    }
+
+    fn write_bundle(
+        &self,
+        clip: &Clip,
+        dest: &Path,
+        audio: BundleAudio,
+        wav: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let mut reader = self.reader(clip)?;
+        let mut spec = reader.spec();
+        if audio == BundleAudio::Pcm16 {
+            spec.bits_per_sample = 16;
+            spec.sample_format = SampleFormat::Int;
+        }
+        write_wav(&mut reader, spec, wav)?;
+        embed::write_file_chunks(&clip.meta, wav)?;
+
+        let manifest = Manifest {
+            version: BUNDLE_VERSION,
+            clip: clip.meta.clone(),
+        };
+        let mut archive = tar::Builder::new(File::create(dest)?);
+        append(
+            &mut archive,
+            MANIFEST_ENTRY,
+            serde_json::to_string_pretty(&manifest)?.as_bytes(),
+        )?;
+        archive.append_path_with_name(wav, AUDIO_ENTRY)?;
+        if let Some(text) = self.transcript(clip)? {
+            append(&mut archive, TRANSCRIPT_ENTRY, text.as_bytes())?;
+        }
+        archive.into_inner()?.sync_all()?;
+        Ok(())
+    }
+
+    fn read_bundle(&self, path: &Path, wav: &Path) -> Result<Clip, anyhow::Error> {
+        let mut manifest = None;
+        let mut transcript = None;
+        let mut has_audio = false;
+        let mut archive = tar::Archive::new(File::open(path)?);
+        // Entries are matched by name and never unpacked to paths taken from the archive.
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            match name.as_str() {
+                MANIFEST_ENTRY => {
+                    let mut json = Vec::new();
+                    entry.read_to_end(&mut json)?;
+                    let parsed: Manifest =
+                        serde_json::from_slice(&json).context("invalid bundle manifest")?;
+                    if parsed.version > BUNDLE_VERSION {
+                        return Err(anyhow!(
+                            "bundle version {} is newer than this build supports",
+                            parsed.version
+                        ));
+                    }
+                    manifest = Some(parsed);
+                }
+                AUDIO_ENTRY => {
+                    io::copy(&mut entry, &mut File::create(wav)?)?;
+                    has_audio = true;
+                }
+                TRANSCRIPT_ENTRY => {
+                    let mut text = String::new();
+                    entry.read_to_string(&mut text)?;
+                    transcript = Some(text);
+                }
+                _ => {}
+            }
+        }
+        let manifest = manifest.ok_or_else(|| anyhow!("bundle has no {}", MANIFEST_ENTRY))?;
+        if !has_audio {
+            return Err(anyhow!("bundle has no {}", AUDIO_ENTRY));
+        }
+
+        // Markers come from the cue chunks of the audio, so they follow any
+        // trimming applied by post-processing.
+        let mut clip = self.import(wav)?;
+        let source = manifest.clip;
+        clip.meta.title = source.title;
+        clip.meta.tags = source.tags;
+        clip.meta.created = source.created;
+        clip.meta.starred = source.starred;
+        self.save(&clip)?;
+        if let Some(text) = transcript {
+            self.set_transcript(&clip, &text)?;
+        }
+        Ok(clip)
+    }
+}
+
+/// Returns where the audio of the bundle at `bundle` is staged: a hidden file
+/// beside it, which holds nothing the bundle does not.
+fn staging_path(bundle: &Path) -> PathBuf {
+    let name = bundle
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "bundle".to_string());
+    bundle.with_file_name(format!(".{}.wav", name))
+}
+
+/// Appends an in-memory file to `archive`.
+fn append(archive: &mut tar::Builder<File>, name: &str, data: &[u8]) -> Result<(), anyhow::Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    );
+    header.set_cksum();
+    archive.append_data(&mut header, name, data)?;
+    Ok(())
+}
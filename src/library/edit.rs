@@ -0,0 +1,401 @@
+use super::{write_wav, Clip, ClipLibrary, ClipReader};
+use crate::crypto;
+use crate::decode::{AudioInfo, FrameSource};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Name of the edit decision list stored in a clip directory.
+const EDITS_FILE: &str = "edits.json";
+
+/// A single non-destructive edit. Positions are frames on the edited timeline
+/// as it stands when the edit is applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum EditOp {
+    /// Keep only the frames in `start..end`.
+    Trim { start: u64, end: u64 },
+    /// Remove the frames in `start..end`.
+    Cut { start: u64, end: u64 },
+    /// Fade in over the first `frames` frames of the result.
+    FadeIn { frames: u64 },
+    /// Fade out over the last `frames` frames of the result.
+    FadeOut { frames: u64 },
+    /// Append the whole of another clip.
+    Join { clip: String },
+}
+
+/// A contiguous range of frames taken from one clip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub clip: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Region {
+    fn len(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+/// The edit decision list of a clip, with undo/redo history.
+///
+/// Edits never touch the clip's audio files; they are replayed on top of the
+/// original whenever the clip is rendered.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EditList {
+    pub ops: Vec<EditOp>,
+    #[serde(default)]
+    pub redo: Vec<EditOp>,
+}
+
+impl EditList {
+    /// Appends an edit and clears the redo history.
+    pub fn push(&mut self, op: EditOp) {
+        self.ops.push(op);
+        self.redo.clear();
+    }
+
+    /// Removes the most recent edit.
+    ///
+    /// # Returns
+    /// * `Option<EditOp>` - The edit that was undone, if any.
+    pub fn undo(&mut self) -> Option<EditOp> {
+        let op = self.ops.pop()?;
+        self.redo.push(op.clone());
+        Some(op)
+    }
+
+    /// Re-applies the most recently undone edit.
+    ///
+    /// # Returns
+    /// * `Option<EditOp>` - The edit that was redone, if any.
+    pub fn redo(&mut self) -> Option<EditOp> {
+        let op = self.redo.pop()?;
+        self.ops.push(op.clone());
+        Some(op)
+    }
+
+    /// Returns `true` if there is no edit to undo or redo.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty() && self.redo.is_empty()
+    }
+
+    /// Returns the ids of the clips joined by these edits, including undone
+    /// joins that could still be redone.
+    pub fn joins(&self) -> impl Iterator<Item = &str> {
+        self.ops.iter().chain(&self.redo).filter_map(|op| match op {
+            EditOp::Join { clip } => Some(clip.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Converts every frame position and length with `rescale`, for when the
+    /// clip's audio is rewritten at another sample rate.
+    pub(super) fn rescale(&mut self, rescale: impl Fn(u64) -> u64) {
+        for op in self.ops.iter_mut().chain(self.redo.iter_mut()) {
+            match op {
+                EditOp::Trim { start, end } | EditOp::Cut { start, end } => {
+                    *start = rescale(*start);
+                    *end = rescale(*end);
+                }
+                EditOp::FadeIn { frames } | EditOp::FadeOut { frames } => {
+                    *frames = rescale(*frames)
+                }
+                EditOp::Join { .. } => {}
+            }
+        }
+    }
+
+    /// Returns the fade-in and fade-out lengths in frames; the latest of each wins.
+    pub fn fades(&self) -> (u64, u64) {
+        self.ops.iter().fold((0, 0), |(fade_in, fade_out), op| match op {
+            EditOp::FadeIn { frames } => (*frames, fade_out),
+            EditOp::FadeOut { frames } => (fade_in, *frames),
+            _ => (fade_in, fade_out),
+        })
+    }
+
+    /// Resolves the edits into the list of source regions that make up the result.
+    ///
+    /// # Arguments
+    /// * `clip` - The clip the edits belong to.
+    /// * `lookup` - Resolves the ids of joined clips.
+    pub fn regions(
+        &self,
+        clip: &Clip,
+        lookup: impl Fn(&str) -> Result<Clip, anyhow::Error>,
+    ) -> Result<Vec<Region>, anyhow::Error> {
+        let mut regions = vec![Region {
+            clip: clip.id().to_string(),
+            start: 0,
+            end: clip.meta.total_frames(),
+        }];
+        for op in &self.ops {
+            let len: u64 = regions.iter().map(Region::len).sum();
+            match op {
+                EditOp::Trim { start, end } => {
+                    EditList::check_range(*start, *end, len)?;
+                    regions = EditList::slice(&regions, *start, *end);
+                }
+                EditOp::Cut { start, end } => {
+                    EditList::check_range(*start, *end, len)?;
+                    let mut kept = EditList::slice(&regions, 0, *start);
+                    kept.extend(EditList::slice(&regions, *end, len));
+                    regions = kept;
+                }
+                EditOp::Join { clip: id } => {
+                    let other = lookup(id)?;
+                    if other.meta.sample_rate != clip.meta.sample_rate
+                        || other.meta.channels != clip.meta.channels
+                    {
+                        return Err(anyhow!(
+                            "clip '{}' has a different format and cannot be joined",
+                            id
+                        ));
+                    }
+                    regions.push(Region {
+                        clip: id.clone(),
+                        start: 0,
+                        end: other.meta.total_frames(),
+                    });
+                }
+                EditOp::FadeIn { .. } | EditOp::FadeOut { .. } => {}
+            }
+        }
+        Ok(regions)
+    }
+
+    fn check_range(start: u64, end: u64, len: u64) -> Result<(), anyhow::Error> {
+        if start > end || end > len {
+            return Err(anyhow!(
+                "edit range {}..{} is outside the clip (0..{})",
+                start,
+                end,
+                len
+            ));
+        }
+        Ok(())
+    }
+
+    fn slice(regions: &[Region], start: u64, end: u64) -> Vec<Region> {
+        let mut out = Vec::new();
+        let mut offset = 0;
+        for region in regions {
+            let (lo, hi) = (offset, offset + region.len());
+            offset = hi;
+            let from = start.max(lo);
+            let to = end.min(hi);
+            if from < to {
+                out.push(Region {
+                    clip: region.clip.clone(),
+                    start: region.start + (from - lo),
+                    end: region.start + (to - lo),
+                });
+            }
+        }
+        out
+    }
+}
+
+/// Plays a clip with its edit decision list applied.
+pub struct EditedReader<'a> {
+    library: &'a ClipLibrary,
+    info: AudioInfo,
+    regions: Vec<Region>,
+    fade_in: u64,
+    fade_out: u64,
+    index: usize,
+    current: Option<ClipReader>,
+    /// Offset of the current region on the edited timeline.
+    base: u64,
+    position: u64,
+}
+
+impl<'a> EditedReader<'a> {
+    fn open(library: &'a ClipLibrary, clip: &Clip) -> Result<EditedReader<'a>, anyhow::Error> {
+        let edits = library.edits(clip)?;
+        let regions = edits.regions(clip, |id| library.get(id))?;
+        let (fade_in, fade_out) = edits.fades();
+        let mut info = ClipReader::open(clip)?.info().clone();
+        info.total_frames = Some(regions.iter().map(Region::len).sum());
+        let mut reader = EditedReader {
+            library,
+            info,
+            regions,
+            fade_in,
+            fade_out,
+            index: 0,
+            current: None,
+            base: 0,
+            position: 0,
+        };
+        reader.enter_region(0, 0)?;
+        Ok(reader)
+    }
+
+    fn enter_region(&mut self, index: usize, offset: u64) -> Result<(), anyhow::Error> {
+        self.index = index;
+        self.base = self.regions[..index].iter().map(Region::len).sum();
+        self.current = match self.regions.get(index) {
+            Some(region) => {
+                let mut reader = ClipReader::open(&self.library.get(&region.clip)?)?;
+                reader.seek(region.start + offset)?;
+                Some(reader)
+            }
+            None => None,
+        };
+        self.position = self.base + offset;
+        Ok(())
+    }
+
+    fn gain(&self, frame: u64) -> f32 {
+        let total = self.info.total_frames.unwrap_or(0);
+        let mut gain = 1.0;
+        if frame < self.fade_in {
+            gain *= frame as f32 / self.fade_in as f32;
+        }
+        let from_end = total - frame;
+        if from_end <= self.fade_out {
+            gain *= (from_end - 1) as f32 / self.fade_out as f32;
+        }
+        gain
+    }
+}
+
+impl FrameSource for EditedReader<'_> {
+    fn info(&self) -> &AudioInfo {
+        &self.info
+    }
+
+    fn read_frames(&mut self, buf: &mut [f32]) -> Result<usize, anyhow::Error> {
+        let channels = self.info.channels as usize;
+        let wanted = buf.len() / channels;
+        let mut written = 0;
+        while written < wanted {
+            let Some(reader) = self.current.as_mut() else {
+                break;
+            };
+            let region_left = self.base + self.regions[self.index].len() - self.position;
+            if region_left == 0 {
+                self.enter_region(self.index + 1, 0)?;
+                continue;
+            }
+            let n = (region_left as usize).min(wanted - written);
+            let out = &mut buf[written * channels..(written + n) * channels];
+            let read = reader.read_frames(out)?;
+            if read == 0 {
+                return Err(anyhow!("clip '{}' ended early", self.regions[self.index].clip));
+            }
+            for (i, frame) in out[..read * channels].chunks_mut(channels).enumerate() {
+                let gain = self.gain(self.position + i as u64);
+                frame.iter_mut().for_each(|s| *s *= gain);
+            }
+            self.position += read as u64;
+            written += read;
+        }
+        Ok(written)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<u64, anyhow::Error> {
+        let frame = frame.min(self.info.total_frames.unwrap_or(0));
+        let mut base = 0;
+        for (index, region) in self.regions.iter().enumerate() {
+            if frame < base + region.len() {
+                self.enter_region(index, frame - base)?;
+                return Ok(frame);
+            }
+            base += region.len();
+        }
+        self.enter_region(self.regions.len(), 0)?;
+        self.position = frame;
+        Ok(frame)
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl ClipLibrary {
+    /// Loads the edit decision list of `clip`; a clip without edits has an empty list.
+    pub fn edits(&self, clip: &Clip) -> Result<EditList, anyhow::Error> {
+        let path = clip.dir.join(EDITS_FILE);
+        if !path.is_file() {
+            return Ok(EditList::default());
+        }
+        Ok(serde_json::from_slice(&crypto::read(path, self.key.as_ref())?)?)
+    }
+
+    /// Adds an edit to `clip` after checking that it applies cleanly.
+    pub fn apply_edit(&self, clip: &Clip, op: EditOp) -> Result<EditList, anyhow::Error> {
+        let mut edits = self.edits(clip)?;
+        edits.push(op);
+        edits.regions(clip, |id| self.get(id))?;
+        self.store_edits(clip, &edits)?;
+        Ok(edits)
+    }
+
+    /// Undoes the most recent edit of `clip`.
+    pub fn undo_edit(&self, clip: &Clip) -> Result<Option<EditOp>, anyhow::Error> {
+        let mut edits = self.edits(clip)?;
+        let Some(op) = edits.undo() else {
+            return Ok(None);
+        };
+        self.store_edits(clip, &edits)?;
+        Ok(Some(op))
+    }
+
+    /// Re-applies the most recently undone edit of `clip`.
+    pub fn redo_edit(&self, clip: &Clip) -> Result<Option<EditOp>, anyhow::Error> {
+        let mut edits = self.edits(clip)?;
+        let Some(op) = edits.redo() else {
+            return Ok(None);
+        };
+        self.store_edits(clip, &edits)?;
+        Ok(Some(op))
+    }
+
+    /// Returns the ids of the clips whose edits join `clip`.
+    pub fn joined_into(&self, clip: &Clip) -> Result<Vec<String>, anyhow::Error> {
+        let mut ids = Vec::new();
+        for other in self.list()? {
+            if self.edits(&other)?.joins().any(|id| id == clip.id()) {
+                ids.push(other.id().to_string());
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Returns the ids of every clip joined by the edits of another clip.
+    pub(super) fn joined_clips(&self) -> Result<HashSet<String>, anyhow::Error> {
+        let mut ids = HashSet::new();
+        for clip in self.list()? {
+            ids.extend(self.edits(&clip)?.joins().map(str::to_string));
+        }
+        Ok(ids)
+    }
+
+    /// Opens a reader that plays `clip` with its edits applied.
+    pub fn edited_reader<'a>(&'a self, clip: &Clip) -> Result<EditedReader<'a>, anyhow::Error> {
+        EditedReader::open(self, clip)
+    }
+
+    /// Renders `clip` with its edits applied into a WAV file at `dest`.
+    pub fn render(&self, clip: &Clip, dest: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        let mut reader = self.edited_reader(clip)?;
+        let spec = ClipReader::open(clip)?.spec();
+        write_wav(&mut reader, spec, dest)
+    }
+
+    pub(super) fn store_edits(&self, clip: &Clip, edits: &EditList) -> Result<(), anyhow::Error> {
+        let tmp = clip.dir.join(format!("{}.tmp", EDITS_FILE));
+        let json = serde_json::to_string_pretty(edits)?;
+        crypto::write(&tmp, json.as_bytes(), self.key.as_ref())?;
+        fs::rename(tmp, clip.dir.join(EDITS_FILE))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use super::markers::{cue_chunks, cue_markers};
+use super::{Clip, ClipMetadata, Marker};
+use crate::crypto;
+use crate::riff::{self, Bext, Chunk, IXml};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Timelike};
+use std::ops::Range;
+use std::path::Path;
+
+/// Application name written as the originator of every file.
+const ORIGINATOR: &str = "PikaPulse";
+
+/// Clip metadata recovered from the chunks of a WAV file.
+#[derive(Debug, Clone, Default)]
+pub(super) struct EmbeddedMetadata {
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    pub created: Option<DateTime<Local>>,
+    pub markers: Vec<Marker>,
+}
+
+/// Returns `true` for the chunks the library manages inside its WAV files.
+fn is_embedded(chunk: &Chunk) -> bool {
+    matches!(&chunk.id, b"bext" | b"iXML" | b"cue ") || chunk.list_type() == Some(b"adtl")
+}
+
+/// Builds the chunks describing the part of a clip stored in one file.
+///
+/// # Arguments
+/// * `meta` - The clip's metadata.
+/// * `range` - The frames of the clip the file holds; markers within it are included.
+fn embedded_chunks(meta: &ClipMetadata, range: Range<u64>) -> Vec<Chunk> {
+    let rate = meta.sample_rate as u64;
+    let since_midnight = meta.created.num_seconds_from_midnight() as u64 * rate
+        + meta.created.nanosecond() as u64 * rate / 1_000_000_000;
+    let bext = Bext {
+        description: meta.title.clone().unwrap_or_default(),
+        originator: ORIGINATOR.to_string(),
+        originator_reference: meta.id.clone(),
+        origination_date: meta.created.format("%Y-%m-%d").to_string(),
+        origination_time: meta.created.format("%H:%M:%S").to_string(),
+        time_reference: since_midnight + range.start,
+        coding_history: String::new(),
+    };
+
+    let mut chunks = vec![bext.to_chunk()];
+    if meta.title.is_some() || !meta.tags.is_empty() {
+        let ixml = IXml {
+            title: meta.title.clone(),
+            tags: meta.tags.clone(),
+        };
+        chunks.push(ixml.to_chunk());
+    }
+    chunks.extend(cue_chunks(&meta.markers, range));
+    chunks
+}
+
+/// Mirrors the metadata of `clip` into its segment files, so tools that only see
+/// the files get the title, tags, recording time and markers too.
+///
+/// Encrypted segments are skipped: other tools cannot read them anyway, and the
+/// metadata is still embedded when the clip is exported.
+pub(super) fn write_segment_chunks(clip: &Clip) -> Result<(), anyhow::Error> {
+    let count = clip.meta.segments.len();
+    let mut offset = 0;
+    for (i, (segment, path)) in clip
+        .meta
+        .segments
+        .iter()
+        .zip(clip.segment_paths())
+        .enumerate()
+    {
+        // A marker on a boundary belongs to the segment starting there, except
+        // at the very end of the clip.
+        let end = offset + segment.frames + (i + 1 == count) as u64;
+        if crypto::is_encrypted(&path)? {
+            offset += segment.frames;
+            continue;
+        }
+        riff::write_chunks(
+            &path,
+            is_embedded,
+            &embedded_chunks(&clip.meta, offset..end),
+        )?;
+        offset += segment.frames;
+    }
+    Ok(())
+}
+
+/// Writes the metadata of a whole clip into the single WAV file at `path`.
+pub(super) fn write_file_chunks(meta: &ClipMetadata, path: &Path) -> Result<(), anyhow::Error> {
+    let range = 0..meta.total_frames() + 1;
+    riff::write_chunks(path, is_embedded, &embedded_chunks(meta, range))
+}
+
+/// Reads the Broadcast WAV, iXML and cue metadata of a WAV file.
+///
+/// Missing or malformed chunks are skipped, so any WAV file can be read.
+pub(super) fn read_embedded(path: &Path) -> EmbeddedMetadata {
+    let ids = [*b"bext", *b"iXML", *b"cue ", *b"LIST"];
+    let chunks = riff::read_chunks(path, &ids).unwrap_or_default();
+    let bext = chunks
+        .iter()
+        .find(|c| &c.id == b"bext")
+        .and_then(|c| Bext::from_chunk(c).ok());
+    let ixml = chunks
+        .iter()
+        .find(|c| &c.id == b"iXML")
+        .map(IXml::from_chunk)
+        .unwrap_or_default();
+
+    // The spec allows any of a few separators in the date and time fields.
+    let created = bext.as_ref().and_then(|b| {
+        let date = b.origination_date.replace(['_', ':', ' ', '.'], "-");
+        let time = b.origination_time.replace(['_', '-', ' ', '.'], ":");
+        let naive =
+            NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M:%S")
+                .ok()?;
+        Local.from_local_datetime(&naive).single()
+    });
+    let description = bext.map(|b| b.description).filter(|d| !d.is_empty());
+
+    EmbeddedMetadata {
+        title: ixml.title.or(description),
+        tags: ixml.tags,
+        created,
+        markers: cue_markers(&chunks),
+    }
+}
@@ -0,0 +1,91 @@
+use super::ClipLibrary;
+use crate::crypto::{Key, KeySource};
+use anyhow::{anyhow, Context};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Name of the file in the library root describing how the key is derived.
+const ENCRYPTION_FILE: &str = "encryption.json";
+/// Known plaintext sealed with the key, used to recognize a wrong passphrase.
+const CHECK_PLAINTEXT: &[u8] = b"pika_pulse clip library";
+
+/// Key derivation parameters of an encrypted library. Contains no secrets.
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptionInfo {
+    kdf: String,
+    /// Hex encoded salt.
+    salt: String,
+    /// Hex encoded [`CHECK_PLAINTEXT`], sealed with the key.
+    check: String,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, anyhow::Error> {
+    if !hex.is_ascii() || hex.len() & 1 == 1 {
+        return Err(anyhow!("invalid hex string"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+impl ClipLibrary {
+    /// Turns on encrypted storage, with a key derived from a passphrase or key file.
+    ///
+    /// The first time a library is opened with encryption a random salt is stored
+    /// in its root; later opens must use the same passphrase or key file. Audio is
+    /// encrypted as it is recorded and the metadata, edit list and peak cache are
+    /// encrypted too, while reading, playback and export decrypt transparently.
+    /// Clips stored before encryption was turned on stay readable as they are.
+    pub fn with_encryption(mut self, source: &KeySource) -> Result<ClipLibrary, anyhow::Error> {
+        let path = self.root.join(ENCRYPTION_FILE);
+        let key = if path.is_file() {
+            let info: EncryptionInfo = serde_json::from_str(&fs::read_to_string(&path)?)
+                .with_context(|| format!("invalid encryption settings in {}", path.display()))?;
+            if info.kdf != "argon2id" {
+                return Err(anyhow!("unsupported key derivation '{}'", info.kdf));
+            }
+            let key = Key::derive(source, &from_hex(&info.salt)?)?;
+            match key.open(&from_hex(&info.check)?) {
+                Ok(check) if check == CHECK_PLAINTEXT => key,
+                _ => return Err(anyhow!("wrong passphrase or key file for this library")),
+            }
+        } else {
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            let key = Key::derive(source, &salt)?;
+            let info = EncryptionInfo {
+                kdf: "argon2id".to_string(),
+                salt: to_hex(&salt),
+                check: to_hex(&key.seal(CHECK_PLAINTEXT)?),
+            };
+            fs::write(&path, serde_json::to_string_pretty(&info)?)?;
+            key
+        };
+        self.key = Some(key);
+        Ok(self)
+    }
+
+    /// Returns `true` if new clips are stored encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Fails if the library is encrypted but was opened without its key, so no
+    /// plaintext clip is written into it by mistake.
+    pub(super) fn check_key(&self) -> Result<(), anyhow::Error> {
+        if self.key.is_none() && self.root.join(ENCRYPTION_FILE).is_file() {
+            return Err(anyhow!(
+                "{} is encrypted; open it with its passphrase or key file",
+                self.root.display()
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use super::{Clip, ClipLibrary, Undecodable};
+use anyhow::anyhow;
+use chrono::Local;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Directory inside the inbox that rejected files are moved into.
+const REJECTED_DIR: &str = "rejected";
+/// Log of rejected files and the reasons, kept in the rejected directory.
+const REJECTED_LOG: &str = "rejected.log";
+
+/// A file moved from the inbox into the library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ingested {
+    pub file: String,
+    pub clip: String,
+}
+
+/// A file that could not be ingested and was moved aside.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected {
+    pub file: String,
+    pub reason: String,
+}
+
+/// A file that could not be ingested for a reason other than its contents, such
+/// as a full disk, and was left in the inbox to try again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failed {
+    pub file: String,
+    pub reason: String,
+}
+
+/// The outcome of one pass over the inbox.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IngestReport {
+    pub ingested: Vec<Ingested>,
+    pub rejected: Vec<Rejected>,
+    pub failed: Vec<Failed>,
+    /// Files still being written, left for a later pass.
+    pub pending: usize,
+}
+
+/// Size and modification time of a file when it was last seen changing.
+struct Seen {
+    len: u64,
+    modified: Option<SystemTime>,
+    since: Instant,
+}
+
+/// Watches an inbox directory and moves the audio files dropped into it into a
+/// [`ClipLibrary`].
+///
+/// The inbox is polled rather than watched for events, which works the same on
+/// every filesystem, including removable media. A file is only picked up once
+/// its size and modification time have stayed the same for the settle time, so
+/// files still being copied are left alone. Each file is decoded and validated
+/// by [`ClipLibrary::import`], which stores it as a new clip titled from its
+/// tags or file name. The original is removed once the clip is stored; files
+/// whose audio cannot be decoded are moved into the inbox's `rejected`
+/// directory and the reason is appended to `rejected/rejected.log`. Any other
+/// failure, such as a full disk or a missing library key, leaves the file in
+/// place to try again and is reported as failed.
+pub struct InboxWatcher {
+    inbox: PathBuf,
+    settle: Duration,
+    seen: HashMap<PathBuf, Seen>,
+}
+
+impl InboxWatcher {
+    /// Creates a watcher for `inbox`, creating the directory if it does not exist.
+    pub fn new(inbox: impl AsRef<Path>) -> Result<InboxWatcher, anyhow::Error> {
+        let inbox = inbox.as_ref().to_path_buf();
+        fs::create_dir_all(&inbox)?;
+        Ok(InboxWatcher {
+            inbox,
+            settle: Duration::from_secs(2),
+            seen: HashMap::new(),
+        })
+    }
+
+    /// Sets how long a file must stay unchanged before it is ingested. Defaults to two seconds.
+    pub fn with_settle_time(mut self, settle: Duration) -> InboxWatcher {
+        self.settle = settle;
+        self
+    }
+
+    /// Returns the inbox directory.
+    pub fn inbox(&self) -> &Path {
+        &self.inbox
+    }
+
+    /// Scans the inbox once and ingests every file that has finished being written.
+    ///
+    /// Hidden files and directories are ignored. A file that fails for a reason
+    /// other than its contents is left in the inbox for a later pass, and the
+    /// pass goes on to the next. Only failing to read the inbox is an error.
+    ///
+    /// # Returns
+    /// * `IngestReport` - The files ingested, rejected and failed in this pass.
+    pub fn poll(&mut self, library: &ClipLibrary) -> Result<IngestReport, anyhow::Error> {
+        let mut report = IngestReport::default();
+        let mut present = Vec::new();
+        let mut paths: Vec<_> = fs::read_dir(&self.inbox)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        paths.sort();
+
+        for path in paths {
+            let hidden = path
+                .file_name()
+                .is_none_or(|name| name.to_string_lossy().starts_with('.'));
+            let meta = match fs::metadata(&path) {
+                Ok(meta) if meta.is_file() && !hidden => meta,
+                _ => continue,
+            };
+            present.push(path.clone());
+            if !self.is_settled(&path, meta.len(), meta.modified().ok()) {
+                report.pending += 1;
+                continue;
+            }
+            self.seen.remove(&path);
+
+            let file = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let failure = match InboxWatcher::ingest(library, &path) {
+                Ok(clip) => match fs::remove_file(&path) {
+                    Ok(()) => {
+                        report.ingested.push(Ingested {
+                            file,
+                            clip: clip.id().to_string(),
+                        });
+                        continue;
+                    }
+                    // Keep a single copy: drop the clip and try the file again.
+                    Err(e) => {
+                        let _ = library.remove(&clip);
+                        anyhow::Error::from(e).context("the file could not be removed")
+                    }
+                },
+                Err(e) if e.downcast_ref::<Undecodable>().is_none() => e,
+                Err(e) => {
+                    let reason = format!("{:#}", e);
+                    match self.reject(&path, &file, &reason) {
+                        Ok(()) => {
+                            report.rejected.push(Rejected { file, reason });
+                            continue;
+                        }
+                        Err(e) => e.context(format!("{}, and it could not be moved aside", reason)),
+                    }
+                }
+            };
+            report.failed.push(Failed {
+                file,
+                reason: format!("{:#}", failure),
+            });
+        }
+        self.seen.retain(|path, _| present.contains(path));
+        Ok(report)
+    }
+
+    /// Polls the inbox every `interval` until `stop` is set.
+    ///
+    /// Failures never end the loop: a pass that cannot read the inbox at all
+    /// is reported with the inbox itself as the failed file, and tried again
+    /// after `interval`.
+    ///
+    /// # Arguments
+    /// * `library` - The library new clips are stored in.
+    /// * `interval` - Time between passes over the inbox.
+    /// * `stop` - Set to end the loop after the current pass.
+    /// * `on_report` - Called after every pass that ingested, rejected or
+    ///   failed to ingest a file.
+    pub fn run(
+        &mut self,
+        library: &ClipLibrary,
+        interval: Duration,
+        stop: &AtomicBool,
+        mut on_report: impl FnMut(&IngestReport),
+    ) {
+        while !stop.load(Ordering::Relaxed) {
+            let report = self.poll(library).unwrap_or_else(|e| IngestReport {
+                failed: vec![Failed {
+                    file: self.inbox.display().to_string(),
+                    reason: format!("{:#}", e),
+                }],
+                ..Default::default()
+            });
+            if !report.ingested.is_empty()
+                || !report.rejected.is_empty()
+                || !report.failed.is_empty()
+            {
+                on_report(&report);
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    /// Returns `true` once the file has stayed unchanged for the settle time.
+    fn is_settled(&mut self, path: &Path, len: u64, modified: Option<SystemTime>) -> bool {
+        let now = Instant::now();
+        let seen = self.seen.entry(path.to_path_buf()).or_insert(Seen {
+            len,
+            modified,
+            since: now,
+        });
+        if seen.len != len || seen.modified != modified {
+            *seen = Seen {
+                len,
+                modified,
+                since: now,
+            };
+        }
+        // A file seen for the first time in this pass is never settled.
+        seen.since < now && now.duration_since(seen.since) >= self.settle
+    }
+
+    /// Imports one file, removing the clip again if it turns out to hold no audio.
+    fn ingest(library: &ClipLibrary, path: &Path) -> Result<Clip, anyhow::Error> {
+        let clip = library.import(path)?;
+        if clip.meta.total_frames() == 0 {
+            library.remove(&clip)?;
+            return Err(anyhow!("the file contains no audio").context(Undecodable));
+        }
+        Ok(clip)
+    }
+
+    /// Moves a file into the rejected directory and logs why.
+    fn reject(&self, path: &Path, file: &str, reason: &str) -> Result<(), anyhow::Error> {
+        let dir = self.inbox.join(REJECTED_DIR);
+        fs::create_dir_all(&dir)?;
+        let mut dest = dir.join(file);
+        let mut n = 1;
+        while dest.exists() {
+            dest = dir.join(format!("{}-{}", n, file));
+            n += 1;
+        }
+        fs::rename(path, &dest)?;
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(REJECTED_LOG))?;
+        writeln!(
+            log,
+            "{}\t{}\t{}",
+            Local::now().to_rfc3339(),
+            file,
+            reason.replace(['\n', '\t'], " ")
+        )?;
+        Ok(())
+    }
+}
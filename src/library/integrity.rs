@@ -0,0 +1,224 @@
+use super::{Clip, ClipLibrary, METADATA_FILE};
+use crate::crypto::{FileReader, Key};
+use crate::recorder::segment::SegmentInfo;
+use crate::riff;
+use hound::WavReader;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Directory inside the library root that damaged clips are moved into.
+const QUARANTINE_DIR: &str = ".quarantine";
+
+/// What is wrong with a clip or one of its files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Damage {
+    /// `clip.json` could not be read or parsed.
+    InvalidMetadata(String),
+    /// A segment listed in the metadata does not exist.
+    MissingFile,
+    /// The WAV header is unreadable or does not match the clip's format.
+    InvalidHeader(String),
+    /// The file could not be read, or its encrypted data failed authentication.
+    Unreadable(String),
+    /// The file holds fewer frames than the metadata records.
+    Truncated {
+        expected_frames: u64,
+        found_frames: u64,
+    },
+    /// The audio data no longer matches the checksum taken when the clip was finalized.
+    ChecksumMismatch,
+}
+
+/// A damaged clip, and the file within it if the damage is limited to one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub clip: String,
+    pub file: Option<String>,
+    pub damage: Damage,
+}
+
+/// The outcome of verifying the library.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub clips_checked: usize,
+    /// Clips whose structure checked out but that have no checksums to compare against.
+    pub unverified: Vec<String>,
+    pub problems: Vec<Problem>,
+    /// Ids of the clips moved into the quarantine directory.
+    pub quarantined: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if no damage was found.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Computes the hex SHA-256 of the audio data of a WAV file.
+///
+/// Only the `data` chunk is hashed, so rewriting the metadata chunks stored
+/// alongside the audio does not change the checksum. Encrypted files are hashed
+/// by their plaintext.
+fn audio_checksum(path: &Path, key: Option<&Key>) -> Result<String, anyhow::Error> {
+    let mut file = FileReader::open(path, key)?;
+    let range = riff::data_range(&mut file)?;
+    file.seek(SeekFrom::Start(range.start))?;
+    let mut hasher = Sha256::new();
+    let mut left = range.end - range.start;
+    let mut buf = vec![0u8; 64 * 1024];
+    while left > 0 {
+        let want = (left as usize).min(buf.len());
+        let read = file.read(&mut buf[..want])?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        left -= read as u64;
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Records the checksum of every segment of `clip` in its metadata.
+pub(super) fn checksum_segments(clip: &mut Clip) -> Result<(), anyhow::Error> {
+    let paths = clip.segment_paths();
+    for (segment, path) in clip.meta.segments.iter_mut().zip(paths) {
+        segment.sha256 = Some(audio_checksum(&path, clip.key.as_ref())?);
+    }
+    Ok(())
+}
+
+/// Checks one segment file against its metadata.
+fn check_segment(clip: &Clip, segment: &SegmentInfo, path: &Path) -> Option<Damage> {
+    let meta = &clip.meta;
+    let key = clip.key.as_ref();
+    if !path.is_file() {
+        return Some(Damage::MissingFile);
+    }
+    let mut file = match FileReader::open(path, key) {
+        Ok(file) => file,
+        Err(e) => return Some(Damage::Unreadable(format!("{:#}", e))),
+    };
+    let spec = match WavReader::new(BufReader::new(&mut file)) {
+        Ok(reader) => reader.spec(),
+        Err(hound::Error::IoError(e)) => return Some(Damage::Unreadable(e.to_string())),
+        Err(e) => return Some(Damage::InvalidHeader(e.to_string())),
+    };
+    if spec.sample_rate != meta.sample_rate || spec.channels != meta.channels {
+        return Some(Damage::InvalidHeader(format!(
+            "{} Hz, {} channels does not match the clip's {} Hz, {} channels",
+            spec.sample_rate, spec.channels, meta.sample_rate, meta.channels
+        )));
+    }
+
+    // Frames actually present: bounded by both the header and the file length.
+    let range = match riff::data_range(&mut file) {
+        Ok(range) => range,
+        Err(e) => return Some(Damage::InvalidHeader(e.to_string())),
+    };
+    let len = file.plaintext_len().unwrap_or(0);
+    let bytes_per_frame = spec.channels as u64 * (spec.bits_per_sample as u64 / 8);
+    let found_frames = (range.end.min(len).saturating_sub(range.start)) / bytes_per_frame;
+    if found_frames < segment.frames {
+        return Some(Damage::Truncated {
+            expected_frames: segment.frames,
+            found_frames,
+        });
+    }
+
+    let expected = segment.sha256.as_ref()?;
+    match audio_checksum(path, key) {
+        Ok(actual) if &actual == expected => None,
+        Ok(_) => Some(Damage::ChecksumMismatch),
+        Err(e) => Some(Damage::Unreadable(format!("{:#}", e))),
+    }
+}
+
+impl ClipLibrary {
+    /// Re-reads every clip in the library and checks it for damage.
+    ///
+    /// Each segment's header is validated against the clip's metadata, its length
+    /// against the recorded frame count, and its audio against the checksum taken
+    /// when the clip was finalized. Clips that are still being recorded are skipped.
+    ///
+    /// Fails before checking anything if the library is encrypted and was opened
+    /// without its key, rather than reporting every clip as damaged. The key
+    /// itself is checked when the library is opened, so metadata that cannot be
+    /// decrypted is reported as damaged like any other.
+    ///
+    /// # Arguments
+    /// * `quarantine` - Move damaged clips into the `.quarantine` directory of the
+    ///   library, out of the way of playback and retention.
+    ///
+    /// # Returns
+    /// * `VerifyReport` - The clips checked and the damage found.
+    pub fn verify(&self, quarantine: bool) -> Result<VerifyReport, anyhow::Error> {
+        // Without the key every clip would look damaged.
+        self.check_key()?;
+        let mut report = VerifyReport::default();
+        let mut dirs: Vec<_> = fs::read_dir(&self.root)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        dirs.sort();
+
+        for dir in dirs {
+            if !dir.join(METADATA_FILE).is_file() {
+                continue;
+            }
+            let id = dir
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let damage = match self.load(&dir) {
+                Ok(clip) if !clip.is_complete() => continue,
+                Ok(clip) => {
+                    if clip.meta.segments.iter().any(|s| s.sha256.is_none()) {
+                        report.unverified.push(id.clone());
+                    }
+                    let paths = clip.segment_paths();
+                    clip.meta
+                        .segments
+                        .iter()
+                        .zip(paths)
+                        .filter_map(|(segment, path)| {
+                            check_segment(&clip, segment, &path)
+                                .map(|damage| (Some(segment.file.clone()), damage))
+                        })
+                        .collect()
+                }
+                Err(e) => vec![(None, Damage::InvalidMetadata(format!("{:#}", e)))],
+            };
+            report.clips_checked += 1;
+            if damage.is_empty() {
+                continue;
+            }
+            report.unverified.retain(|u| u != &id);
+            for (file, damage) in damage {
+                report.problems.push(Problem {
+                    clip: id.clone(),
+                    file,
+                    damage,
+                });
+            }
+            if quarantine {
+                let target = self.root.join(QUARANTINE_DIR);
+                fs::create_dir_all(&target)?;
+                let mut dest = target.join(&id);
+                let mut n = 1;
+                while dest.exists() {
+                    dest = target.join(format!("{}-{}", id, n));
+                    n += 1;
+                }
+                fs::rename(&dir, dest)?;
+                report.quarantined.push(id);
+            }
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,168 @@
+use super::markers::Marker;
+use super::{Clip, ClipLibrary};
+use crate::decode::FrameSource;
+use crate::dsp::{SilenceConfig, SilenceDetector};
+use anyhow::{anyhow, Context};
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+/// One label of an Audacity label track. Times are in seconds.
+///
+/// A point label has `start == end`; a region label spans `start..end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+impl Label {
+    /// Parses the text of an Audacity label file.
+    ///
+    /// Each line holds a start time, an end time and the label text separated by
+    /// tabs. The frequency lines Audacity writes for spectral selections, which
+    /// start with a backslash, and blank lines are skipped.
+    pub fn parse_all(text: &str) -> Result<Vec<Label>, anyhow::Error> {
+        let mut labels = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('\\') {
+                continue;
+            }
+            let mut fields = line.splitn(3, '\t');
+            let mut time = |name: &str| -> Result<f64, anyhow::Error> {
+                let field = fields.next().unwrap_or("").trim();
+                let secs: f64 = field.parse().with_context(|| {
+                    format!("line {}: invalid {} time '{}'", n + 1, name, field)
+                })?;
+                if !secs.is_finite() || secs < 0.0 {
+                    return Err(anyhow!("line {}: invalid {} time '{}'", n + 1, name, field));
+                }
+                Ok(secs)
+            };
+            let start = time("start")?;
+            let end = time("end")?;
+            labels.push(Label {
+                start: start.min(end),
+                end: start.max(end),
+                text: fields.next().unwrap_or("").to_string(),
+            });
+        }
+        Ok(labels)
+    }
+
+    /// Formats labels as the text of an Audacity label file.
+    pub fn format_all(labels: &[Label]) -> String {
+        labels
+            .iter()
+            .map(|l| {
+                let text = l.text.replace(['\t', '\n', '\r'], " ");
+                format!("{:.6}\t{:.6}\t{}\n", l.start, l.end, text)
+            })
+            .collect()
+    }
+}
+
+impl ClipLibrary {
+    /// Writes the markers of `clip` to `dest` as an Audacity label file.
+    ///
+    /// Point markers become point labels and region markers region labels.
+    pub fn export_labels(&self, clip: &Clip, dest: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        let rate = clip.meta.sample_rate as f64;
+        let labels: Vec<Label> = clip
+            .meta
+            .markers
+            .iter()
+            .map(|m| Label {
+                start: m.position as f64 / rate,
+                end: m.end.unwrap_or(m.position) as f64 / rate,
+                text: m.label.clone(),
+            })
+            .collect();
+        fs::write(dest, Label::format_all(&labels))?;
+        Ok(())
+    }
+
+    /// Writes the speech regions of `clip` to `dest` as an Audacity label file,
+    /// one region label per stretch of audio detected as non-silent.
+    ///
+    /// # Arguments
+    /// * `clip` - The clip to analyze.
+    /// * `dest` - Path of the label file to create.
+    /// * `config` - The silence thresholds, as used for trimming recordings.
+    pub fn export_speech_labels(
+        &self,
+        clip: &Clip,
+        dest: impl AsRef<Path>,
+        config: &SilenceConfig,
+    ) -> Result<(), anyhow::Error> {
+        let rate = clip.meta.sample_rate as f64;
+        let labels: Vec<Label> = self
+            .speech_regions(clip, config)?
+            .into_iter()
+            .map(|range| Label {
+                start: range.start as f64 / rate,
+                end: range.end as f64 / rate,
+                text: "speech".to_string(),
+            })
+            .collect();
+        fs::write(dest, Label::format_all(&labels))?;
+        Ok(())
+    }
+
+    /// Finds the stretches of `clip` that are not silent.
+    ///
+    /// # Returns
+    /// * `Vec<Range<u64>>` - The frames of each stretch, in order and padded as
+    ///   configured.
+    pub fn speech_regions(
+        &self,
+        clip: &Clip,
+        config: &SilenceConfig,
+    ) -> Result<Vec<Range<u64>>, anyhow::Error> {
+        let channels = clip.meta.channels;
+        let mut detector = SilenceDetector::new(config, clip.meta.sample_rate, channels);
+        let mut reader = self.reader(clip)?;
+        let mut buf = vec![0.0; 4096 * channels as usize];
+        loop {
+            let frames = reader.read_frames(&mut buf)?;
+            if frames == 0 {
+                break;
+            }
+            detector.push(&buf[..frames * channels as usize]);
+        }
+        Ok(detector.finish_regions())
+    }
+
+    /// Replaces the markers of `clip` with the labels of an Audacity label file
+    /// and saves it.
+    ///
+    /// Point labels become point markers and region labels region markers.
+    /// Labels past the end of the clip are clamped to it.
+    ///
+    /// # Returns
+    /// * `usize` - The number of markers imported.
+    pub fn import_labels(
+        &self,
+        clip: &mut Clip,
+        path: impl AsRef<Path>,
+    ) -> Result<usize, anyhow::Error> {
+        let path = path.as_ref();
+        let labels = Label::parse_all(&fs::read_to_string(path)?)
+            .with_context(|| format!("invalid label file {}", path.display()))?;
+        let rate = clip.meta.sample_rate as f64;
+        let total = clip.meta.total_frames();
+        let frame = |secs: f64| ((secs * rate).round() as u64).min(total);
+
+        let mut markers = Vec::new();
+        for label in &labels {
+            let (start, end) = (frame(label.start), frame(label.end));
+            let end = (end > start).then_some(end);
+            Marker::insert(&mut markers, start, end, label.text.clone());
+        }
+        clip.meta.markers = markers;
+        self.save(clip)?;
+        Ok(labels.len())
+    }
+}
@@ -0,0 +1,159 @@
+use super::{Clip, ClipLibrary, ClipMetadata};
+use crate::riff::{decode_cue_points, encode_cue_points, Chunk, CuePoint};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// A labelled position or region inside a clip.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Marker {
+    pub id: u32,
+    /// Position in frames from the start of the clip.
+    pub position: u64,
+    /// End of the region starting at `position`, for markers that span a region.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<u64>,
+    pub label: String,
+}
+
+impl ClipMetadata {
+    /// Returns the first marker after `position`, if any.
+    pub fn next_marker(&self, position: u64) -> Option<&Marker> {
+        self.markers.iter().find(|m| m.position > position)
+    }
+
+    /// Returns the last marker before `position`, if any.
+    pub fn previous_marker(&self, position: u64) -> Option<&Marker> {
+        self.markers.iter().rev().find(|m| m.position < position)
+    }
+}
+
+impl Marker {
+    /// Inserts a marker keeping `markers` sorted by position, and returns its id.
+    pub(crate) fn insert(
+        markers: &mut Vec<Marker>,
+        position: u64,
+        end: Option<u64>,
+        label: String,
+    ) -> u32 {
+        let id = markers.iter().map(|m| m.id).max().unwrap_or(0) + 1;
+        let index = markers.partition_point(|m| m.position <= position);
+        markers.insert(
+            index,
+            Marker {
+                id,
+                position,
+                end,
+                label,
+            },
+        );
+        id
+    }
+}
+
+/// Encodes the markers within `range` as `cue ` and `LIST adtl` chunks, with
+/// positions relative to the start of `range`.
+pub(super) fn cue_chunks(markers: &[Marker], range: Range<u64>) -> Vec<Chunk> {
+    let points: Vec<CuePoint> = markers
+        .iter()
+        .filter(|m| range.contains(&m.position))
+        .map(|m| CuePoint {
+            id: m.id,
+            position: (m.position - range.start) as u32,
+            // A region is cut short at the end of the file holding its start.
+            length: m.end.map_or(0, |end| (end.min(range.end) - m.position) as u32),
+            label: m.label.clone(),
+        })
+        .collect();
+    if points.is_empty() {
+        return Vec::new();
+    }
+    encode_cue_points(&points)
+}
+
+/// Decodes the cue points in `chunks` as markers.
+pub(super) fn cue_markers(chunks: &[Chunk]) -> Vec<Marker> {
+    let mut markers = Vec::new();
+    for point in decode_cue_points(chunks) {
+        let position = point.position as u64;
+        let end = (point.length > 0).then(|| position + point.length as u64);
+        Marker::insert(&mut markers, position, end, point.label);
+    }
+    markers
+}
+
+impl ClipLibrary {
+    /// Adds a marker to `clip` and saves it.
+    ///
+    /// # Arguments
+    /// * `clip` - The clip to mark.
+    /// * `position` - The marked frame, at most the clip's length.
+    /// * `label` - A short description of the marked spot.
+    ///
+    /// # Returns
+    /// * `u32` - The id of the new marker.
+    pub fn add_marker(
+        &self,
+        clip: &mut Clip,
+        position: u64,
+        label: impl Into<String>,
+    ) -> Result<u32, anyhow::Error> {
+        self.insert_marker(clip, position, None, label.into())
+    }
+
+    /// Adds a marker spanning a region of `clip` and saves it.
+    ///
+    /// # Arguments
+    /// * `clip` - The clip to mark.
+    /// * `start` - The first frame of the region.
+    /// * `end` - The frame after the region, at most the clip's length.
+    /// * `label` - A short description of the region.
+    ///
+    /// # Returns
+    /// * `u32` - The id of the new marker.
+    pub fn add_region(
+        &self,
+        clip: &mut Clip,
+        start: u64,
+        end: u64,
+        label: impl Into<String>,
+    ) -> Result<u32, anyhow::Error> {
+        if end < start {
+            return Err(anyhow!("region end {} is before its start {}", end, start));
+        }
+        self.insert_marker(clip, start, Some(end), label.into())
+    }
+
+    /// Removes the marker with the given id from `clip` and saves it.
+    pub fn remove_marker(&self, clip: &mut Clip, id: u32) -> Result<(), anyhow::Error> {
+        let index = clip
+            .meta
+            .markers
+            .iter()
+            .position(|m| m.id == id)
+            .ok_or_else(|| anyhow!("clip '{}' has no marker {}", clip.id(), id))?;
+        clip.meta.markers.remove(index);
+        self.save(clip)
+    }
+
+    fn insert_marker(
+        &self,
+        clip: &mut Clip,
+        position: u64,
+        end: Option<u64>,
+        label: String,
+    ) -> Result<u32, anyhow::Error> {
+        let total = clip.meta.total_frames();
+        let last = end.unwrap_or(position);
+        if last > total {
+            return Err(anyhow!(
+                "marker at frame {} is past the end of the clip ({} frames)",
+                last,
+                total
+            ));
+        }
+        let id = Marker::insert(&mut clip.meta.markers, position, end, label);
+        self.save(clip)?;
+        Ok(id)
+    }
+}
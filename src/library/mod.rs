@@ -0,0 +1,494 @@
+mod batch;
+mod bundle;
+mod edit;
+mod embed;
+mod encryption;
+mod inbox;
+mod integrity;
+mod labels;
+mod markers;
+mod peaks;
+mod postprocess;
+mod query;
+mod reader;
+mod retention;
+
+pub use batch::{Analysis, BatchProgress, BatchReport, BatchRunner, BatchStep, ClipOutcome};
+pub use bundle::BundleAudio;
+pub use edit::{EditList, EditOp, EditedReader, Region};
+pub use inbox::{Failed, InboxWatcher, IngestReport, Ingested, Rejected};
+pub use integrity::{Damage, Problem, VerifyReport};
+pub use labels::Label;
+pub use markers::Marker;
+pub use peaks::{PeakBin, PeakCache};
+pub use postprocess::{Normalization, NormalizationRecord, PostProcess, SilenceTrim};
+pub use query::ClipQuery;
+pub use reader::ClipReader;
+pub use retention::{Deletion, DeletionReason, RetentionPolicy, RetentionReport};
+
+use crate::crypto::{self, Key};
+use crate::decode::{is_io_error, AudioFormat, Decoder, FrameSource};
+use crate::recorder::segment::{RolloverPolicy, SegmentInfo, SegmentWriter};
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Local};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the metadata sidecar stored in every clip directory.
+const METADATA_FILE: &str = "clip.json";
+
+/// Metadata stored alongside the audio of a clip.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClipMetadata {
+    pub id: String,
+    pub title: Option<String>,
+    pub created: DateTime<Local>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub segments: Vec<SegmentInfo>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub starred: bool,
+    #[serde(default)]
+    pub silence_trim: Option<SilenceTrim>,
+    #[serde(default)]
+    pub normalization: Option<NormalizationRecord>,
+    #[serde(default)]
+    pub markers: Vec<Marker>,
+}
+
+impl ClipMetadata {
+    /// Returns the total number of frames across all segments.
+    pub fn total_frames(&self) -> u64 {
+        self.segments.iter().map(|s| s.frames).sum()
+    }
+
+    /// Returns the length of the clip in seconds.
+    pub fn duration_secs(&self) -> f64 {
+        self.total_frames() as f64 / self.sample_rate as f64
+    }
+}
+
+/// A single logical recording in the library, backed by one or more segment files.
+#[derive(Debug, Clone)]
+pub struct Clip {
+    dir: PathBuf,
+    meta: ClipMetadata,
+    /// Key of an encrypted library, used to read and write the clip's files.
+    key: Option<Key>,
+}
+
+impl Clip {
+    /// Returns the clip's unique identifier.
+    pub fn id(&self) -> &str {
+        &self.meta.id
+    }
+
+    /// Returns the clip's metadata.
+    pub fn metadata(&self) -> &ClipMetadata {
+        &self.meta
+    }
+
+    /// Returns the clip's metadata for editing. Use [`ClipLibrary::save`] to persist changes.
+    pub fn metadata_mut(&mut self) -> &mut ClipMetadata {
+        &mut self.meta
+    }
+
+    /// Returns the directory holding the clip's files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the paths of the clip's segment files in playback order.
+    pub fn segment_paths(&self) -> Vec<PathBuf> {
+        self.meta
+            .segments
+            .iter()
+            .map(|s| self.dir.join(&s.file))
+            .collect()
+    }
+
+    /// Returns `true` if the recording was finalized and has audio to play.
+    pub fn is_complete(&self) -> bool {
+        !self.meta.segments.is_empty()
+    }
+
+    /// Returns the combined size in bytes of every file belonging to the clip.
+    pub fn size_bytes(&self) -> Result<u64, anyhow::Error> {
+        let mut total = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let meta = entry?.metadata()?;
+            if meta.is_file() {
+                total += meta.len();
+            }
+        }
+        Ok(total)
+    }
+}
+
+/// Context marking an import that failed because the file's audio could not be
+/// decoded, rather than because of the disk or the library.
+#[derive(Debug)]
+struct Undecodable;
+
+impl fmt::Display for Undecodable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the audio could not be decoded")
+    }
+}
+
+/// Marks a decoding error as [`Undecodable`], unless reading the file failed.
+fn undecodable(e: anyhow::Error) -> anyhow::Error {
+    if is_io_error(&e) {
+        e
+    } else {
+        e.context(Undecodable)
+    }
+}
+
+/// A recording stored by [`ClipLibrary::finalize_recording`].
+#[derive(Debug)]
+pub struct FinishedRecording {
+    /// The stored clip, post-processed unless that failed.
+    pub clip: Clip,
+    /// Why post-processing or building the peak cache failed. The clip is
+    /// kept either way, as recorded if post-processing did not finish.
+    pub post_process_error: Option<anyhow::Error>,
+    /// The clips the retention policy deleted to make room, if it deletes
+    /// anything.
+    pub retention: Option<RetentionReport>,
+    /// Why applying the retention policy failed.
+    pub retention_error: Option<anyhow::Error>,
+}
+
+/// A directory of clips, each stored in its own sub-directory.
+///
+/// A clip directory contains a `clip.json` metadata file and one or more
+/// `segment-NNN.wav` files which together form a single logical recording.
+/// Post-processing writes the rewritten audio as `segment-NNN.R.wav`, where
+/// `R` counts the rewrites.
+/// In an encrypted library these files are encrypted on disk; see
+/// [`ClipLibrary::with_encryption`].
+pub struct ClipLibrary {
+    root: PathBuf,
+    retention: RetentionPolicy,
+    post_process: PostProcess,
+    key: Option<Key>,
+}
+
+impl ClipLibrary {
+    /// Opens the library at `root`, creating the directory if it does not exist.
+    pub fn open(root: impl AsRef<Path>) -> Result<ClipLibrary, anyhow::Error> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(&root)?;
+        Ok(ClipLibrary {
+            root,
+            retention: RetentionPolicy::default(),
+            post_process: PostProcess::default(),
+            key: None,
+        })
+    }
+
+    /// Sets the quota and retention policy applied by this library.
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> ClipLibrary {
+        self.retention = retention;
+        self
+    }
+
+    /// Returns the library's quota and retention policy.
+    pub fn retention(&self) -> &RetentionPolicy {
+        &self.retention
+    }
+
+    /// Returns the root directory of the library.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Lists every clip in the library, oldest first.
+    pub fn list(&self) -> Result<Vec<Clip>, anyhow::Error> {
+        let mut clips = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let dir = entry?.path();
+            if dir.join(METADATA_FILE).is_file() {
+                clips.push(self.load(&dir)?);
+            }
+        }
+        clips.sort_by_key(|c| c.meta.created);
+        Ok(clips)
+    }
+
+    /// Looks up a clip by id.
+    pub fn get(&self, id: &str) -> Result<Clip, anyhow::Error> {
+        let dir = self.root.join(id);
+        if !dir.join(METADATA_FILE).is_file() {
+            return Err(anyhow!("no clip with id '{}'", id));
+        }
+        self.load(&dir)
+    }
+
+    /// Persists changes made through [`Clip::metadata_mut`].
+    ///
+    /// The title, tags, recording time and markers are also written into the
+    /// segment files as Broadcast WAV, iXML and cue chunks.
+    pub fn save(&self, clip: &Clip) -> Result<(), anyhow::Error> {
+        self.store(&clip.dir, &clip.meta)?;
+        embed::write_segment_chunks(clip)
+    }
+
+    /// Removes a clip and all of its files.
+    ///
+    /// A clip joined into another clip by its edits is refused, since that
+    /// clip could no longer be rendered; undo or remove the join first.
+    pub fn delete(&self, clip: &Clip) -> Result<(), anyhow::Error> {
+        let joined = self.joined_into(clip)?;
+        if !joined.is_empty() {
+            return Err(anyhow!(
+                "clip '{}' is joined into {} and cannot be deleted",
+                clip.id(),
+                joined.join(", ")
+            ));
+        }
+        self.remove(clip)
+    }
+
+    /// Removes a clip and all of its files without checking for joins.
+    pub(super) fn remove(&self, clip: &Clip) -> Result<(), anyhow::Error> {
+        fs::remove_dir_all(&clip.dir)?;
+        Ok(())
+    }
+
+    /// Starts a new recording in the library.
+    ///
+    /// Creates the clip directory and its metadata, and returns a writer for the
+    /// audio. Pass the writer to [`ClipLibrary::finalize_recording`] when done.
+    /// Fails if free space is below the retention policy's threshold.
+    pub fn begin_recording(
+        &self,
+        spec: WavSpec,
+        policy: RolloverPolicy,
+    ) -> Result<SegmentWriter, anyhow::Error> {
+        self.check_free_space()?;
+        self.create_clip(spec, policy, None)
+    }
+
+    /// Finishes a recording started with [`ClipLibrary::begin_recording`].
+    ///
+    /// Stores the markers dropped during the recording and the checksums of the
+    /// audio, applies the library's post-processing, if any, once the audio is on
+    /// disk and then generates the clip's peak cache.
+    ///
+    /// The retention policy is then applied to the rest of the library; the new
+    /// clip itself is never deleted by it.
+    ///
+    /// Only a failure to store the recording is an error. Once it is stored, a
+    /// failure to post-process it, build its peak cache or apply the retention
+    /// policy is reported in the result alongside the clip.
+    pub fn finalize_recording(
+        &self,
+        writer: SegmentWriter,
+    ) -> Result<FinishedRecording, anyhow::Error> {
+        let dir = writer.dir().to_path_buf();
+        let mut clip = self.load(&dir)?;
+        clip.meta.markers = writer.markers().to_vec();
+        clip.meta.segments = writer.finalize()?;
+        integrity::checksum_segments(&mut clip)?;
+        self.save(&clip)?;
+
+        let processed = self
+            .post_process(&clip, &self.post_process)
+            .and_then(|processed| {
+                self.build_peaks(&processed)?;
+                Ok(processed)
+            });
+        let (clip, post_process_error) = match processed {
+            Ok(clip) => (clip, None),
+            // Post-processing may have got as far as replacing the audio.
+            Err(e) => (self.load(&dir).unwrap_or(clip), Some(e)),
+        };
+        let (retention, retention_error) = if self.retention.deletes() {
+            match self.apply_retention(false, Some(clip.id())) {
+                Ok(report) => (Some(report), None),
+                Err(e) => (None, Some(e)),
+            }
+        } else {
+            (None, None)
+        };
+        Ok(FinishedRecording {
+            clip,
+            post_process_error,
+            retention,
+            retention_error,
+        })
+    }
+
+    /// Imports an audio file into the library as a new clip.
+    ///
+    /// Any format supported by [`Decoder`] is accepted; the audio is stored as
+    /// 32-bit float WAV. The clip title is taken from the file's tags, falling back
+    /// to the file name. WAV files also contribute their Broadcast WAV, iXML and
+    /// cue metadata: recording time, title, tags and markers.
+    pub fn import(&self, path: impl AsRef<Path>) -> Result<Clip, anyhow::Error> {
+        let path = path.as_ref();
+        let mut decoder = Decoder::open(path).map_err(undecodable)?;
+        let info = decoder.info().clone();
+        let spec = WavSpec {
+            channels: info.channels,
+            sample_rate: info.sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let embedded = match info.format {
+            AudioFormat::Wav => embed::read_embedded(path),
+            _ => embed::EmbeddedMetadata::default(),
+        };
+        let title = info.title.clone().or(embedded.title).or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        });
+        let mut writer = self.create_clip(spec, RolloverPolicy::default(), title)?;
+        for marker in embedded.markers {
+            match marker.end {
+                Some(end) => writer.add_region(marker.position, end, marker.label),
+                None => writer.add_marker(marker.position, marker.label),
+            };
+        }
+
+        let mut buf = vec![0.0; 4096 * info.channels as usize];
+        let result = loop {
+            match decoder.read_frames(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(frames) => {
+                    if let Err(e) = writer.write(&buf[..frames * info.channels as usize]) {
+                        break Err(e);
+                    }
+                }
+                Err(e) => break Err(undecodable(e)),
+            }
+        };
+        if let Err(e) = result {
+            let dir = writer.dir().to_path_buf();
+            drop(writer);
+            fs::remove_dir_all(dir)?;
+            return Err(e.context(format!("failed to import {}", path.display())));
+        }
+        let finished = self.finalize_recording(writer)?;
+        let mut clip = finished.clip;
+        if let Some(e) = finished.post_process_error {
+            // An import either completes or leaves nothing behind.
+            self.remove(&clip)?;
+            return Err(e.context(format!("failed to import {}", path.display())));
+        }
+        if !embedded.tags.is_empty() || embedded.created.is_some() {
+            clip.meta.tags = embedded.tags;
+            clip.meta.created = embedded.created.unwrap_or(clip.meta.created);
+            self.save(&clip)?;
+        }
+        Ok(clip)
+    }
+
+    /// Opens a reader that plays the clip's segments back to back.
+    pub fn reader(&self, clip: &Clip) -> Result<ClipReader, anyhow::Error> {
+        ClipReader::open(clip)
+    }
+
+    /// Writes the clip into a single WAV file at `dest`, including its metadata.
+    pub fn export(&self, clip: &Clip, dest: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        let mut reader = self.reader(clip)?;
+        let spec = reader.spec();
+        write_wav(&mut reader, spec, &dest)?;
+        embed::write_file_chunks(&clip.meta, dest.as_ref())
+    }
+
+    fn create_clip(
+        &self,
+        spec: WavSpec,
+        policy: RolloverPolicy,
+        title: Option<String>,
+    ) -> Result<SegmentWriter, anyhow::Error> {
+        self.check_key()?;
+        let created = Local::now();
+        let id = self.unique_id(&created.format("%Y%m%d-%H%M%S").to_string());
+        let dir = self.root.join(&id);
+        let writer = SegmentWriter::create_with_key(&dir, spec, policy, self.key.clone())?;
+        let meta = ClipMetadata {
+            id,
+            title,
+            created,
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            segments: Vec::new(),
+            tags: Vec::new(),
+            starred: false,
+            silence_trim: None,
+            normalization: None,
+            markers: Vec::new(),
+        };
+        self.store(&dir, &meta)?;
+        Ok(writer)
+    }
+
+    fn unique_id(&self, base: &str) -> String {
+        let mut id = base.to_string();
+        let mut n = 1;
+        while self.root.join(&id).exists() {
+            id = format!("{}-{}", base, n);
+            n += 1;
+        }
+        id
+    }
+
+    fn load(&self, dir: &Path) -> Result<Clip, anyhow::Error> {
+        let path = dir.join(METADATA_FILE);
+        let json = crypto::read(&path, self.key.as_ref())
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let meta = serde_json::from_slice(&json)
+            .with_context(|| format!("invalid clip metadata in {}", path.display()))?;
+        Ok(Clip {
+            dir: dir.to_path_buf(),
+            meta,
+            key: self.key.clone(),
+        })
+    }
+
+    fn store(&self, dir: &Path, meta: &ClipMetadata) -> Result<(), anyhow::Error> {
+        let tmp = dir.join(format!("{}.tmp", METADATA_FILE));
+        let json = serde_json::to_string_pretty(meta)?;
+        crypto::write(&tmp, json.as_bytes(), self.key.as_ref())?;
+        fs::rename(tmp, dir.join(METADATA_FILE))?;
+        Ok(())
+    }
+}
+
+/// Streams every frame of `source` into a new WAV file at `dest`.
+///
+/// Samples are converted to integers when `spec` asks for an integer format.
+fn write_wav(
+    source: &mut impl FrameSource,
+    spec: WavSpec,
+    dest: impl AsRef<Path>,
+) -> Result<(), anyhow::Error> {
+    let mut writer = WavWriter::create(dest, spec)?;
+    let mut buf = vec![0.0; 4096 * spec.channels as usize];
+    let scale = ((1u64 << (spec.bits_per_sample - 1)) - 1) as f32;
+    loop {
+        let frames = source.read_frames(&mut buf)?;
+        if frames == 0 {
+            break;
+        }
+        for &sample in &buf[..frames * spec.channels as usize] {
+            match spec.sample_format {
+                SampleFormat::Float => writer.write_sample(sample)?,
+                SampleFormat::Int => {
+                    writer.write_sample((sample.clamp(-1.0, 1.0) * scale).round() as i32)?
+                }
+            }
+        }
+    }
+    writer.finalize()?;
+    Ok(())
+}
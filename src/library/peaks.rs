@@ -0,0 +1,379 @@
+use super::{Clip, ClipLibrary, ClipReader};
+use crate::crypto::{FileReader, FileWriter, Key};
+use crate::decode::FrameSource;
+use anyhow::anyhow;
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// Name of the peak cache stored in a clip directory.
+pub(super) const PEAKS_FILE: &str = "peaks.bin";
+const PEAKS_MAGIC: &[u8; 4] = b"PKPK";
+const PEAKS_VERSION: u32 = 1;
+/// Bytes taken by the header, by the start of each level, and by each bin.
+const HEADER_BYTES: u64 = 22;
+const LEVEL_HEADER_BYTES: u64 = 16;
+const BIN_BYTES: u64 = 12;
+/// Frames summarized by one bin of the finest level.
+const BASE_BIN_FRAMES: u64 = 256;
+/// Each level's bins cover this many bins of the level below.
+const LEVEL_FACTOR: u64 = 4;
+
+/// Summary of a span of audio, with all channels folded together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeakBin {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+impl PeakBin {
+    const EMPTY: PeakBin = PeakBin {
+        min: 0.0,
+        max: 0.0,
+        rms: 0.0,
+    };
+}
+
+/// Accumulates samples into a [`PeakBin`].
+#[derive(Debug, Clone, Copy)]
+struct BinAccumulator {
+    min: f32,
+    max: f32,
+    sum_squares: f64,
+    count: u64,
+}
+
+impl BinAccumulator {
+    fn new() -> BinAccumulator {
+        BinAccumulator {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum_squares: 0.0,
+            count: 0,
+        }
+    }
+
+    fn add_sample(&mut self, sample: f32) {
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.sum_squares += (sample * sample) as f64;
+        self.count += 1;
+    }
+
+    /// Merges a bin that summarized `count` samples.
+    fn add_bin(&mut self, bin: &PeakBin, count: u64) {
+        self.min = self.min.min(bin.min);
+        self.max = self.max.max(bin.max);
+        self.sum_squares += (bin.rms as f64).powi(2) * count as f64;
+        self.count += count;
+    }
+
+    fn finish(&self) -> PeakBin {
+        if self.count == 0 {
+            return PeakBin::EMPTY;
+        }
+        PeakBin {
+            min: self.min,
+            max: self.max,
+            rms: (self.sum_squares / self.count as f64).sqrt() as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PeakLevel {
+    bin_frames: u64,
+    bins: Vec<PeakBin>,
+}
+
+/// Multi-resolution min/max/RMS overview of a clip.
+///
+/// The finest level summarizes every 256 frames and each following level is four
+/// times coarser, so any zoom level can be served by reading a few thousand bins.
+#[derive(Debug, Clone)]
+pub struct PeakCache {
+    channels: u16,
+    total_frames: u64,
+    levels: Vec<PeakLevel>,
+}
+
+impl PeakCache {
+    /// Builds the cache by reading `source` from its current position to the end.
+    pub fn build(source: &mut impl FrameSource) -> Result<PeakCache, anyhow::Error> {
+        let channels = source.info().channels;
+        let mut bins = Vec::new();
+        let mut acc = BinAccumulator::new();
+        let mut in_bin = 0;
+        let mut total_frames = 0;
+        let mut buf = vec![0.0; BASE_BIN_FRAMES as usize * 16 * channels as usize];
+        loop {
+            let frames = source.read_frames(&mut buf)?;
+            if frames == 0 {
+                break;
+            }
+            for frame in buf[..frames * channels as usize].chunks_exact(channels as usize) {
+                frame.iter().for_each(|&s| acc.add_sample(s));
+                in_bin += 1;
+                if in_bin == BASE_BIN_FRAMES {
+                    bins.push(acc.finish());
+                    acc = BinAccumulator::new();
+                    in_bin = 0;
+                }
+            }
+            total_frames += frames as u64;
+        }
+        if in_bin > 0 {
+            bins.push(acc.finish());
+        }
+
+        let mut levels = vec![PeakLevel {
+            bin_frames: BASE_BIN_FRAMES,
+            bins,
+        }];
+        while levels.last().is_some_and(|l| l.bins.len() > 1) {
+            let below = levels.last().unwrap();
+            let bin_frames = below.bin_frames * LEVEL_FACTOR;
+            let bins = below
+                .bins
+                .chunks(LEVEL_FACTOR as usize)
+                .enumerate()
+                .map(|(i, group)| {
+                    let mut acc = BinAccumulator::new();
+                    for (j, bin) in group.iter().enumerate() {
+                        let index = (i * LEVEL_FACTOR as usize + j) as u64;
+                        let frames = below.frames_in(index, total_frames);
+                        acc.add_bin(bin, frames * channels as u64);
+                    }
+                    acc.finish()
+                })
+                .collect();
+            levels.push(PeakLevel { bin_frames, bins });
+        }
+
+        Ok(PeakCache {
+            channels,
+            total_frames,
+            levels,
+        })
+    }
+
+    /// Returns the number of frames covered by the cache.
+    pub fn total_frames(&self) -> u64 {
+        self.total_frames
+    }
+
+    /// Summarizes `range` into `bins` equally sized bins from the cached levels.
+    ///
+    /// # Returns
+    /// * `Option<Vec<PeakBin>>` - The bins, or `None` if the requested resolution is
+    ///   finer than the cache holds and the audio has to be read instead.
+    pub fn range(&self, range: Range<u64>, bins: usize) -> Option<Vec<PeakBin>> {
+        let range = range.start.min(self.total_frames)..range.end.min(self.total_frames);
+        let span = range.end.saturating_sub(range.start);
+        if bins == 0 {
+            return Some(Vec::new());
+        }
+        // Prefer a level a step finer than the output so bin edges stay accurate.
+        let frames_per_bin = span / bins as u64;
+        let level = self
+            .levels
+            .iter()
+            .rev()
+            .find(|l| l.bin_frames * LEVEL_FACTOR <= frames_per_bin)
+            .or_else(|| self.levels.iter().rev().find(|l| l.bin_frames <= frames_per_bin))?;
+
+        Some(
+            (0..bins as u64)
+                .map(|i| {
+                    let start = range.start + span * i / bins as u64;
+                    let end = range.start + span * (i + 1) / bins as u64;
+                    // Each cached bin goes to the output bin containing its centre, so
+                    // neighbouring output bins never share data.
+                    let half = level.bin_frames / 2;
+                    let first = start.saturating_sub(half).div_ceil(level.bin_frames);
+                    let last = end.saturating_sub(half).div_ceil(level.bin_frames);
+                    let mut acc = BinAccumulator::new();
+                    for index in first..last.min(level.bins.len() as u64) {
+                        let frames = level.frames_in(index, self.total_frames);
+                        acc.add_bin(&level.bins[index as usize], frames * self.channels as u64);
+                    }
+                    acc.finish()
+                })
+                .collect(),
+        )
+    }
+
+    /// Stores the cache at `path`, replacing any older one only once it is complete.
+    fn write(&self, path: &Path, key: Option<&Key>) -> Result<(), anyhow::Error> {
+        let tmp = path.with_extension("bin.tmp");
+        let mut out = BufWriter::new(FileWriter::create(&tmp, key)?);
+        out.write_all(PEAKS_MAGIC)?;
+        out.write_all(&PEAKS_VERSION.to_le_bytes())?;
+        out.write_all(&self.channels.to_le_bytes())?;
+        out.write_all(&self.total_frames.to_le_bytes())?;
+        out.write_all(&(self.levels.len() as u32).to_le_bytes())?;
+        for level in &self.levels {
+            out.write_all(&level.bin_frames.to_le_bytes())?;
+            out.write_all(&(level.bins.len() as u64).to_le_bytes())?;
+            for bin in &level.bins {
+                out.write_all(&bin.min.to_le_bytes())?;
+                out.write_all(&bin.max.to_le_bytes())?;
+                out.write_all(&bin.rms.to_le_bytes())?;
+            }
+        }
+        out.into_inner().map_err(|e| e.into_error())?.finish()?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Loads the cache at `path`, which must summarize `expected_frames` frames.
+    ///
+    /// Counts in the file are checked against its length before anything is
+    /// allocated, so a damaged file is an error rather than an abort.
+    fn read(
+        path: &Path,
+        key: Option<&Key>,
+        expected_frames: u64,
+    ) -> Result<PeakCache, anyhow::Error> {
+        let file = FileReader::open(path, key)?;
+        let damaged = || anyhow!("{} is damaged", path.display());
+        let mut remaining = file
+            .plaintext_len()?
+            .checked_sub(HEADER_BYTES)
+            .ok_or_else(damaged)?;
+        let mut input = BufReader::new(file);
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != PEAKS_MAGIC || read_u32(&mut input)? != PEAKS_VERSION {
+            return Err(anyhow!("{} is not a peak cache", path.display()));
+        }
+        let mut channels = [0u8; 2];
+        input.read_exact(&mut channels)?;
+        let channels = u16::from_le_bytes(channels);
+        let total_frames = read_u64(&mut input)?;
+        if total_frames != expected_frames {
+            return Err(anyhow!("{} is out of date", path.display()));
+        }
+        let level_count = read_u32(&mut input)?;
+        if level_count as u64 * LEVEL_HEADER_BYTES > remaining {
+            return Err(damaged());
+        }
+        let mut levels = Vec::with_capacity(level_count as usize);
+        for _ in 0..level_count {
+            let bin_frames = read_u64(&mut input)?;
+            let count = read_u64(&mut input)?;
+            remaining -= LEVEL_HEADER_BYTES;
+            if bin_frames == 0
+                || count != total_frames.div_ceil(bin_frames)
+                || count.saturating_mul(BIN_BYTES) > remaining
+            {
+                return Err(damaged());
+            }
+            remaining -= count * BIN_BYTES;
+            let mut bins = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                bins.push(PeakBin {
+                    min: read_f32(&mut input)?,
+                    max: read_f32(&mut input)?,
+                    rms: read_f32(&mut input)?,
+                });
+            }
+            levels.push(PeakLevel { bin_frames, bins });
+        }
+        Ok(PeakCache {
+            channels,
+            total_frames,
+            levels,
+        })
+    }
+}
+
+impl PeakLevel {
+    /// Number of frames summarized by bin `index`; only the last bin may be short.
+    fn frames_in(&self, index: u64, total_frames: u64) -> u64 {
+        let start = index * self.bin_frames;
+        (start + self.bin_frames).min(total_frames) - start
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32, anyhow::Error> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(input: &mut impl Read) -> Result<u64, anyhow::Error> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f32(input: &mut impl Read) -> Result<f32, anyhow::Error> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+impl ClipLibrary {
+    /// Generates the peak cache of `clip` and stores it alongside the audio.
+    pub fn build_peaks(&self, clip: &Clip) -> Result<PeakCache, anyhow::Error> {
+        let cache = PeakCache::build(&mut ClipReader::open(clip)?)?;
+        cache.write(&clip.dir.join(PEAKS_FILE), clip.key.as_ref())?;
+        Ok(cache)
+    }
+
+    /// Loads the peak cache of `clip`, generating it first if it is missing or stale.
+    pub fn peak_cache(&self, clip: &Clip) -> Result<PeakCache, anyhow::Error> {
+        let path = clip.dir.join(PEAKS_FILE);
+        if path.is_file() {
+            if let Ok(cache) = PeakCache::read(&path, clip.key.as_ref(), clip.meta.total_frames()) {
+                return Ok(cache);
+            }
+        }
+        self.build_peaks(clip)
+    }
+
+    /// Returns `bins` min/max/RMS bins covering the frames in `range` of `clip`.
+    ///
+    /// Served from the peak cache when zoomed out; when zoomed in past the cache's
+    /// finest level the audio in `range` is read directly. A range that is empty
+    /// or reversed gives empty bins.
+    pub fn peaks(
+        &self,
+        clip: &Clip,
+        range: Range<u64>,
+        bins: usize,
+    ) -> Result<Vec<PeakBin>, anyhow::Error> {
+        let cache = self.peak_cache(clip)?;
+        if let Some(peaks) = cache.range(range.clone(), bins) {
+            return Ok(peaks);
+        }
+
+        // A reversed range covers no frames, like an empty one.
+        let total = clip.meta.total_frames();
+        let start = range.start.min(total);
+        let range = start..range.end.clamp(start, total);
+        let span = range.end - range.start;
+        let channels = clip.meta.channels as usize;
+        let mut reader = ClipReader::open(clip)?;
+        reader.seek(range.start)?;
+        let mut samples = vec![0.0; span as usize * channels];
+        let read = reader.read_frames(&mut samples)?;
+        samples.truncate(read * channels);
+
+        Ok((0..bins as u64)
+            .map(|i| {
+                let start = (span * i / bins as u64) as usize;
+                let end = (span * (i + 1) / bins as u64) as usize;
+                let mut acc = BinAccumulator::new();
+                let end = end.min(samples.len() / channels);
+                for &sample in samples[start.min(end) * channels..end * channels].iter() {
+                    acc.add_sample(sample);
+                }
+                acc.finish()
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,304 @@
+use super::integrity::checksum_segments;
+use super::peaks::PEAKS_FILE;
+use super::{Clip, ClipLibrary, ClipReader};
+use crate::decode::FrameSource;
+use crate::dsp::{
+    db_to_gain, gain_to_db, LoudnessMeter, PeakMeter, Resampler, SilenceConfig, SilenceDetector,
+};
+use crate::recorder::segment::{RolloverPolicy, SegmentInfo, SegmentWriter};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory inside a clip used while its audio is being rewritten.
+const WORK_DIR: &str = ".postprocess";
+
+/// Target level for loudness normalization.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Normalization {
+    /// Scale so the highest sample sits at `target_dbfs`.
+    Peak { target_dbfs: f32 },
+    /// Scale to an integrated loudness of `target_lufs`, without pushing the peak above 0 dBFS.
+    Loudness { target_lufs: f32 },
+}
+
+/// Optional processing applied to a recording when it is finalized.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PostProcess {
+    pub trim_silence: Option<SilenceConfig>,
+    pub normalize: Option<Normalization>,
+}
+
+impl PostProcess {
+    /// Returns `true` if no processing is configured.
+    pub fn is_empty(&self) -> bool {
+        self.trim_silence.is_none() && self.normalize.is_none()
+    }
+}
+
+/// Record of leading and trailing silence removed from a clip.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SilenceTrim {
+    pub config: SilenceConfig,
+    pub removed_start_frames: u64,
+    pub removed_end_frames: u64,
+}
+
+/// Record of the gain applied to normalize a clip.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NormalizationRecord {
+    pub target: Normalization,
+    /// Level measured before processing, in dBFS for peak mode or LUFS for loudness mode.
+    pub measured: f32,
+    pub gain_db: f32,
+}
+
+impl ClipLibrary {
+    /// Sets the processing applied by [`ClipLibrary::finalize_recording`].
+    pub fn with_post_processing(mut self, post_process: PostProcess) -> ClipLibrary {
+        self.post_process = post_process;
+        self
+    }
+
+    /// Trims silence from and normalizes `clip` in place, recording what was done
+    /// in its metadata.
+    ///
+    /// The audio is analyzed in one pass and rewritten in a second, keeping the
+    /// clip's segment size. A clip that is silent throughout is left untrimmed, and
+    /// one too short or too quiet to measure is left at its original level.
+    /// Trimming a clip that has edits is an error, as they would no longer line
+    /// up with the audio.
+    ///
+    /// # Returns
+    /// * `Clip` - The clip with updated segments and metadata.
+    pub fn post_process(
+        &self,
+        clip: &Clip,
+        options: &PostProcess,
+    ) -> Result<Clip, anyhow::Error> {
+        let mut clip = clip.clone();
+        if options.is_empty() {
+            return Ok(clip);
+        }
+        let (rate, channels) = (clip.meta.sample_rate, clip.meta.channels);
+        let total = clip.meta.total_frames();
+
+        let mut reader = ClipReader::open(&clip)?;
+        let mut detector = options
+            .trim_silence
+            .map(|config| SilenceDetector::new(&config, rate, channels));
+        let mut peak = PeakMeter::default();
+        let mut loudness = LoudnessMeter::new(rate, channels);
+        let mut buf = vec![0.0; 4096 * channels as usize];
+        loop {
+            let frames = reader.read_frames(&mut buf)?;
+            if frames == 0 {
+                break;
+            }
+            let samples = &buf[..frames * channels as usize];
+            if let Some(detector) = detector.as_mut() {
+                detector.push(samples);
+            }
+            peak.push(samples);
+            if matches!(options.normalize, Some(Normalization::Loudness { .. })) {
+                loudness.push(samples);
+            }
+        }
+
+        let keep = detector.and_then(|d| d.finish()).unwrap_or(0..total);
+        // Edits point into the audio as recorded, and cannot follow it once the
+        // start or end is cut away.
+        if keep != (0..total) && !self.edits(&clip)?.is_empty() {
+            return Err(anyhow!(
+                "clip '{}' has edits, so its silence cannot be trimmed",
+                clip.id()
+            ));
+        }
+        let peak_db = gain_to_db(peak.peak());
+        let normalization = match options.normalize {
+            Some(target @ Normalization::Peak { target_dbfs }) if peak.peak() > 0.0 => {
+                Some(NormalizationRecord {
+                    target,
+                    measured: peak_db,
+                    gain_db: target_dbfs - peak_db,
+                })
+            }
+            Some(target @ Normalization::Loudness { target_lufs }) => {
+                loudness.integrated().map(|lufs| NormalizationRecord {
+                    target,
+                    measured: lufs as f32,
+                    gain_db: (target_lufs - lufs as f32).min(-peak_db),
+                })
+            }
+            _ => None,
+        };
+
+        let gain = normalization.map_or(1.0, |n| db_to_gain(n.gain_db));
+        let spec = reader.spec();
+        let work = clip.dir.join(WORK_DIR);
+        let policy = match clip.meta.segments.as_slice() {
+            [first, _, ..] => RolloverPolicy::with_segment_frames(first.frames, &spec),
+            _ => RolloverPolicy::default(),
+        };
+        let mut writer = SegmentWriter::create_with_key(&work, spec, policy, clip.key.clone())?;
+        reader.seek(keep.start)?;
+        let mut left = keep.end - keep.start;
+        while left > 0 {
+            let want = (left as usize).min(4096);
+            let frames = reader.read_frames(&mut buf[..want * channels as usize])?;
+            if frames == 0 {
+                break;
+            }
+            let samples = &mut buf[..frames * channels as usize];
+            samples.iter_mut().for_each(|s| *s *= gain);
+            writer.write(samples)?;
+            left -= frames as u64;
+        }
+        let segments = writer.finalize()?;
+        drop(reader);
+        let old = install_segments(&mut clip, &work, segments)?;
+        // Markers follow the audio they point at; ones inside trimmed silence move
+        // to the nearest kept frame.
+        for marker in clip.meta.markers.iter_mut() {
+            marker.position = marker.position.clamp(keep.start, keep.end) - keep.start;
+            marker.end = marker.end.map(|end| end.clamp(keep.start, keep.end) - keep.start);
+        }
+        if let Some(config) = options.trim_silence {
+            clip.meta.silence_trim = Some(SilenceTrim {
+                config,
+                removed_start_frames: keep.start,
+                removed_end_frames: total - keep.end,
+            });
+        }
+        if normalization.is_some() {
+            clip.meta.normalization = normalization;
+        }
+        self.save(&clip)?;
+        remove_stale_segments(&clip, &work, old)?;
+        Ok(clip)
+    }
+
+    /// Converts the audio of `clip` to `sample_rate` in place.
+    ///
+    /// Segment lengths keep their duration, and markers, edits and the silence
+    /// trim record are rescaled to the new rate. A clip already at the rate is
+    /// returned unchanged.
+    ///
+    /// # Returns
+    /// * `Clip` - The clip with updated segments and metadata.
+    pub fn resample(&self, clip: &Clip, sample_rate: u32) -> Result<Clip, anyhow::Error> {
+        let mut clip = clip.clone();
+        let from = clip.meta.sample_rate;
+        if from == sample_rate {
+            return Ok(clip);
+        }
+        if sample_rate == 0 {
+            return Err(anyhow!("sample rate must be positive"));
+        }
+        let rescale = |frames: u64| frames * sample_rate as u64 / from as u64;
+        let channels = clip.meta.channels as usize;
+        let mut edits = self.edits(&clip)?;
+
+        let mut reader = ClipReader::open(&clip)?;
+        let mut spec = reader.spec();
+        spec.sample_rate = sample_rate;
+        let work = clip.dir.join(WORK_DIR);
+        let policy = match clip.meta.segments.as_slice() {
+            [first, _, ..] => RolloverPolicy::with_segment_frames(rescale(first.frames), &spec),
+            _ => RolloverPolicy::default(),
+        };
+        let mut writer = SegmentWriter::create_with_key(&work, spec, policy, clip.key.clone())?;
+        let mut resampler = Resampler::new(from, sample_rate, clip.meta.channels);
+        let mut buf = vec![0.0; 4096 * channels];
+        let mut out = Vec::new();
+        loop {
+            let frames = reader.read_frames(&mut buf)?;
+            if frames == 0 {
+                break;
+            }
+            out.clear();
+            resampler.process(&buf[..frames * channels], &mut out);
+            writer.write(&out)?;
+        }
+        out.clear();
+        resampler.finish(&mut out);
+        writer.write(&out)?;
+        let segments = writer.finalize()?;
+        drop(reader);
+        let old = install_segments(&mut clip, &work, segments)?;
+
+        clip.meta.sample_rate = sample_rate;
+        for marker in clip.meta.markers.iter_mut() {
+            marker.position = rescale(marker.position);
+            marker.end = marker.end.map(rescale);
+        }
+        if let Some(trim) = clip.meta.silence_trim.as_mut() {
+            trim.removed_start_frames = rescale(trim.removed_start_frames);
+            trim.removed_end_frames = rescale(trim.removed_end_frames);
+        }
+        self.save(&clip)?;
+        if !edits.is_empty() {
+            edits.rescale(rescale);
+            self.store_edits(&clip, &edits)?;
+        }
+        remove_stale_segments(&clip, &work, old)?;
+        Ok(clip)
+    }
+}
+
+/// Moves the segments written into `work` into `clip` under names no other
+/// file uses, then refreshes the checksums.
+///
+/// The old segments are left in place, so until the metadata describing the
+/// new ones is saved the clip still reads as its old audio, even if this is
+/// interrupted. Once it is saved, pass the returned paths to
+/// [`remove_stale_segments`].
+///
+/// # Returns
+/// * `Vec<PathBuf>` - The segment files of the old audio.
+fn install_segments(
+    clip: &mut Clip,
+    work: &Path,
+    mut segments: Vec<SegmentInfo>,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    // The audio is about to change, so any cached overview is stale.
+    let peaks = clip.dir.join(PEAKS_FILE);
+    if peaks.exists() {
+        fs::remove_file(peaks)?;
+    }
+    // Number the new set of segments after the first revision whose names are
+    // all free, so none replaces a file the saved metadata may still point at.
+    let revision = (1..)
+        .find(|revision| {
+            (0..segments.len())
+                .all(|index| !clip.dir.join(revised_file_name(index, *revision)).exists())
+        })
+        .expect("a free revision");
+    for (index, segment) in segments.iter_mut().enumerate() {
+        let file = revised_file_name(index, revision);
+        fs::rename(work.join(&segment.file), clip.dir.join(&file))?;
+        segment.file = file;
+    }
+    let old = clip.segment_paths();
+    clip.meta.segments = segments;
+    checksum_segments(clip)?;
+    Ok(old)
+}
+
+/// Returns the file name of segment `index` of a rewritten clip.
+fn revised_file_name(index: usize, revision: u32) -> String {
+    format!("segment-{:03}.{}.wav", index, revision)
+}
+
+/// Removes the old segment files that the new ones did not replace, and the
+/// work directory.
+fn remove_stale_segments(clip: &Clip, work: &Path, old: Vec<PathBuf>) -> Result<(), anyhow::Error> {
+    let current = clip.segment_paths();
+    for path in old.into_iter().filter(|path| !current.contains(path)) {
+        fs::remove_file(path)?;
+    }
+    fs::remove_dir_all(work)?;
+    Ok(())
+}
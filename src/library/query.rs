@@ -0,0 +1,61 @@
+use super::{Clip, ClipLibrary};
+use chrono::{DateTime, Local};
+use std::time::Duration;
+
+/// Criteria for selecting clips from the library.
+///
+/// Every criterion is optional and a clip must meet all of those that are set;
+/// the default query matches every finished clip.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClipQuery {
+    /// Text that must appear, ignoring case, in the clip's id, title or one of its tags.
+    pub text: Option<String>,
+    /// Tags the clip must all have.
+    pub tags: Vec<String>,
+    pub starred: Option<bool>,
+    /// Only clips recorded at or after this time.
+    pub after: Option<DateTime<Local>>,
+    /// Only clips recorded before this time.
+    pub before: Option<DateTime<Local>>,
+    pub min_duration: Option<Duration>,
+    pub max_duration: Option<Duration>,
+}
+
+impl ClipQuery {
+    /// Returns `true` if `clip` meets every criterion of the query.
+    pub fn matches(&self, clip: &Clip) -> bool {
+        let meta = &clip.meta;
+        if let Some(text) = &self.text {
+            let text = text.to_lowercase();
+            let found = meta.id.to_lowercase().contains(&text)
+                || meta
+                    .title
+                    .as_ref()
+                    .is_some_and(|t| t.to_lowercase().contains(&text))
+                || meta.tags.iter().any(|t| t.to_lowercase().contains(&text));
+            if !found {
+                return false;
+            }
+        }
+        // A sample rate of zero makes the duration infinite or NaN, which
+        // matches no duration bound.
+        let duration = Duration::try_from_secs_f64(meta.duration_secs()).ok();
+        self.tags.iter().all(|tag| meta.tags.contains(tag))
+            && self.starred.is_none_or(|starred| meta.starred == starred)
+            && self.after.is_none_or(|after| meta.created >= after)
+            && self.before.is_none_or(|before| meta.created < before)
+            && self.min_duration.is_none_or(|min| duration.is_some_and(|d| d >= min))
+            && self.max_duration.is_none_or(|max| duration.is_some_and(|d| d <= max))
+    }
+}
+
+impl ClipLibrary {
+    /// Lists the finished clips matching `query`, oldest first.
+    pub fn query(&self, query: &ClipQuery) -> Result<Vec<Clip>, anyhow::Error> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|clip| clip.is_complete() && query.matches(clip))
+            .collect())
+    }
+}
@@ -0,0 +1,163 @@
+use super::Clip;
+use crate::crypto::{FileReader, Key};
+use crate::decode::{AudioFormat, AudioInfo, FrameSource};
+use anyhow::{anyhow, Context};
+use hound::{WavReader, WavSpec};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Reads the segments of a clip back to back as one continuous stream of frames.
+///
+/// Only the segment under the read position is open at any time, so memory use
+/// does not grow with the length of the clip. A segment that is shorter than its
+/// metadata claims, or whose samples cannot be decoded, is reported as an error.
+pub struct ClipReader {
+    spec: WavSpec,
+    info: AudioInfo,
+    segments: Vec<(PathBuf, u64)>,
+    key: Option<Key>,
+    index: usize,
+    current: WavReader<BufReader<FileReader>>,
+    /// Frames already read from the current segment.
+    segment_pos: u64,
+    /// Combined length of all segments before the current one.
+    base: u64,
+}
+
+/// Opens a segment for reading, decrypting it if needed.
+pub(super) fn open_wav(
+    path: &Path,
+    key: Option<&Key>,
+) -> Result<WavReader<BufReader<FileReader>>, anyhow::Error> {
+    Ok(WavReader::new(BufReader::new(FileReader::open(path, key)?))?)
+}
+
+impl ClipReader {
+    /// Opens a reader over all segments of `clip`.
+    pub fn open(clip: &Clip) -> Result<ClipReader, anyhow::Error> {
+        let meta = clip.metadata();
+        let segments: Vec<(PathBuf, u64)> = clip
+            .segment_paths()
+            .into_iter()
+            .zip(meta.segments.iter().map(|s| s.frames))
+            .collect();
+        let first = segments
+            .first()
+            .ok_or_else(|| anyhow!("clip '{}' has no audio", clip.id()))?;
+        let current = open_wav(&first.0, clip.key.as_ref())?;
+        if (current.duration() as u64) < first.1 {
+            return Err(anyhow!(
+                "segment {} is truncated: expected {} frames, found {}",
+                first.0.display(),
+                first.1,
+                current.duration()
+            ));
+        }
+        Ok(ClipReader {
+            spec: current.spec(),
+            info: AudioInfo {
+                format: AudioFormat::Wav,
+                sample_rate: meta.sample_rate,
+                channels: meta.channels,
+                total_frames: Some(meta.total_frames()),
+                title: meta.title.clone(),
+                artist: None,
+            },
+            segments,
+            key: clip.key.clone(),
+            index: 0,
+            current,
+            segment_pos: 0,
+            base: 0,
+        })
+    }
+
+    /// Returns the spec shared by the clip's segments.
+    pub fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    fn open_segment(&mut self, index: usize) -> Result<(), anyhow::Error> {
+        let (path, frames) = &self.segments[index];
+        let reader = open_wav(path, self.key.as_ref())?;
+        if reader.spec() != self.spec {
+            return Err(anyhow!(
+                "segment {} does not match the clip's format",
+                path.display()
+            ));
+        }
+        if (reader.duration() as u64) < *frames {
+            return Err(anyhow!(
+                "segment {} is truncated: expected {} frames, found {}",
+                path.display(),
+                frames,
+                reader.duration()
+            ));
+        }
+        self.base = self.segments[..index].iter().map(|(_, f)| f).sum();
+        self.index = index;
+        self.current = reader;
+        self.segment_pos = 0;
+        Ok(())
+    }
+}
+
+impl FrameSource for ClipReader {
+    fn info(&self) -> &AudioInfo {
+        &self.info
+    }
+
+    fn read_frames(&mut self, buf: &mut [f32]) -> Result<usize, anyhow::Error> {
+        let channels = self.info.channels as usize;
+        let wanted = buf.len() / channels;
+        let mut written = 0;
+        while written < wanted {
+            let remaining = self.segments[self.index].1 - self.segment_pos;
+            if remaining == 0 {
+                if self.index + 1 == self.segments.len() {
+                    break;
+                }
+                self.open_segment(self.index + 1)?;
+                continue;
+            }
+            let n = (remaining as usize).min(wanted - written);
+            let out = &mut buf[written * channels..(written + n) * channels];
+            let path = &self.segments[self.index].0;
+            let mut samples = self.current.samples::<f32>();
+            for slot in out.iter_mut() {
+                *slot = samples
+                    .next()
+                    .ok_or_else(|| anyhow!("unexpected end of data in {}", path.display()))?
+                    .with_context(|| format!("corrupt audio data in {}", path.display()))?;
+            }
+            self.segment_pos += n as u64;
+            written += n;
+        }
+        Ok(written)
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<u64, anyhow::Error> {
+        let total = self.info.total_frames.unwrap_or(0);
+        let frame = frame.min(total);
+        let mut base = 0;
+        let mut index = self.segments.len() - 1;
+        for (i, (_, frames)) in self.segments.iter().enumerate() {
+            if frame < base + frames {
+                index = i;
+                break;
+            }
+            base += frames;
+        }
+        if index != self.index {
+            self.open_segment(index)?;
+        }
+        let offset = frame - self.base;
+        self.current.seek(offset as u32)?;
+        self.segment_pos = offset;
+        Ok(frame)
+    }
+
+    fn position(&self) -> u64 {
+        self.base + self.segment_pos
+    }
+}
@@ -0,0 +1,179 @@
+use super::{Clip, ClipLibrary};
+use anyhow::anyhow;
+use chrono::Local;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+/// Limits on how much the library may store and for how long.
+///
+/// Every limit is optional; the default policy keeps everything and never
+/// refuses a recording. The limits are applied each time a recording is
+/// finalized, and whenever [`ClipLibrary::enforce_retention`] is called.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Maximum combined size of all clips, in bytes.
+    pub max_total_bytes: Option<u64>,
+    /// Clips older than this are deleted.
+    pub max_age: Option<Duration>,
+    /// Never delete clips that have at least one tag.
+    pub keep_tagged: bool,
+    /// Never delete starred clips.
+    pub keep_starred: bool,
+    /// Refuse to start recording when the filesystem has less free space than this.
+    pub min_free_bytes: Option<u64>,
+    /// Delete clips that were never finalized, such as recordings cut short by
+    /// a crash, once none of their files has changed for this long. Without
+    /// it they are kept, and count towards `max_total_bytes`, until deleted by
+    /// hand. Recordings in progress keep writing, so are never this idle
+    /// unless their input stream is paused for longer.
+    pub abandoned_after: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    /// Returns `true` if the policy protects `clip` from deletion.
+    pub fn protects(&self, clip: &Clip) -> bool {
+        (self.keep_starred && clip.meta.starred) || (self.keep_tagged && !clip.meta.tags.is_empty())
+    }
+
+    /// Returns `true` if the policy can delete clips at all.
+    pub fn deletes(&self) -> bool {
+        self.max_total_bytes.is_some() || self.max_age.is_some() || self.abandoned_after.is_some()
+    }
+}
+
+/// Why a clip was selected for deletion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletionReason {
+    /// The clip is older than the policy's `max_age`.
+    Expired,
+    /// The library exceeded `max_total_bytes` and this was the oldest unprotected clip.
+    OverQuota,
+    /// The clip was never finalized and has been idle for the policy's
+    /// `abandoned_after`.
+    Abandoned,
+}
+
+/// A clip that was (or in a dry run, would be) deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deletion {
+    pub id: String,
+    pub bytes: u64,
+    pub reason: DeletionReason,
+}
+
+/// The outcome of applying a retention policy.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    pub deletions: Vec<Deletion>,
+    pub total_bytes_before: u64,
+    pub total_bytes_after: u64,
+}
+
+impl ClipLibrary {
+    /// Applies the library's retention policy.
+    ///
+    /// Expired clips are removed first, then the oldest unprotected clips until the
+    /// library fits within its quota. Clips that were never finalized are only
+    /// removed once abandoned, so recordings in progress are not touched, and
+    /// clips joined into another clip by its edits are always kept. With
+    /// `dry_run` set nothing is deleted and the report lists what would have been.
+    ///
+    /// # Returns
+    /// * `RetentionReport` - The clips selected for deletion and the library size before and after.
+    pub fn enforce_retention(&self, dry_run: bool) -> Result<RetentionReport, anyhow::Error> {
+        self.apply_retention(dry_run, None)
+    }
+
+    /// Applies the retention policy, never deleting the clip `spare`.
+    pub(super) fn apply_retention(
+        &self,
+        dry_run: bool,
+        spare: Option<&str>,
+    ) -> Result<RetentionReport, anyhow::Error> {
+        let policy = &self.retention;
+        let joined = self.joined_clips()?;
+        let mut clips = Vec::new();
+        let mut total = 0;
+        for clip in self.list()? {
+            let bytes = clip.size_bytes()?;
+            total += bytes;
+            clips.push((clip, bytes));
+        }
+
+        let mut report = RetentionReport {
+            dry_run,
+            total_bytes_before: total,
+            ..Default::default()
+        };
+        let now = Local::now();
+        // `list` returns clips oldest first, so quota deletions start with the oldest.
+        for (clip, bytes) in &clips {
+            if spare == Some(clip.id()) || policy.protects(clip) || joined.contains(clip.id()) {
+                continue;
+            }
+            if !clip.is_complete() {
+                match policy.abandoned_after {
+                    Some(max) if idle_time(clip)? > max => {}
+                    _ => continue,
+                }
+            }
+            let age = now
+                .signed_duration_since(clip.meta.created)
+                .to_std()
+                .unwrap_or_default();
+            let reason = if !clip.is_complete() {
+                DeletionReason::Abandoned
+            } else if policy.max_age.is_some_and(|max| age > max) {
+                DeletionReason::Expired
+            } else if policy.max_total_bytes.is_some_and(|max| total > max) {
+                DeletionReason::OverQuota
+            } else {
+                continue;
+            };
+            if !dry_run {
+                self.remove(clip)?;
+            }
+            total -= bytes;
+            report.deletions.push(Deletion {
+                id: clip.id().to_string(),
+                bytes: *bytes,
+                reason,
+            });
+        }
+        report.total_bytes_after = total;
+        Ok(report)
+    }
+
+    /// Returns the number of bytes available to the library on its filesystem.
+    pub fn free_space(&self) -> Result<u64, anyhow::Error> {
+        Ok(fs2::available_space(&self.root)?)
+    }
+
+    /// Fails if free space is below the retention policy's `min_free_bytes`.
+    pub fn check_free_space(&self) -> Result<(), anyhow::Error> {
+        if let Some(min) = self.retention.min_free_bytes {
+            let free = self.free_space()?;
+            if free < min {
+                return Err(anyhow!(
+                    "only {} bytes free in {}, need at least {} to record",
+                    free,
+                    self.root.display(),
+                    min
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns how long it has been since any file of `clip` changed.
+fn idle_time(clip: &Clip) -> Result<Duration, anyhow::Error> {
+    let mut modified = SystemTime::UNIX_EPOCH;
+    for entry in fs::read_dir(&clip.dir)? {
+        modified = modified.max(entry?.metadata()?.modified()?);
+    }
+    Ok(SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default())
+}
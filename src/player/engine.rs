@@ -0,0 +1,547 @@
+use super::mixer::{MixSettings, Mixer, VoiceId};
+use super::track::{Settings, Track};
+use super::{PlaybackState, PlayerEvent, Repeat, Transition};
+use crate::decode::FrameSource;
+use crate::dsp::{ChannelMap, SilenceConfig, MAX_SPEED, MIN_SPEED};
+use crate::library::{Clip, ClipMetadata, ClipReader};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// A reader for a queued clip, or why it could not be opened.
+type Opened = Result<Box<dyn FrameSource + Send>, anyhow::Error>;
+
+/// A track fading out under the start of the next one.
+struct Fade {
+    track: Track,
+    /// Frames of the crossfade played so far.
+    done: usize,
+    /// Length of the crossfade in frames.
+    length: usize,
+}
+
+/// Playback state shared between the player and the audio callback.
+pub(super) struct Engine {
+    pub state: PlaybackState,
+    pub volume: f32,
+    settings: Settings,
+    track: Option<Track>,
+    fading: Option<Fade>,
+    queue: Vec<Clip>,
+    /// Index in `queue` of the clip being played, if it came from the queue.
+    index: Option<usize>,
+    repeat: Repeat,
+    transition: Transition,
+    /// Frames of silence left before the clip at the given index starts.
+    gap: Option<(usize, usize)>,
+    /// Scratch space for the outgoing track of a crossfade, sized up front so
+    /// the audio thread does not allocate.
+    mix: Vec<f32>,
+    mixer: Mixer,
+    events: Sender<PlayerEvent>,
+    /// Asks the loader thread to open a queued clip, tagged with `generation`.
+    loader: Sender<(u64, usize)>,
+    /// Changes whenever the queue is replaced, so readers opened for an old
+    /// queue are thrown away.
+    generation: u64,
+    /// Index of the clip the loader was last asked to open.
+    requested: Option<usize>,
+    /// The clip at the given index, opened ahead of time by the loader.
+    next: Option<(usize, Opened)>,
+}
+
+impl Engine {
+    /// Creates an engine for a sink asking for up to `max_block_frames`
+    /// frames at a time.
+    pub fn new(
+        output_rate: u32,
+        output_channels: u16,
+        max_block_frames: usize,
+        events: Sender<PlayerEvent>,
+        loader: Sender<(u64, usize)>,
+    ) -> Engine {
+        let block_samples = max_block_frames * output_channels as usize;
+        Engine {
+            state: PlaybackState::Stopped,
+            volume: 1.0,
+            settings: Settings {
+                output_rate,
+                output_channels,
+                routing: None,
+                speed: 1.0,
+                skip_silence: None,
+            },
+            track: None,
+            fading: None,
+            queue: Vec::new(),
+            index: None,
+            repeat: Repeat::Off,
+            transition: Transition::None,
+            gap: None,
+            mix: Vec::with_capacity(block_samples),
+            mixer: Mixer::new(output_rate, block_samples),
+            events,
+            loader,
+            generation: 0,
+            requested: None,
+            next: None,
+        }
+    }
+
+    /// Loads `source` and starts playing it from its current position,
+    /// replacing the queue.
+    pub fn load(&mut self, source: Box<dyn FrameSource + Send>, clip: Option<ClipMetadata>) {
+        self.set_queue(Vec::new());
+        self.index = None;
+        self.start(Track::new(source, clip, &self.settings));
+    }
+
+    /// Replaces the queue with `clips` and starts playing the clip at `index`
+    /// from `source`.
+    pub fn load_queue(
+        &mut self,
+        clips: Vec<Clip>,
+        index: usize,
+        source: Box<dyn FrameSource + Send>,
+    ) {
+        self.set_queue(clips);
+        self.play_queued(index, source);
+    }
+
+    /// Starts playing the queued clip at `index` from `source`.
+    pub fn play_queued(&mut self, index: usize, source: Box<dyn FrameSource + Send>) {
+        let clip = self.queue.get(index).map(|c| c.metadata().clone());
+        self.index = Some(index);
+        self.start(Track::new(source, clip, &self.settings));
+        self.prefetch();
+    }
+
+    /// Unloads the source and clears the queue.
+    pub fn unload(&mut self) {
+        self.track = None;
+        self.fading = None;
+        self.gap = None;
+        self.set_queue(Vec::new());
+        self.index = None;
+        self.state = PlaybackState::Stopped;
+    }
+
+    /// Returns the track being played.
+    pub fn track(&self) -> Option<&Track> {
+        self.track.as_ref()
+    }
+
+    /// Moves the track to `frame`, dropping anything buffered.
+    pub fn seek(&mut self, frame: u64) -> Result<u64, anyhow::Error> {
+        let track = self
+            .track
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("nothing is loaded"))?;
+        self.fading = None;
+        self.gap = None;
+        track.seek(frame)
+    }
+
+    /// Returns the source frame heard next.
+    pub fn position(&self) -> u64 {
+        self.track.as_ref().map_or(0, |t| t.position())
+    }
+
+    /// Returns the queued clips.
+    pub fn queue(&self) -> &[Clip] {
+        &self.queue
+    }
+
+    /// Returns the index in the queue of the clip being played.
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    /// Returns the index of the clip after the current one.
+    ///
+    /// # Arguments
+    /// * `ended` - `true` when the current clip ran out, so [`Repeat::One`] applies.
+    pub fn next_index(&self, ended: bool) -> Option<usize> {
+        let index = self.index?;
+        if ended && self.repeat == Repeat::One {
+            Some(index)
+        } else if index + 1 < self.queue.len() {
+            Some(index + 1)
+        } else if self.repeat == Repeat::All {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the index of the clip before the current one.
+    pub fn previous_index(&self) -> Option<usize> {
+        match self.index? {
+            0 if self.repeat == Repeat::All => self.queue.len().checked_sub(1),
+            0 => None,
+            index => Some(index - 1),
+        }
+    }
+
+    /// Returns what happens when a clip ends.
+    pub fn repeat(&self) -> Repeat {
+        self.repeat
+    }
+
+    /// Sets what happens when a clip ends.
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+        self.prefetch();
+    }
+
+    /// Returns how one queued clip leads into the next.
+    pub fn transition(&self) -> Transition {
+        self.transition
+    }
+
+    /// Sets how one queued clip leads into the next.
+    pub fn set_transition(&mut self, transition: Transition) {
+        self.transition = transition;
+    }
+
+    /// Sets the playback speed, taking effect from the next buffer.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.settings.speed = if speed.is_finite() {
+            speed.clamp(MIN_SPEED, MAX_SPEED)
+        } else {
+            1.0
+        };
+        let settings = &self.settings;
+        for track in tracks(&mut self.track, &mut self.fading) {
+            track.set_speed(settings);
+        }
+    }
+
+    /// Returns the playback speed.
+    pub fn speed(&self) -> f64 {
+        self.settings.speed
+    }
+
+    /// Turns skipping of long silences on with the given thresholds, or off.
+    ///
+    /// Playback continues from the current position.
+    pub fn set_skip_silence(&mut self, config: Option<SilenceConfig>) -> Result<(), anyhow::Error> {
+        self.settings.skip_silence = config;
+        self.fading = None;
+        match self.track.as_mut() {
+            Some(track) => track.set_skip_silence(&self.settings),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the thresholds used to skip silences, if skipping is on.
+    pub fn skip_silence(&self) -> Option<SilenceConfig> {
+        self.settings.skip_silence
+    }
+
+    /// Sets the routing to use for sources with a matching channel count.
+    pub fn set_routing(&mut self, routing: Option<ChannelMap>) {
+        self.settings.routing = routing;
+        let settings = &self.settings;
+        for track in tracks(&mut self.track, &mut self.fading).chain(self.mixer.tracks()) {
+            track.set_routing(settings);
+        }
+    }
+
+    /// Fills `output` with the next frames of the source mixed with any voices,
+    /// or silence when nothing plays.
+    ///
+    /// # Returns
+    /// * `bool` - Whether a source or voice was playing.
+    pub fn render(&mut self, output: &mut [f32]) -> bool {
+        output.fill(0.0);
+        let channels = self.settings.output_channels as usize;
+        let playing = self.state == PlaybackState::Playing && self.track.is_some();
+        if playing {
+            self.render_main(output);
+        }
+        self.mixer.apply_main(output, channels);
+        let mixing = self.mixer.has_voices();
+        let events = &self.events;
+        self.mixer.mix_voices(output, channels, |id, result| {
+            let event = match result {
+                Ok(()) => PlayerEvent::VoiceFinished(id),
+                Err(e) => PlayerEvent::Error(format!("mixed source failed: {:#}", e)),
+            };
+            let _ = events.send(event);
+        });
+
+        if self.volume != 1.0 {
+            for sample in output.iter_mut() {
+                *sample *= self.volume;
+            }
+        }
+        playing || mixing
+    }
+
+    /// Starts `source` playing over the main playback, without the speed or
+    /// silence skipping of the main playback.
+    pub fn add_voice(&mut self, source: Box<dyn FrameSource + Send>, mix: MixSettings) -> VoiceId {
+        let settings = Settings {
+            speed: 1.0,
+            skip_silence: None,
+            ..self.settings.clone()
+        };
+        self.mixer.add(Track::new(source, None, &settings), mix)
+    }
+
+    /// Returns the clip the loader was asked to open, unless the queue has
+    /// been replaced since.
+    pub fn to_open(&self, generation: u64, index: usize) -> Option<Clip> {
+        if generation != self.generation || self.requested != Some(index) {
+            return None;
+        }
+        self.queue.get(index).cloned()
+    }
+
+    /// Takes a reader opened by the loader, unless the queue has moved on.
+    pub fn opened(&mut self, generation: u64, index: usize, reader: Opened) {
+        if generation == self.generation && self.requested == Some(index) {
+            self.next = Some((index, reader));
+        }
+    }
+
+    /// Returns the mixer for the main playback and the voices over it.
+    pub fn mixer(&mut self) -> &mut Mixer {
+        &mut self.mixer
+    }
+
+    /// Renders the main playback into `output`.
+    fn render_main(&mut self, output: &mut [f32]) {
+        let channels = self.settings.output_channels as usize;
+        let frames = output.len() / channels;
+
+        let mut written = 0;
+        // Tracks that ended without playing anything; enough of them in a row
+        // means the whole queue is empty and would loop forever.
+        let mut empty = 0;
+        while written < frames && self.state == PlaybackState::Playing {
+            let block = &mut output[written * channels..];
+            if let Some((left, index)) = self.gap {
+                let silence = left.min(frames - written);
+                written += silence;
+                self.gap = (silence < left).then_some((left - silence, index));
+                if self.gap.is_none()
+                    && !self.advance(index)
+                    && self.state == PlaybackState::Playing
+                {
+                    // The loader has not opened the clip yet; wait in silence.
+                    self.gap = Some((frames - written, index));
+                }
+                continue;
+            }
+            self.begin_crossfade();
+            let Some(track) = self.track.as_mut() else {
+                break;
+            };
+            let rendered = match track.render(block) {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    self.state = PlaybackState::Stopped;
+                    let _ = self.events.send(PlayerEvent::Error(format!("{:#}", e)));
+                    break;
+                }
+            };
+            if let Some(fade) = self.fading.as_mut() {
+                mix_crossfade(
+                    fade,
+                    &mut block[..rendered * channels],
+                    &mut self.mix,
+                    channels,
+                );
+                if fade.done >= fade.length {
+                    self.fading = None;
+                }
+            }
+            written += rendered;
+            if written < frames {
+                empty = if rendered == 0 { empty + 1 } else { 0 };
+                if empty > self.queue.len() + 1 {
+                    self.state = PlaybackState::Stopped;
+                    let _ = self.events.send(PlayerEvent::Finished);
+                    break;
+                }
+                self.track_ended();
+            }
+        }
+    }
+
+    /// Replaces the queue, forgetting any clip opened ahead of time.
+    fn set_queue(&mut self, clips: Vec<Clip>) {
+        self.queue = clips;
+        self.generation += 1;
+        self.requested = None;
+        self.next = None;
+    }
+
+    /// Asks the loader to open the clip that plays after the current one, so
+    /// moving on never opens files on the audio thread.
+    fn prefetch(&mut self) {
+        let wanted = self.next_index(true);
+        if wanted == self.requested {
+            return;
+        }
+        self.requested = wanted;
+        self.next = None;
+        if let Some(index) = wanted {
+            let _ = self.loader.send((self.generation, index));
+        }
+    }
+
+    /// Makes `track` the one playing, dropping any crossfade or gap.
+    fn start(&mut self, track: Track) {
+        self.track = Some(track);
+        self.fading = None;
+        self.gap = None;
+        self.state = PlaybackState::Playing;
+    }
+
+    /// Starts the next clip under the end of the current one when a crossfade is due.
+    fn begin_crossfade(&mut self) {
+        let Transition::Crossfade(length) = self.transition else {
+            return;
+        };
+        if self.fading.is_some() {
+            return;
+        }
+        let Some(remaining) = self.track.as_ref().and_then(|t| t.remaining()) else {
+            return;
+        };
+        let length = frames(length, self.settings.output_rate).min(remaining as usize);
+        if remaining as usize > length || length == 0 {
+            return;
+        }
+        let Some(index) = self.next_index(true) else {
+            return;
+        };
+        if let Some(outgoing) = self.track.take() {
+            if self.advance(index) {
+                self.fading = Some(Fade {
+                    track: outgoing,
+                    done: 0,
+                    length,
+                });
+            } else {
+                self.track = Some(outgoing);
+            }
+        }
+    }
+
+    /// Moves on after the current track ran out.
+    fn track_ended(&mut self) {
+        let next = match self.index {
+            Some(_) => self.next_index(true),
+            None if self.repeat != Repeat::Off => {
+                // A lone source repeats by starting over.
+                if self.seek(0).is_ok() {
+                    return;
+                }
+                None
+            }
+            None => None,
+        };
+        match (next, self.transition) {
+            (Some(index), Transition::Gap(gap)) => {
+                self.gap = Some((frames(gap, self.settings.output_rate), index));
+            }
+            (Some(index), _) => {
+                if !self.advance(index) && self.state == PlaybackState::Playing {
+                    self.gap = Some((0, index));
+                }
+            }
+            (None, _) => {
+                self.state = PlaybackState::Stopped;
+                let _ = self.events.send(PlayerEvent::Finished);
+            }
+        }
+    }
+
+    /// Starts playing the queued clip at `index` from the reader the loader
+    /// opened for it.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the clip started. If the reader failed to open,
+    ///   playback stops with an error; if it is not open yet, nothing changes.
+    fn advance(&mut self, index: usize) -> bool {
+        match self.next.take() {
+            Some((ready, opened)) if ready == index => {
+                self.requested = None;
+                match opened {
+                    Ok(reader) => {
+                        self.play_queued(index, reader);
+                        let _ = self.events.send(PlayerEvent::ClipChanged(index));
+                        true
+                    }
+                    Err(e) => {
+                        self.state = PlaybackState::Stopped;
+                        let _ = self.events.send(PlayerEvent::Error(format!("{:#}", e)));
+                        false
+                    }
+                }
+            }
+            other => {
+                self.next = other;
+                false
+            }
+        }
+    }
+}
+
+/// Starts the thread that opens queued clips for `engine` ahead of time.
+///
+/// The thread ends once the engine is dropped.
+pub(super) fn spawn_loader(engine: Weak<Mutex<Engine>>, requests: Receiver<(u64, usize)>) {
+    thread::spawn(move || {
+        for (generation, index) in requests {
+            let Some(engine) = engine.upgrade() else {
+                break;
+            };
+            let clip = engine.lock().unwrap().to_open(generation, index);
+            if let Some(clip) = clip {
+                let reader = ClipReader::open(&clip)
+                    .map(|reader| Box::new(reader) as Box<dyn FrameSource + Send>);
+                engine.lock().unwrap().opened(generation, index, reader);
+            }
+        }
+    });
+}
+
+/// Returns the playing track and the one fading out, if any.
+fn tracks<'a>(
+    track: &'a mut Option<Track>,
+    fading: &'a mut Option<Fade>,
+) -> impl Iterator<Item = &'a mut Track> {
+    track
+        .iter_mut()
+        .chain(fading.iter_mut().map(|fade| &mut fade.track))
+}
+
+/// Fades the incoming audio in `block` in and mixes the outgoing track under it.
+fn mix_crossfade(fade: &mut Fade, block: &mut [f32], mix: &mut Vec<f32>, channels: usize) {
+    mix.clear();
+    mix.resize(block.len(), 0.0);
+    // An outgoing track that errors or ends early just leaves silence.
+    let _ = fade.track.render(mix);
+    for (i, (new, old)) in block
+        .chunks_exact_mut(channels)
+        .zip(mix.chunks_exact(channels))
+        .enumerate()
+    {
+        let t = ((fade.done + i) as f32 / fade.length as f32).min(1.0);
+        for (n, o) in new.iter_mut().zip(old) {
+            *n = *n * t + *o * (1.0 - t);
+        }
+    }
+    fade.done += block.len() / channels;
+}
+
+/// Converts a duration to frames at `rate`.
+fn frames(duration: Duration, rate: u32) -> usize {
+    (duration.as_secs_f64() * rate as f64) as usize
+}
@@ -0,0 +1,250 @@
+use super::track::Track;
+use crate::dsp::db_to_gain;
+use std::f64::consts::{FRAC_PI_4, SQRT_2};
+use std::time::Duration;
+
+/// Identifies a source mixed over the main playback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VoiceId(u64);
+
+/// How a source is mixed into the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MixSettings {
+    /// Linear gain; 1.0 leaves the source unchanged.
+    pub gain: f32,
+    /// Position between the first two output channels, from -1.0 (left) to
+    /// 1.0 (right), as a balance control: centered sources play at full level
+    /// on both, and moving off center turns the other channel down.
+    pub pan: f32,
+    /// Turn the main playback down while this source plays, as for
+    /// notification sounds.
+    pub duck: bool,
+}
+
+impl Default for MixSettings {
+    fn default() -> Self {
+        MixSettings {
+            gain: 1.0,
+            pan: 0.0,
+            duck: false,
+        }
+    }
+}
+
+/// How far and how quickly the main playback is turned down while a ducking
+/// source plays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ducking {
+    /// Level of the main playback while ducked, in dB relative to normal.
+    pub depth_db: f32,
+    /// Time to fade down once a ducking source starts.
+    pub attack: Duration,
+    /// Time to fade back up once the last ducking source ends.
+    pub release: Duration,
+}
+
+impl Default for Ducking {
+    fn default() -> Self {
+        Ducking {
+            depth_db: -12.0,
+            attack: Duration::from_millis(20),
+            release: Duration::from_millis(300),
+        }
+    }
+}
+
+/// A source mixed over the main playback.
+struct Voice {
+    id: VoiceId,
+    track: Track,
+    settings: MixSettings,
+}
+
+/// Sums sources over the main playback, each with its own gain and pan, and
+/// ducks the main playback under sources that ask for it.
+pub(super) struct Mixer {
+    output_rate: u32,
+    main: MixSettings,
+    voices: Vec<Voice>,
+    next_id: u64,
+    ducking: Ducking,
+    /// Current gain applied to the main playback by ducking.
+    duck_gain: f32,
+    buf: Vec<f32>,
+}
+
+impl Mixer {
+    /// Creates a mixer with scratch space for blocks of up to `block_samples`
+    /// interleaved samples.
+    pub fn new(output_rate: u32, block_samples: usize) -> Mixer {
+        Mixer {
+            output_rate,
+            main: MixSettings::default(),
+            voices: Vec::new(),
+            next_id: 0,
+            ducking: Ducking::default(),
+            duck_gain: 1.0,
+            buf: Vec::with_capacity(block_samples),
+        }
+    }
+
+    /// Starts mixing `track` into the output.
+    pub fn add(&mut self, track: Track, settings: MixSettings) -> VoiceId {
+        let id = VoiceId(self.next_id);
+        self.next_id += 1;
+        self.voices.push(Voice {
+            id,
+            track,
+            settings,
+        });
+        id
+    }
+
+    /// Stops mixing the voice `id`.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the voice was still playing.
+    pub fn remove(&mut self, id: VoiceId) -> bool {
+        let count = self.voices.len();
+        self.voices.retain(|voice| voice.id != id);
+        self.voices.len() < count
+    }
+
+    /// Returns `true` while any voice is playing.
+    pub fn has_voices(&self) -> bool {
+        !self.voices.is_empty()
+    }
+
+    /// Returns the voices still playing, oldest first.
+    pub fn ids(&self) -> Vec<VoiceId> {
+        self.voices.iter().map(|voice| voice.id).collect()
+    }
+
+    /// Returns the mix settings of the voice `id`, if it is still playing.
+    pub fn settings_mut(&mut self, id: VoiceId) -> Option<&mut MixSettings> {
+        self.voices
+            .iter_mut()
+            .find(|voice| voice.id == id)
+            .map(|voice| &mut voice.settings)
+    }
+
+    /// Returns every voice's track.
+    pub fn tracks(&mut self) -> impl Iterator<Item = &mut Track> {
+        self.voices.iter_mut().map(|voice| &mut voice.track)
+    }
+
+    /// Returns the gain and pan of the main playback.
+    pub fn main(&self) -> MixSettings {
+        self.main
+    }
+
+    /// Sets the gain and pan of the main playback; its `duck` flag is ignored.
+    pub fn set_main(&mut self, main: MixSettings) {
+        self.main = main;
+    }
+
+    /// Returns how the main playback is ducked.
+    pub fn ducking(&self) -> Ducking {
+        self.ducking
+    }
+
+    /// Sets how the main playback is ducked.
+    pub fn set_ducking(&mut self, ducking: Ducking) {
+        self.ducking = ducking;
+    }
+
+    /// Applies the main gain, pan and ducking to the main playback in `output`.
+    pub fn apply_main(&mut self, output: &mut [f32], channels: usize) {
+        let ducked = self.voices.iter().any(|voice| voice.settings.duck);
+        let floor = db_to_gain(self.ducking.depth_db.min(0.0));
+        let (target, time) = if ducked {
+            (floor, self.ducking.attack)
+        } else {
+            (1.0, self.ducking.release)
+        };
+        let ramp_frames = (time.as_secs_f32() * self.output_rate as f32).max(1.0);
+        let step = (1.0 - floor) / ramp_frames;
+        let (left, right) = pan_gains(self.main.pan);
+
+        for frame in output.chunks_exact_mut(channels) {
+            self.duck_gain = if self.duck_gain > target {
+                (self.duck_gain - step).max(target)
+            } else {
+                (self.duck_gain + step).min(target)
+            };
+            let gain = self.main.gain * self.duck_gain;
+            apply_gains(frame, gain * left, gain * right, gain);
+        }
+    }
+
+    /// Adds every voice into `output`, removing those that end.
+    ///
+    /// # Arguments
+    /// * `ended` - Called with each voice that played to its end, or with the
+    ///   error that stopped it.
+    pub fn mix_voices(
+        &mut self,
+        output: &mut [f32],
+        channels: usize,
+        mut ended: impl FnMut(VoiceId, Result<(), anyhow::Error>),
+    ) {
+        let frames = output.len() / channels;
+        let buf = &mut self.buf;
+        buf.resize(output.len(), 0.0);
+        self.voices.retain_mut(|voice| {
+            buf.fill(0.0);
+            let rendered = match voice.track.render(buf) {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    ended(voice.id, Err(e));
+                    return false;
+                }
+            };
+            let (left, right) = pan_gains(voice.settings.pan);
+            let gain = voice.settings.gain;
+            for (out, frame) in output
+                .chunks_exact_mut(channels)
+                .zip(buf.chunks_exact_mut(channels))
+                .take(rendered)
+            {
+                apply_gains(frame, gain * left, gain * right, gain);
+                for (o, s) in out.iter_mut().zip(frame.iter()) {
+                    *o += s;
+                }
+            }
+            if rendered < frames {
+                ended(voice.id, Ok(()));
+                return false;
+            }
+            true
+        });
+    }
+}
+
+/// Returns the gains of the left and right channels for `pan`.
+///
+/// A balance control rather than a constant-power pan: the center leaves both
+/// channels at full level, and moving off it only turns the far side down
+/// smoothly, reaching silence at the end. A source panned hard to
+/// one side is therefore 3 dB quieter in total than one in the center. Worked
+/// out in `f64` so the center is exactly unity and leaves the audio untouched.
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) as f64 + 1.0) * FRAC_PI_4;
+    let scale = SQRT_2;
+    (
+        (angle.cos() * scale).min(1.0) as f32,
+        (angle.sin() * scale).min(1.0) as f32,
+    )
+}
+
+/// Scales the first two channels of `frame` by `left` and `right`, and any
+/// others by `rest`. Mono frames take `rest`, as pan does not apply.
+fn apply_gains(frame: &mut [f32], left: f32, right: f32, rest: f32) {
+    if frame.len() < 2 {
+        frame.iter_mut().for_each(|s| *s *= rest);
+        return;
+    }
+    frame[0] *= left;
+    frame[1] *= right;
+    frame[2..].iter_mut().for_each(|s| *s *= rest);
+}
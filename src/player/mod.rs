@@ -0,0 +1,665 @@
+mod engine;
+mod mixer;
+mod queue;
+mod sink;
+mod skip;
+mod track;
+
+use crate::audio_setup::{select_output_dev, DeviceSelector};
+use crate::decode::{Decoder, FrameSource};
+use crate::dsp::{ChannelMap, SilenceConfig};
+use crate::library::{Clip, ClipLibrary, ClipMetadata, ClipQuery, ClipReader};
+use anyhow::anyhow;
+use cpal::{Device, SampleRate, StreamConfig};
+use engine::Engine;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub use mixer::{Ducking, MixSettings, VoiceId};
+pub use queue::{Repeat, Transition};
+pub use sink::{CpalSink, MemorySink, MemorySinkHandle, OutputSink, Pacing, RenderFn};
+
+/// What the player is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    /// Nothing loaded, or the loaded source or queue has played to its end.
+    Stopped,
+    Playing,
+    Paused,
+}
+
+/// Notifications sent by the player from the audio thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlayerEvent {
+    /// The source, or the last clip of the queue, played to its end.
+    Finished,
+    /// The queue moved on to the clip at this index.
+    ClipChanged(usize),
+    /// Reading the source failed and playback stopped, or reading a source
+    /// mixed over it failed and it was dropped.
+    Error(String),
+    /// A source mixed over the main playback played to its end.
+    VoiceFinished(VoiceId),
+}
+
+/// Plays audio from any [`FrameSource`] on an output device.
+///
+/// Audio goes to an [`OutputSink`]: an output device, or a [`MemorySink`] that
+/// records it for checking without a sound card.
+///
+/// The output stream runs for the lifetime of the player; loading, pausing and
+/// seeking only change what the audio callback reads. Sources are resampled to
+/// the device's rate and their channels mapped onto the device's, so a mono
+/// 48 kHz clip plays correctly on a 44.1 kHz stereo device. Positions are
+/// frames of the source being played.
+///
+/// Clips can also be queued, from a list or a [`ClipQuery`], to play one after
+/// another with optional repeat and a gap or crossfade between them. The
+/// clip after the current one is opened ahead of time on a background thread;
+/// if it is not ready when needed, playback waits for it in silence.
+///
+/// Other sources, such as notification sounds, can be mixed over the main
+/// playback with their own gain and pan, turning it down while they play.
+pub struct Player {
+    _sink: Box<dyn OutputSink>,
+    config: StreamConfig,
+    engine: Arc<Mutex<Engine>>,
+    events: Receiver<PlayerEvent>,
+}
+
+impl Player {
+    /// Creates a player on the output device chosen by
+    /// [`select_output_dev`](crate::audio_setup::select_output_dev).
+    pub fn new() -> Result<Player, anyhow::Error> {
+        Player::with_device(&select_output_dev()?)
+    }
+
+    /// Creates a player on the output device picked by `selector`.
+    pub fn with_selector(selector: &DeviceSelector) -> Result<Player, anyhow::Error> {
+        Player::with_device(&selector.select_output()?)
+    }
+
+    /// Creates a player on `device`, using its default output configuration.
+    pub fn with_device(device: &Device) -> Result<Player, anyhow::Error> {
+        Player::with_sink(CpalSink::new(device.clone())?)
+    }
+
+    /// Creates a player that sends its audio to `sink`.
+    pub fn with_sink(mut sink: impl OutputSink + 'static) -> Result<Player, anyhow::Error> {
+        let config = StreamConfig {
+            channels: sink.channels(),
+            sample_rate: SampleRate(sink.sample_rate()),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let (events_tx, events) = channel();
+        let (loader_tx, loader) = channel();
+        let engine = Arc::new(Mutex::new(Engine::new(
+            config.sample_rate.0,
+            config.channels,
+            sink.max_block_frames(),
+            events_tx,
+            loader_tx,
+        )));
+        engine::spawn_loader(Arc::downgrade(&engine), loader);
+
+        let callback_engine = engine.clone();
+        sink.start(Box::new(move |data: &mut [f32]| {
+            // Never block the audio thread; a busy engine means one buffer of silence.
+            match callback_engine.try_lock() {
+                Ok(mut engine) => engine.render(data),
+                Err(_) => {
+                    data.fill(0.0);
+                    false
+                }
+            }
+        }))?;
+
+        Ok(Player {
+            _sink: Box::new(sink),
+            config,
+            engine,
+            events,
+        })
+    }
+
+    /// Starts playing `source` from its current position, replacing anything loaded.
+    pub fn play(&self, source: impl FrameSource + Send + 'static) {
+        self.load(Box::new(source), None);
+    }
+
+    /// Starts playing the audio file at `path`.
+    pub fn play_file(&self, path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        self.play(Decoder::open(path)?);
+        Ok(())
+    }
+
+    /// Starts playing `clip` from `library`, with its markers available for navigation.
+    ///
+    /// The clip replaces the queue, as a queue of one.
+    pub fn play_clip(&self, library: &ClipLibrary, clip: &Clip) -> Result<(), anyhow::Error> {
+        let reader = library.reader(clip)?;
+        let mut engine = self.engine.lock().unwrap();
+        engine.load_queue(vec![clip.clone()], 0, Box::new(reader));
+        while self.events.try_recv().is_ok() {}
+        Ok(())
+    }
+
+    /// Replaces the queue with `clips` and starts playing the first.
+    ///
+    /// When a clip ends the next one starts, according to
+    /// [`Player::set_repeat`] and [`Player::set_transition`].
+    pub fn play_queue(&self, clips: Vec<Clip>) -> Result<(), anyhow::Error> {
+        let first = clips.first().ok_or_else(|| anyhow!("the queue is empty"))?;
+        let reader = ClipReader::open(first)?;
+        let mut engine = self.engine.lock().unwrap();
+        engine.load_queue(clips, 0, Box::new(reader));
+        while self.events.try_recv().is_ok() {}
+        Ok(())
+    }
+
+    /// Queues the clips of `library` matching `query`, oldest first, and starts
+    /// playing the first.
+    ///
+    /// # Returns
+    /// * `usize` - The number of clips queued.
+    pub fn play_query(
+        &self,
+        library: &ClipLibrary,
+        query: &ClipQuery,
+    ) -> Result<usize, anyhow::Error> {
+        let clips = library.query(query)?;
+        let count = clips.len();
+        self.play_queue(clips)?;
+        Ok(count)
+    }
+
+    /// Skips to the next clip of the queue, wrapping around with [`Repeat::All`].
+    ///
+    /// # Returns
+    /// * `Option<usize>` - The index of the clip now playing, or `None` if there
+    ///   is no next clip.
+    pub fn next(&self) -> Result<Option<usize>, anyhow::Error> {
+        let target = {
+            let engine = self.engine.lock().unwrap();
+            engine
+                .next_index(false)
+                .map(|index| (index, engine.queue()[index].clone()))
+        };
+        target.map(|(index, clip)| self.play_queued(index, &clip)).transpose()
+    }
+
+    /// Goes back to the previous clip of the queue, wrapping around with
+    /// [`Repeat::All`].
+    ///
+    /// # Returns
+    /// * `Option<usize>` - The index of the clip now playing, or `None` if there
+    ///   is no previous clip.
+    pub fn previous(&self) -> Result<Option<usize>, anyhow::Error> {
+        let target = {
+            let engine = self.engine.lock().unwrap();
+            engine
+                .previous_index()
+                .map(|index| (index, engine.queue()[index].clone()))
+        };
+        target.map(|(index, clip)| self.play_queued(index, &clip)).transpose()
+    }
+
+    /// Returns the queued clips.
+    pub fn queue(&self) -> Vec<Clip> {
+        self.engine.lock().unwrap().queue().to_vec()
+    }
+
+    /// Returns the index in the queue of the clip being played, or `None` if a
+    /// lone source is playing.
+    pub fn queue_index(&self) -> Option<usize> {
+        self.engine.lock().unwrap().index()
+    }
+
+    /// Sets what happens when a clip or source ends.
+    ///
+    /// A lone source started with [`Player::play`] or [`Player::play_file`]
+    /// starts over with either [`Repeat::One`] or [`Repeat::All`].
+    pub fn set_repeat(&self, repeat: Repeat) {
+        self.engine.lock().unwrap().set_repeat(repeat);
+    }
+
+    /// Returns what happens when a clip or source ends.
+    pub fn repeat(&self) -> Repeat {
+        self.engine.lock().unwrap().repeat()
+    }
+
+    /// Sets how one queued clip leads into the next.
+    pub fn set_transition(&self, transition: Transition) {
+        self.engine.lock().unwrap().set_transition(transition);
+    }
+
+    /// Returns how one queued clip leads into the next.
+    pub fn transition(&self) -> Transition {
+        self.engine.lock().unwrap().transition()
+    }
+
+    /// Pauses playback, keeping the position.
+    pub fn pause(&self) {
+        let mut engine = self.engine.lock().unwrap();
+        if engine.state == PlaybackState::Playing {
+            engine.state = PlaybackState::Paused;
+        }
+    }
+
+    /// Resumes playback after [`Player::pause`], or replays a finished source
+    /// from wherever it was last seeked to.
+    pub fn resume(&self) {
+        let mut engine = self.engine.lock().unwrap();
+        if engine.track().is_some() {
+            engine.state = PlaybackState::Playing;
+        }
+    }
+
+    /// Stops playback and unloads the source. Mixed voices keep playing.
+    pub fn stop(&self) {
+        self.engine.lock().unwrap().unload();
+    }
+
+    /// Moves playback to `frame` of the source.
+    ///
+    /// # Returns
+    /// * `u64` - The frame playback continues from, clamped to the end of the source.
+    pub fn seek(&self, frame: u64) -> Result<u64, anyhow::Error> {
+        self.engine.lock().unwrap().seek(frame)
+    }
+
+    /// Moves playback to `secs` seconds into the source.
+    pub fn seek_secs(&self, secs: f64) -> Result<u64, anyhow::Error> {
+        let rate = self
+            .source_rate()
+            .ok_or_else(|| anyhow!("nothing is loaded"))?;
+        self.seek((secs.max(0.0) * rate as f64) as u64)
+    }
+
+    /// Jumps to the next marker of the clip being played.
+    ///
+    /// # Returns
+    /// * `Option<u64>` - The marker's frame, or `None` if there is no later marker.
+    pub fn next_marker(&self) -> Result<Option<u64>, anyhow::Error> {
+        let target = {
+            let engine = self.engine.lock().unwrap();
+            let position = engine.position();
+            engine
+                .track()
+                .and_then(|track| track.clip())
+                .and_then(|clip| clip.next_marker(position))
+                .map(|m| m.position)
+        };
+        target.map(|frame| self.seek(frame)).transpose()
+    }
+
+    /// Jumps to the previous marker of the clip being played.
+    ///
+    /// # Returns
+    /// * `Option<u64>` - The marker's frame, or `None` if there is no earlier marker.
+    pub fn previous_marker(&self) -> Result<Option<u64>, anyhow::Error> {
+        let target = {
+            let engine = self.engine.lock().unwrap();
+            let position = engine.position();
+            engine
+                .track()
+                .and_then(|track| track.clip())
+                .and_then(|clip| clip.previous_marker(position))
+                .map(|m| m.position)
+        };
+        target.map(|frame| self.seek(frame)).transpose()
+    }
+
+    /// Sets the playback gain; 1.0 plays the source unchanged.
+    pub fn set_volume(&self, volume: f32) {
+        self.engine.lock().unwrap().volume = volume.max(0.0);
+    }
+
+    /// Returns the playback gain.
+    pub fn volume(&self) -> f32 {
+        self.engine.lock().unwrap().volume
+    }
+
+    /// Sets the playback speed, from 0.5 to 3.0; 1.0 plays the source unchanged.
+    ///
+    /// Other speeds are time-stretched so voices keep their pitch. The speed
+    /// can be changed during playback and is kept for later sources.
+    pub fn set_speed(&self, speed: f64) {
+        self.engine.lock().unwrap().set_speed(speed);
+    }
+
+    /// Returns the playback speed.
+    pub fn speed(&self) -> f64 {
+        self.engine.lock().unwrap().speed()
+    }
+
+    /// Turns skip-silence playback on with the given thresholds, or off with `None`.
+    ///
+    /// Silences are detected ahead of the playhead exactly as when trimming
+    /// recordings; pass the same [`SilenceConfig`] to get the same results.
+    /// Any silence longer than twice the padding is shortened to the padding
+    /// on either side, joined by a short crossfade. Positions stay on the
+    /// source's own timeline, jumping forward over each skipped stretch.
+    pub fn set_skip_silence(&self, config: Option<SilenceConfig>) -> Result<(), anyhow::Error> {
+        self.engine.lock().unwrap().set_skip_silence(config)
+    }
+
+    /// Returns the thresholds used to skip silences, if skip-silence playback is on.
+    pub fn skip_silence(&self) -> Option<SilenceConfig> {
+        self.engine.lock().unwrap().skip_silence()
+    }
+
+    /// Routes source channels to device channels with `map`, or restores the
+    /// automatic mapping with `None`.
+    ///
+    /// The map applies to sources whose channel count matches its inputs and to
+    /// a device whose channel count matches its outputs; other sources use the
+    /// automatic mapping: mono to both front channels, anything to mono by
+    /// averaging, otherwise channel by channel.
+    pub fn set_channel_map(&self, map: Option<ChannelMap>) -> Result<(), anyhow::Error> {
+        if let Some(map) = &map {
+            if map.outputs() != self.config.channels {
+                return Err(anyhow!(
+                    "channel map has {} outputs but the device has {} channels",
+                    map.outputs(),
+                    self.config.channels
+                ));
+            }
+        }
+        self.engine.lock().unwrap().set_routing(map);
+        Ok(())
+    }
+
+    /// Starts `source` playing over the main playback.
+    ///
+    /// The source plays once at normal speed, whether or not anything else is
+    /// playing, and is not affected by pausing or stopping the main playback.
+    /// The volume applies to it as to everything else.
+    ///
+    /// # Returns
+    /// * `VoiceId` - Identifies the voice, until [`PlayerEvent::VoiceFinished`]
+    ///   is sent for it, or [`PlayerEvent::Error`] if reading it fails.
+    pub fn mix(&self, source: impl FrameSource + Send + 'static, settings: MixSettings) -> VoiceId {
+        self.engine
+            .lock()
+            .unwrap()
+            .add_voice(Box::new(source), settings)
+    }
+
+    /// Starts the audio file at `path` playing over the main playback.
+    pub fn mix_file(
+        &self,
+        path: impl AsRef<Path>,
+        settings: MixSettings,
+    ) -> Result<VoiceId, anyhow::Error> {
+        Ok(self.mix(Decoder::open(path)?, settings))
+    }
+
+    /// Starts `clip` from `library` playing over the main playback.
+    pub fn mix_clip(
+        &self,
+        library: &ClipLibrary,
+        clip: &Clip,
+        settings: MixSettings,
+    ) -> Result<VoiceId, anyhow::Error> {
+        Ok(self.mix(library.reader(clip)?, settings))
+    }
+
+    /// Changes the gain, pan or ducking of the voice `id` while it plays.
+    ///
+    /// # Returns
+    /// * `bool` - Whether the voice was still playing.
+    pub fn set_voice_mix(&self, id: VoiceId, settings: MixSettings) -> bool {
+        let mut engine = self.engine.lock().unwrap();
+        match engine.mixer().settings_mut(id) {
+            Some(current) => {
+                *current = settings;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stops the voice `id` without sending [`PlayerEvent::VoiceFinished`].
+    ///
+    /// # Returns
+    /// * `bool` - Whether the voice was still playing.
+    pub fn stop_voice(&self, id: VoiceId) -> bool {
+        self.engine.lock().unwrap().mixer().remove(id)
+    }
+
+    /// Returns the voices still playing, oldest first.
+    pub fn voices(&self) -> Vec<VoiceId> {
+        self.engine.lock().unwrap().mixer().ids()
+    }
+
+    /// Sets the gain and pan of the main playback; the `duck` flag is ignored.
+    pub fn set_main_mix(&self, settings: MixSettings) {
+        self.engine.lock().unwrap().mixer().set_main(settings);
+    }
+
+    /// Returns the gain and pan of the main playback.
+    pub fn main_mix(&self) -> MixSettings {
+        self.engine.lock().unwrap().mixer().main()
+    }
+
+    /// Sets how far and how quickly the main playback is turned down while a
+    /// voice with [`MixSettings::duck`] plays.
+    pub fn set_ducking(&self, ducking: Ducking) {
+        self.engine.lock().unwrap().mixer().set_ducking(ducking);
+    }
+
+    /// Returns how the main playback is ducked.
+    pub fn ducking(&self) -> Ducking {
+        self.engine.lock().unwrap().mixer().ducking()
+    }
+
+    /// Returns what the player is doing.
+    pub fn state(&self) -> PlaybackState {
+        self.engine.lock().unwrap().state
+    }
+
+    /// Returns the frame of the source that plays next, or 0 if nothing is loaded.
+    pub fn position(&self) -> u64 {
+        self.engine.lock().unwrap().position()
+    }
+
+    /// Returns the playback position in seconds.
+    pub fn position_secs(&self) -> f64 {
+        let engine = self.engine.lock().unwrap();
+        engine.track().map_or(0.0, |track| {
+            track.position() as f64 / track.source().info().sample_rate as f64
+        })
+    }
+
+    /// Returns the length of the loaded source in frames, if known.
+    pub fn duration(&self) -> Option<u64> {
+        let engine = self.engine.lock().unwrap();
+        engine.track().and_then(|t| t.source().info().total_frames)
+    }
+
+    /// Returns the next pending event without waiting.
+    pub fn try_event(&self) -> Option<PlayerEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Waits up to `timeout` for the next event.
+    pub fn wait_event(&self, timeout: Duration) -> Option<PlayerEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Returns the output format.
+    pub fn config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    fn source_rate(&self) -> Option<u32> {
+        let engine = self.engine.lock().unwrap();
+        engine.track().map(|t| t.source().info().sample_rate)
+    }
+
+    fn play_queued(&self, index: usize, clip: &Clip) -> Result<usize, anyhow::Error> {
+        let reader = ClipReader::open(clip)?;
+        self.engine
+            .lock()
+            .unwrap()
+            .play_queued(index, Box::new(reader));
+        Ok(index)
+    }
+
+    fn load(&self, source: Box<dyn FrameSource + Send>, clip: Option<ClipMetadata>) {
+        let mut engine = self.engine.lock().unwrap();
+        engine.load(source, clip);
+        // Events from the previous source are no longer meaningful.
+        while self.events.try_recv().is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{AudioFormat, AudioInfo};
+    use std::f32::consts::PI;
+
+    /// A sine wave of known length, generated on the fly.
+    struct Sine {
+        info: AudioInfo,
+        frequency: f32,
+        position: u64,
+    }
+
+    impl Sine {
+        fn new(sample_rate: u32, frequency: f32, frames: u64) -> Sine {
+            Sine {
+                info: AudioInfo {
+                    format: AudioFormat::Wav,
+                    sample_rate,
+                    channels: 1,
+                    total_frames: Some(frames),
+                    title: None,
+                    artist: None,
+                },
+                frequency,
+                position: 0,
+            }
+        }
+
+        /// Returns the sample at `frame`.
+        fn sample(&self, frame: u64) -> f32 {
+            0.5 * (2.0 * PI * self.frequency * frame as f32 / self.info.sample_rate as f32).sin()
+        }
+    }
+
+    impl FrameSource for Sine {
+        fn info(&self) -> &AudioInfo {
+            &self.info
+        }
+
+        fn read_frames(&mut self, buf: &mut [f32]) -> Result<usize, anyhow::Error> {
+            let total = self.info.total_frames.unwrap();
+            let frames = (buf.len() as u64).min(total - self.position) as usize;
+            for (i, sample) in buf[..frames].iter_mut().enumerate() {
+                *sample = self.sample(self.position + i as u64);
+            }
+            self.position += frames as u64;
+            Ok(frames)
+        }
+
+        fn seek(&mut self, frame: u64) -> Result<u64, anyhow::Error> {
+            self.position = frame.min(self.info.total_frames.unwrap());
+            Ok(self.position)
+        }
+
+        fn position(&self) -> u64 {
+            self.position
+        }
+    }
+
+    /// Counts sign changes of the first channel of interleaved `samples`.
+    fn zero_crossings(samples: &[f32], channels: usize) -> usize {
+        samples
+            .chunks_exact(channels)
+            .map(|frame| frame[0] >= 0.0)
+            .collect::<Vec<_>>()
+            .windows(2)
+            .filter(|pair| pair[0] != pair[1])
+            .count()
+    }
+
+    #[test]
+    fn resamples_mono_source_to_stereo_device() {
+        let sink = MemorySink::new(48_000, 2, Pacing::Manual);
+        let handle = sink.handle();
+        let player = Player::with_sink(sink).unwrap();
+        player.play(Sine::new(44_100, 1000.0, 44_100));
+
+        while player.state() == PlaybackState::Playing {
+            handle.pull(480).unwrap();
+        }
+        let output = handle.take();
+        let last = output.iter().rposition(|s| *s != 0.0).unwrap() / 2;
+        // One second of audio, give or take the filter's ringing at the end.
+        assert!((47_990..=48_010).contains(&last), "ends at frame {}", last);
+
+        let second = &output[..48_000 * 2];
+        for frame in second.chunks_exact(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+        // 1 kHz crosses zero twice per cycle.
+        let crossings = zero_crossings(second, 2);
+        assert!((1998..=2002).contains(&crossings), "{} crossings", crossings);
+    }
+
+    /// Creates a player on a manually pulled 48 kHz mono sink, playing a sine
+    /// of `frames` frames at the same rate so output samples match the source.
+    fn manual_player(frames: u64) -> (Player, MemorySinkHandle, Sine) {
+        let sink = MemorySink::new(48_000, 1, Pacing::Manual);
+        let handle = sink.handle();
+        let player = Player::with_sink(sink).unwrap();
+        player.play(Sine::new(48_000, 440.0, frames));
+        (player, handle, Sine::new(48_000, 440.0, frames))
+    }
+
+    #[test]
+    fn seek_lands_on_exact_frame() {
+        let (player, handle, sine) = manual_player(48_000);
+        handle.pull(100).unwrap();
+        assert_eq!(player.seek(10_000).unwrap(), 10_000);
+        assert_eq!(player.position(), 10_000);
+        let output = handle.pull(64).unwrap();
+        for (i, sample) in output.iter().enumerate() {
+            assert_eq!(*sample, sine.sample(10_000 + i as u64));
+        }
+        assert_eq!(player.position(), 10_064);
+    }
+
+    #[test]
+    fn pause_is_silent_and_resume_continues() {
+        let (player, handle, sine) = manual_player(48_000);
+        handle.pull(1000).unwrap();
+        player.pause();
+        assert!(handle.pull(500).unwrap().iter().all(|s| *s == 0.0));
+        assert_eq!(player.position(), 1000);
+        player.resume();
+        let output = handle.pull(10).unwrap();
+        for (i, sample) in output.iter().enumerate() {
+            assert_eq!(*sample, sine.sample(1000 + i as u64));
+        }
+    }
+
+    #[test]
+    fn finishes_after_total_frames() {
+        let (player, handle, sine) = manual_player(5000);
+        handle.pull(4999).unwrap();
+        assert_eq!(player.try_event(), None);
+        handle.pull(2).unwrap();
+        assert_eq!(player.try_event(), Some(PlayerEvent::Finished));
+        assert_eq!(player.state(), PlaybackState::Stopped);
+
+        let output = handle.take();
+        assert_eq!(output[4999], sine.sample(4999));
+        assert_eq!(output[5000], 0.0);
+    }
+}
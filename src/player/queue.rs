@@ -0,0 +1,25 @@
+use std::time::Duration;
+
+/// What the player does when a clip ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Repeat {
+    /// Move on to the next clip of the queue, and stop after the last.
+    #[default]
+    Off,
+    /// Play the same clip again.
+    One,
+    /// Move on to the next clip, starting over after the last.
+    All,
+}
+
+/// How one clip of the queue leads into the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transition {
+    /// The next clip starts right after the previous one.
+    #[default]
+    None,
+    /// A pause of the given length between clips.
+    Gap(Duration),
+    /// The next clip fades in over the end of the previous one, which fades out.
+    Crossfade(Duration),
+}
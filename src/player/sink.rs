@@ -0,0 +1,296 @@
+use anyhow::anyhow;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{BufferSize, Device, Stream, StreamConfig, SupportedBufferSize};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Fills an interleaved output buffer.
+///
+/// Returns `false` when nothing is playing and the buffer was filled with silence.
+pub type RenderFn = Box<dyn FnMut(&mut [f32]) -> bool + Send>;
+
+/// Frames per block assumed for sinks that do not say.
+const DEFAULT_MAX_BLOCK_FRAMES: usize = 4096;
+
+/// Where the player's audio goes.
+///
+/// A sink pulls audio by calling the render function it is started with, from
+/// whatever thread and in whatever block sizes suit it.
+pub trait OutputSink {
+    /// Returns the sample rate the sink plays at.
+    fn sample_rate(&self) -> u32;
+
+    /// Returns the number of interleaved channels the sink plays.
+    fn channels(&self) -> u16;
+
+    /// Returns the most frames the sink is expected to ask for at a time, so
+    /// scratch space can be set aside before the audio starts.
+    ///
+    /// Larger requests still work, but allocate on the audio thread.
+    fn max_block_frames(&self) -> usize {
+        DEFAULT_MAX_BLOCK_FRAMES
+    }
+
+    /// Starts pulling audio from `render` until the sink is dropped.
+    fn start(&mut self, render: RenderFn) -> Result<(), anyhow::Error>;
+}
+
+/// Plays on an output device through cpal.
+pub struct CpalSink {
+    device: Device,
+    config: StreamConfig,
+    buffer_sizes: SupportedBufferSize,
+    stream: Option<Stream>,
+}
+
+impl CpalSink {
+    /// Creates a sink on `device`, using its default output configuration.
+    pub fn new(device: Device) -> Result<CpalSink, anyhow::Error> {
+        let supported = device.default_output_config()?;
+        Ok(CpalSink {
+            device,
+            buffer_sizes: *supported.buffer_size(),
+            config: supported.into(),
+            stream: None,
+        })
+    }
+
+    /// Asks the device for blocks of `frames` frames, clamped to the sizes it
+    /// supports, instead of its default. Smaller blocks mean less delay.
+    ///
+    /// Devices that do not report their supported sizes keep the default.
+    pub fn with_buffer_frames(mut self, frames: u32) -> CpalSink {
+        if let SupportedBufferSize::Range { min, max } = self.buffer_sizes {
+            self.config.buffer_size = BufferSize::Fixed(frames.clamp(min, max));
+        }
+        self
+    }
+}
+
+impl OutputSink for CpalSink {
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate.0
+    }
+
+    fn channels(&self) -> u16 {
+        self.config.channels
+    }
+
+    fn max_block_frames(&self) -> usize {
+        match self.config.buffer_size {
+            BufferSize::Fixed(frames) => frames as usize,
+            BufferSize::Default => DEFAULT_MAX_BLOCK_FRAMES,
+        }
+    }
+
+    fn start(&mut self, mut render: RenderFn) -> Result<(), anyhow::Error> {
+        let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+        let stream = self.device.build_output_stream(
+            &self.config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                render(data);
+            },
+            err_fn,
+            None,
+        )?;
+        stream.play()?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+/// How a [`MemorySink`] pulls audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// Only when [`MemorySinkHandle::pull`] is called, for sample-exact checks.
+    Manual,
+    /// In blocks on a background thread, at the rate a device would.
+    RealTime,
+    /// In blocks on a background thread, as fast as rendering allows. Blocks
+    /// rendered while nothing plays are not recorded, and are pulled at the
+    /// rate a device would rather than as fast as possible.
+    Fast,
+}
+
+/// State shared between a memory sink, its handle and its pulling thread.
+struct MemoryShared {
+    channels: u16,
+    render: Mutex<Option<RenderFn>>,
+    output: Mutex<Vec<f32>>,
+    stop: AtomicBool,
+}
+
+impl MemoryShared {
+    /// Renders `frames` frames and records them if `keep_idle` or something played.
+    ///
+    /// # Returns
+    /// * `(Vec<f32>, bool)` - The interleaved samples rendered, and whether
+    ///   something played.
+    fn pull(&self, frames: usize, keep_idle: bool) -> Result<(Vec<f32>, bool), anyhow::Error> {
+        let mut block = vec![0.0; frames * self.channels as usize];
+        let active = match self.render.lock().unwrap().as_mut() {
+            Some(render) => render(&mut block),
+            None => return Err(anyhow!("the sink has not been started")),
+        };
+        if active || keep_idle {
+            self.output.lock().unwrap().extend_from_slice(&block);
+        }
+        Ok((block, active))
+    }
+}
+
+/// Records the player's output in memory instead of playing it.
+///
+/// Lets playback run without a sound card, at real-time speed, as fast as
+/// possible, or one pull at a time. Use [`MemorySink::handle`] to pull and read
+/// back the audio after the sink has been handed to the player.
+pub struct MemorySink {
+    sample_rate: u32,
+    pacing: Pacing,
+    block_frames: usize,
+    shared: Arc<MemoryShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MemorySink {
+    /// Creates a sink with the given format, pulling in blocks of 512 frames.
+    pub fn new(sample_rate: u32, channels: u16, pacing: Pacing) -> MemorySink {
+        MemorySink {
+            sample_rate: sample_rate.max(1),
+            pacing,
+            block_frames: 512,
+            shared: Arc::new(MemoryShared {
+                channels: channels.max(1),
+                render: Mutex::new(None),
+                output: Mutex::new(Vec::new()),
+                stop: AtomicBool::new(false),
+            }),
+            thread: None,
+        }
+    }
+
+    /// Sets the number of frames pulled at a time by the background thread.
+    pub fn with_block_frames(mut self, frames: usize) -> MemorySink {
+        self.block_frames = frames.max(1);
+        self
+    }
+
+    /// Returns a handle for pulling and reading back the recorded audio.
+    pub fn handle(&self) -> MemorySinkHandle {
+        MemorySinkHandle {
+            sample_rate: self.sample_rate,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl OutputSink for MemorySink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.shared.channels
+    }
+
+    fn max_block_frames(&self) -> usize {
+        self.block_frames
+    }
+
+    fn start(&mut self, render: RenderFn) -> Result<(), anyhow::Error> {
+        *self.shared.render.lock().unwrap() = Some(render);
+        if self.pacing == Pacing::Manual {
+            return Ok(());
+        }
+
+        let shared = self.shared.clone();
+        let (pacing, block_frames, rate) = (self.pacing, self.block_frames, self.sample_rate);
+        let block = Duration::from_secs_f64(block_frames as f64 / rate as f64);
+        self.thread = Some(thread::spawn(move || {
+            let started = Instant::now();
+            let mut pulled = 0u64;
+            while !shared.stop.load(Ordering::Relaxed) {
+                let active = shared
+                    .pull(block_frames, pacing == Pacing::RealTime)
+                    .is_ok_and(|(_, active)| active);
+                pulled += block_frames as u64;
+                match pacing {
+                    Pacing::RealTime => {
+                        let due = Duration::from_secs_f64(pulled as f64 / rate as f64);
+                        if let Some(wait) = due.checked_sub(started.elapsed()) {
+                            thread::sleep(wait);
+                        }
+                    }
+                    // Nothing to render until something is loaded, resumed or
+                    // mixed in; wait a block's worth instead of spinning.
+                    _ if !active => thread::sleep(block),
+                    _ => thread::yield_now(),
+                }
+            }
+        }));
+        Ok(())
+    }
+}
+
+impl Drop for MemorySink {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Pulls audio from a [`MemorySink`] and reads back what it recorded.
+#[derive(Clone)]
+pub struct MemorySinkHandle {
+    sample_rate: u32,
+    shared: Arc<MemoryShared>,
+}
+
+impl MemorySinkHandle {
+    /// Renders the next `frames` frames, recording them like any other block.
+    ///
+    /// Meant for [`Pacing::Manual`] sinks, where nothing plays between pulls.
+    ///
+    /// # Returns
+    /// * `Vec<f32>` - The interleaved samples rendered.
+    pub fn pull(&self, frames: usize) -> Result<Vec<f32>, anyhow::Error> {
+        Ok(self.shared.pull(frames, true)?.0)
+    }
+
+    /// Returns the number of frames recorded so far.
+    pub fn frames(&self) -> usize {
+        self.shared.output.lock().unwrap().len() / self.shared.channels as usize
+    }
+
+    /// Returns a copy of the interleaved samples recorded so far.
+    pub fn samples(&self) -> Vec<f32> {
+        self.shared.output.lock().unwrap().clone()
+    }
+
+    /// Returns the samples recorded so far and clears the recording.
+    pub fn take(&self) -> Vec<f32> {
+        std::mem::take(&mut *self.shared.output.lock().unwrap())
+    }
+
+    /// Writes the samples recorded so far to `path` as a 32-bit float WAV file.
+    pub fn write_wav(&self, path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+        let spec = WavSpec {
+            channels: self.shared.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(path, spec)?;
+        for &sample in self.shared.output.lock().unwrap().iter() {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use crate::dsp::{db_to_gain, SilenceConfig};
+use std::time::Duration;
+
+/// Length of the crossfade over a shortened silence.
+const FADE: Duration = Duration::from_millis(10);
+
+/// Shortens long silences in a stream of frames, for skip-silence playback.
+///
+/// Frames are classified in windows exactly as [`SilenceDetector`] classifies
+/// recordings. A silent stretch longer than twice the padding plus the fade is
+/// cut down to the padding on either side, joined by a short crossfade, so
+/// speech keeps its natural lead-in and tail.
+///
+/// [`SilenceDetector`]: crate::dsp::SilenceDetector
+pub(super) struct SilenceSkipper {
+    channels: usize,
+    threshold: f32,
+    window_frames: usize,
+    padding: usize,
+    fade: usize,
+    /// The window being filled.
+    window: Vec<f32>,
+    /// Silent frames not yet written. Once it grows past the head and tail it
+    /// would keep, the middle is dropped.
+    held: Vec<f32>,
+    /// Frames dropped from the middle of `held`.
+    dropped: u64,
+}
+
+impl SilenceSkipper {
+    pub fn new(config: &SilenceConfig, sample_rate: u32, channels: u16) -> SilenceSkipper {
+        let frames = |d: Duration| (d.as_secs_f64() * sample_rate as f64) as usize;
+        SilenceSkipper {
+            channels: channels.max(1) as usize,
+            threshold: db_to_gain(config.threshold_db),
+            window_frames: frames(config.window).max(1),
+            padding: frames(config.padding),
+            fade: frames(FADE).max(1),
+            window: Vec::new(),
+            held: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Feeds interleaved frames in and appends what is kept to `out`.
+    ///
+    /// Each cut is pushed to `cuts` as the frame of this call's output where it
+    /// happens and the number of input frames skipped there.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>, cuts: &mut Vec<(usize, u64)>) {
+        let ch = self.channels;
+        let base = out.len();
+        let window_len = self.window_frames * ch;
+        let mut input = input;
+        while !input.is_empty() {
+            let take = (window_len - self.window.len()).min(input.len());
+            self.window.extend_from_slice(&input[..take]);
+            input = &input[take..];
+            if self.window.len() == window_len {
+                self.close_window(out, base, cuts);
+            }
+        }
+    }
+
+    /// Writes everything still held once the input has ended.
+    pub fn flush(&mut self, out: &mut Vec<f32>, cuts: &mut Vec<(usize, u64)>) {
+        let base = out.len();
+        if !self.window.is_empty() {
+            self.close_window(out, base, cuts);
+        }
+        self.release(out, base, cuts);
+    }
+
+    fn close_window(&mut self, out: &mut Vec<f32>, base: usize, cuts: &mut Vec<(usize, u64)>) {
+        let mean_square =
+            self.window.iter().map(|&s| (s * s) as f64).sum::<f64>() / self.window.len() as f64;
+        if (mean_square.sqrt() as f32) < self.threshold {
+            self.held.append(&mut self.window);
+            let ch = self.channels;
+            let keep = self.padding + self.fade;
+            let excess = (self.held.len() / ch).saturating_sub(2 * keep);
+            if excess > 0 {
+                self.held.drain(keep * ch..(keep + excess) * ch);
+                self.dropped += excess as u64;
+            }
+        } else {
+            self.release(out, base, cuts);
+            out.append(&mut self.window);
+        }
+    }
+
+    /// Writes the held silence, crossfading over the dropped middle if any.
+    fn release(&mut self, out: &mut Vec<f32>, base: usize, cuts: &mut Vec<(usize, u64)>) {
+        let ch = self.channels;
+        if self.dropped == 0 {
+            out.append(&mut self.held);
+            return;
+        }
+        let (padding, fade) = (self.padding, self.fade);
+        out.extend_from_slice(&self.held[..padding * ch]);
+        cuts.push(((out.len() - base) / ch, self.dropped + fade as u64));
+        let tail = padding + fade;
+        for i in 0..fade {
+            let t = (i as f32 + 0.5) / fade as f32;
+            for c in 0..ch {
+                let from = self.held[(padding + i) * ch + c];
+                let to = self.held[(tail + i) * ch + c];
+                out.push(from * (1.0 - t) + to * t);
+            }
+        }
+        out.extend_from_slice(&self.held[(tail + fade) * ch..]);
+        self.held.clear();
+        self.dropped = 0;
+    }
+}
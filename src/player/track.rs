@@ -0,0 +1,256 @@
+use super::skip::SilenceSkipper;
+use crate::decode::FrameSource;
+use crate::dsp::{ChannelMap, Resampler, SilenceConfig, TimeStretch};
+use crate::library::ClipMetadata;
+use std::collections::VecDeque;
+
+/// Source frames read at a time.
+const READ_FRAMES: usize = 1024;
+
+/// Player settings that shape how every track is rendered.
+#[derive(Clone)]
+pub(super) struct Settings {
+    pub output_rate: u32,
+    pub output_channels: u16,
+    /// Routing chosen by the user, used for sources it fits.
+    pub routing: Option<ChannelMap>,
+    pub speed: f64,
+    pub skip_silence: Option<SilenceConfig>,
+}
+
+/// One source being played, with its processing chain.
+///
+/// The source has long silences shortened if skipping is on, is time-stretched
+/// to the playback speed, resampled to the output rate and its channels mapped
+/// to the output's, so any source plays at the right speed and pitch on any
+/// device.
+pub(super) struct Track {
+    source: Box<dyn FrameSource + Send>,
+    /// Metadata of the clip being played, for marker navigation.
+    clip: Option<ClipMetadata>,
+    output_rate: u32,
+    map: ChannelMap,
+    skip_silence: Option<SilenceConfig>,
+    skipper: Option<SilenceSkipper>,
+    resampler: Resampler,
+    speed: f64,
+    stretch: TimeStretch,
+    read_buf: Vec<f32>,
+    skipped: Vec<f32>,
+    stretched: Vec<f32>,
+    cuts: Vec<(usize, u64)>,
+    /// Resampled frames, still in the source's channel layout, waiting to be played.
+    pending: Vec<f32>,
+    pending_pos: usize,
+    /// Set once the source is exhausted and the resampler flushed.
+    drained: bool,
+    /// Frames passed on by the skipper, counted from where playback started.
+    fed: u64,
+    /// Frames of that stream heard so far; fractional while resampling.
+    played: f64,
+    /// Cuts made by the skipper not yet reached, as the frame of the stream
+    /// where they happen and the number of source frames skipped there.
+    jumps: VecDeque<(u64, u64)>,
+    /// Source frames skipped before the current playback point.
+    jumped: u64,
+}
+
+impl Track {
+    /// Prepares `source` to play from its current position.
+    pub fn new(
+        source: Box<dyn FrameSource + Send>,
+        clip: Option<ClipMetadata>,
+        settings: &Settings,
+    ) -> Track {
+        let info = source.info();
+        let (rate, channels) = (info.sample_rate, info.channels);
+        let mut stretch = TimeStretch::new(rate, channels);
+        stretch.set_speed(settings.speed);
+        let start = source.position();
+        let mut track = Track {
+            source,
+            clip,
+            output_rate: settings.output_rate,
+            map: ChannelMap::auto(channels, settings.output_channels),
+            skip_silence: settings.skip_silence,
+            skipper: None,
+            resampler: Resampler::new(rate, settings.output_rate, channels),
+            speed: stretch.speed(),
+            stretch,
+            read_buf: Vec::new(),
+            skipped: Vec::new(),
+            stretched: Vec::new(),
+            cuts: Vec::new(),
+            pending: Vec::new(),
+            pending_pos: 0,
+            drained: false,
+            fed: 0,
+            played: 0.0,
+            jumps: VecDeque::new(),
+            jumped: 0,
+        };
+        track.set_routing(settings);
+        track.restart(start);
+        track
+    }
+
+    /// Returns the source being played.
+    pub fn source(&self) -> &(dyn FrameSource + Send) {
+        self.source.as_ref()
+    }
+
+    /// Returns the metadata of the clip being played, if the source is a clip.
+    pub fn clip(&self) -> Option<&ClipMetadata> {
+        self.clip.as_ref()
+    }
+
+    /// Moves the source to `frame`, dropping anything buffered.
+    pub fn seek(&mut self, frame: u64) -> Result<u64, anyhow::Error> {
+        let frame = self.source.seek(frame)?;
+        self.restart(frame);
+        Ok(frame)
+    }
+
+    /// Returns the source frame heard next.
+    pub fn position(&self) -> u64 {
+        self.played as u64 + self.jumped
+    }
+
+    /// Returns roughly how many output frames are left to play, if the length
+    /// of the source is known.
+    pub fn remaining(&self) -> Option<u64> {
+        let total = self.source.info().total_frames?;
+        let left = total.saturating_sub(self.position()) as f64;
+        Some((left / self.step()) as u64)
+    }
+
+    /// Applies the speed from `settings`, taking effect from the next buffer.
+    pub fn set_speed(&mut self, settings: &Settings) {
+        self.stretch.set_speed(settings.speed);
+        self.speed = self.stretch.speed();
+    }
+
+    /// Applies the skip-silence thresholds from `settings`, continuing from the
+    /// current position.
+    pub fn set_skip_silence(&mut self, settings: &Settings) -> Result<(), anyhow::Error> {
+        self.skip_silence = settings.skip_silence;
+        self.seek(self.position()).map(|_| ())
+    }
+
+    /// Applies the routing from `settings` if it fits the source.
+    pub fn set_routing(&mut self, settings: &Settings) {
+        let inputs = self.source.info().channels;
+        self.map = match &settings.routing {
+            Some(map) if map.inputs() == inputs && map.outputs() == settings.output_channels => {
+                map.clone()
+            }
+            _ => ChannelMap::auto(inputs, settings.output_channels),
+        };
+    }
+
+    /// Writes the next frames of the track to `output`.
+    ///
+    /// # Returns
+    /// * `usize` - The number of frames written; fewer than `output` holds once
+    ///   the track has ended.
+    pub fn render(&mut self, output: &mut [f32]) -> Result<usize, anyhow::Error> {
+        let in_channels = self.source.info().channels as usize;
+        let out_channels = self.map.outputs() as usize;
+        let frames = output.len() / out_channels;
+        let step = self.step();
+
+        let mut written = 0;
+        while written < frames {
+            if self.pending_pos == self.pending.len() {
+                self.pending.clear();
+                self.pending_pos = 0;
+                if self.drained {
+                    break;
+                }
+                self.refill()?;
+                continue;
+            }
+            let available = (self.pending.len() - self.pending_pos) / in_channels;
+            let take = available.min(frames - written);
+            self.map.apply(
+                &self.pending[self.pending_pos..self.pending_pos + take * in_channels],
+                &mut output[written * out_channels..(written + take) * out_channels],
+            );
+            self.pending_pos += take * in_channels;
+            written += take;
+            self.played = (self.played + take as f64 * step).min(self.fed as f64);
+            while let Some(&(at, skipped)) = self.jumps.front() {
+                if at as f64 > self.played {
+                    break;
+                }
+                self.jumped += skipped;
+                self.jumps.pop_front();
+            }
+        }
+        Ok(written)
+    }
+
+    /// Source frames played per output frame.
+    fn step(&self) -> f64 {
+        self.speed * self.source.info().sample_rate as f64 / self.output_rate as f64
+    }
+
+    /// Reads the next block of the source and runs it through skipping,
+    /// stretching and resampling into `pending`.
+    fn refill(&mut self) -> Result<(), anyhow::Error> {
+        let channels = self.source.info().channels as usize;
+        self.read_buf.resize(READ_FRAMES * channels, 0.0);
+        let read = self.source.read_frames(&mut self.read_buf)?;
+        let end = read == 0;
+
+        let mut frames = &self.read_buf[..read * channels];
+        if let Some(skipper) = self.skipper.as_mut() {
+            self.skipped.clear();
+            self.cuts.clear();
+            if end {
+                skipper.flush(&mut self.skipped, &mut self.cuts);
+            } else {
+                skipper.process(frames, &mut self.skipped, &mut self.cuts);
+            }
+            for &(at, skipped) in &self.cuts {
+                self.jumps.push_back((self.fed + at as u64, skipped));
+            }
+            frames = &self.skipped;
+        }
+        self.fed += (frames.len() / channels) as u64;
+
+        // Normal speed bypasses the stretcher unless it still holds audio.
+        if self.speed != 1.0 || self.stretch.is_primed() {
+            self.stretched.clear();
+            self.stretch.process(frames, &mut self.stretched);
+            if end {
+                self.stretch.finish(&mut self.stretched);
+            }
+            frames = &self.stretched;
+        }
+
+        self.resampler.process(frames, &mut self.pending);
+        if end {
+            self.resampler.finish(&mut self.pending);
+            self.drained = true;
+        }
+        Ok(())
+    }
+
+    /// Drops everything buffered and restarts the chain at source frame `frame`.
+    fn restart(&mut self, frame: u64) {
+        self.resampler.reset();
+        self.stretch.reset();
+        let info = self.source.info();
+        self.skipper = self
+            .skip_silence
+            .map(|config| SilenceSkipper::new(&config, info.sample_rate, info.channels));
+        self.pending.clear();
+        self.pending_pos = 0;
+        self.drained = false;
+        self.fed = frame;
+        self.played = frame as f64;
+        self.jumps.clear();
+        self.jumped = 0;
+    }
+}
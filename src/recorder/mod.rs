@@ -1,18 +1,80 @@
-use crate::audio_setup::{setup_input_config, setup_live_input};
+pub mod monitor;
+mod ring;
+pub mod segment;
+
+use crate::audio_setup::{
+    measure_latency, setup_input_config, DeviceLoopback, LatencyConfig, LatencyMeasurement,
+};
+use crate::library::{ClipLibrary, FinishedRecording};
+use crate::player::{CpalSink, OutputSink};
 use crate::utils::init_ringbuffer;
-use cpal::traits::StreamTrait;
-use cpal::Stream;
-use ringbuffer::AllocRingBuffer;
+use anyhow::anyhow;
+use audio_visualizer::dynamic::live_input::AudioDevAndCfg;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{Device, Stream, StreamConfig};
+use hound::{SampleFormat, WavSpec};
+use monitor::{Monitor, MonitorConfig, MonitorInput, MonitorStatus, MONITOR_BUFFER_FRAMES};
+use ring::SampleRing;
+use ringbuffer::{AllocRingBuffer, RingBuffer};
+use segment::{RolloverPolicy, SegmentWriter};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+type SegmentWriterHandle = Arc<Mutex<Option<Recording>>>;
+type MonitorInputHandle = Arc<Mutex<Option<MonitorInput>>>;
+
+/// Seconds of input queued for the writer thread before input is dropped.
+const CAPTURE_BUFFER_SECS: usize = 4;
+
+/// How long the writer thread sleeps when it has caught up with the input.
+const WRITE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A recording in progress, shared with the writer thread.
+struct Recording {
+    writer: SegmentWriter,
+    /// The first write that failed; no input is written after it.
+    error: Option<anyhow::Error>,
+}
+
+/// Input handed from the input callback to the thread writing the recording,
+/// so the callback never waits on the disk.
+struct Capture {
+    ring: SampleRing,
+    /// Whether captured input is queued for the recording.
+    recording: AtomicBool,
+    /// Set once input was dropped because the writer thread fell behind.
+    overflowed: AtomicBool,
+    /// Frames queued for the recording so far.
+    frames: AtomicU64,
+    /// Tells the writer thread to finish once the queue is empty.
+    stop: AtomicBool,
+}
 
 /// A struct that manages audio recording using CPAL.
 ///
 /// The `Recorder` struct is responsible for handling audio recording, including
 /// setting up the stream, storing the latest audio data, and controlling the recording process.
 pub struct Recorder {
+    device: Device,
     stream: Stream,
     latest_audio_data: Arc<Mutex<AllocRingBuffer<f32>>>,
     sample_rate: f32,
+    config: StreamConfig,
+    writer: SegmentWriterHandle,
+    capture: Arc<Capture>,
+    writer_thread: Option<JoinHandle<()>>,
+    monitor: Option<Monitor>,
+    /// The device the monitor plays on, unless it was given a sink.
+    monitor_device: Option<Device>,
+    monitor_input: MonitorInputHandle,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder::new()
+    }
 }
 
 impl Recorder {
@@ -24,16 +86,58 @@ impl Recorder {
     /// # Returns
     /// * `Recorder` - A new instance of `Recorder`.
     pub fn new() -> Recorder {
-        let dev_and_cfg = setup_input_config();
-        let sample_rate = dev_and_cfg.cfg().sample_rate.0 as f32;
+        Recorder::from_dev_and_cfg(setup_input_config()).expect("Failed to build input stream")
+    }
+
+    /// Constructs a `Recorder` capturing from `device` with its default input
+    /// configuration.
+    ///
+    /// Use with a [`DeviceSelector`](crate::audio_setup::DeviceSelector) to
+    /// capture from one device while a [`Player`](crate::player::Player)
+    /// plays to another.
+    ///
+    /// # Returns
+    /// * `Recorder` - A new instance of `Recorder`.
+    pub fn with_device(device: &Device) -> Result<Recorder, anyhow::Error> {
+        Recorder::from_dev_and_cfg(AudioDevAndCfg::new(Some(device.clone()), None))
+    }
+
+    fn from_dev_and_cfg(dev_and_cfg: AudioDevAndCfg) -> Result<Recorder, anyhow::Error> {
+        let config = dev_and_cfg.cfg().clone();
+        let sample_rate = config.sample_rate.0 as f32;
         let latest_audio_data = init_ringbuffer(sample_rate as usize);
-        let stream = setup_live_input(latest_audio_data.clone(), dev_and_cfg);
+        let writer: SegmentWriterHandle = Arc::new(Mutex::new(None));
+        let capture = Arc::new(Capture {
+            ring: SampleRing::new(
+                config.sample_rate.0 as usize * config.channels as usize * CAPTURE_BUFFER_SECS,
+            ),
+            recording: AtomicBool::new(false),
+            overflowed: AtomicBool::new(false),
+            frames: AtomicU64::new(0),
+            stop: AtomicBool::new(false),
+        });
+        let monitor_input: MonitorInputHandle = Arc::new(Mutex::new(None));
+        let stream = Recorder::setup_input_stream(
+            dev_and_cfg.dev(),
+            &config,
+            latest_audio_data.clone(),
+            capture.clone(),
+            monitor_input.clone(),
+        )?;
 
-        Recorder {
+        Ok(Recorder {
+            device: dev_and_cfg.dev().clone(),
             stream,
             latest_audio_data,
             sample_rate,
-        }
+            config,
+            writer,
+            capture,
+            writer_thread: None,
+            monitor: None,
+            monitor_device: None,
+            monitor_input,
+        })
     }
 
     /// Starts the audio recording stream.
@@ -70,14 +174,305 @@ impl Recorder {
         self.latest_audio_data.clone()
     }
 
-    // pub fn record(&mut self) {
-        
-    // }
+    /// Starts recording a new clip into `library`.
+    ///
+    /// Captured audio is written to numbered segment files according to `policy`
+    /// while the live buffer keeps updating. The stream is started if needed.
+    ///
+    /// The writing happens on a thread of its own, which the input is queued
+    /// for. If it falls more than a few seconds behind, the recording stops
+    /// there and [`Recorder::stop_recording`] reports it.
+    ///
+    /// # Arguments
+    /// * `library` - The clip library that will own the recording.
+    /// * `policy` - When the recording rolls over into a new segment file.
+    pub fn start_recording(
+        &mut self,
+        library: &ClipLibrary,
+        policy: RolloverPolicy,
+    ) -> Result<(), anyhow::Error> {
+        let mut guard = self.writer.lock().unwrap();
+        if guard.is_some() {
+            return Err(anyhow!("a recording is already in progress"));
+        }
+        *guard = Some(Recording {
+            writer: library.begin_recording(self.wav_spec(), policy)?,
+            error: None,
+        });
+        drop(guard);
+
+        // Nothing reads the queue between recordings, so whatever the last one
+        // left behind can be thrown away here.
+        let capture = &self.capture;
+        capture.ring.skip(capture.ring.len());
+        capture.overflowed.store(false, Ordering::Relaxed);
+        capture.frames.store(0, Ordering::Relaxed);
+        capture.stop.store(false, Ordering::Relaxed);
+        self.writer_thread = Some(Recorder::spawn_writer(
+            capture.clone(),
+            self.writer.clone(),
+            self.config.channels as usize,
+        ));
+        capture.recording.store(true, Ordering::Release);
+        self.stream.play()?;
+        Ok(())
+    }
 
-    // fn setup_writer_for_recording(&self) -> Result<WavWriterHandle, anyhow::Error> {
-    //     let spec = AudioClip::wav_spec_from_config(&self.output_config);
-    //     let writer = WavWriter::create(&self.file_path, spec)?;
-    //     Ok(Arc::new(Mutex::new(Some(writer))))
-    // }
+    /// Stops the current recording and finalizes it in `library`.
+    ///
+    /// The input stream keeps running so the live buffer stays current.
+    ///
+    /// If writing the audio failed along the way, the recording stopped
+    /// there: what was written before is still kept as a clip, but the write
+    /// error is returned.
+    ///
+    /// # Returns
+    /// * `FinishedRecording` - The finished clip, with all of its segments, and
+    ///   any failure to post-process it.
+    pub fn stop_recording(
+        &mut self,
+        library: &ClipLibrary,
+    ) -> Result<FinishedRecording, anyhow::Error> {
+        self.finish_writing();
+        let recording = self
+            .writer
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| anyhow!("no recording in progress"))?;
+        let finished = library.finalize_recording(recording.writer);
+        match (recording.error, finished) {
+            (None, finished) => finished,
+            (Some(e), Ok(finished)) => Err(e.context(format!(
+                "recording failed; the audio before the failure was kept as clip '{}'",
+                finished.clip.id()
+            ))),
+            (Some(e), Err(_)) => Err(e.context("recording failed")),
+        }
+    }
+
+    /// Drops a marker at the current position of the recording.
+    ///
+    /// The marker is saved with the clip when the recording is stopped.
+    ///
+    /// # Returns
+    /// * `u32` - The id of the new marker.
+    pub fn add_marker(&self, label: impl Into<String>) -> Result<u32, anyhow::Error> {
+        let mut guard = self.writer.lock().unwrap();
+        let writer = &mut guard
+            .as_mut()
+            .ok_or_else(|| anyhow!("no recording in progress"))?
+            .writer;
+        // Mark what was captured, not what the writer thread has caught up to.
+        let position = self.capture.frames.load(Ordering::Relaxed);
+        Ok(writer.add_marker(position, label))
+    }
+
+    /// Returns `true` while a recording is being written to disk.
+    pub fn is_recording(&self) -> bool {
+        self.writer.lock().unwrap().is_some()
+    }
 
-}
\ No newline at end of file
+    /// Starts playing the input on `device` as it is captured, to check
+    /// microphone placement on headphones.
+    ///
+    /// The device is asked for small blocks to keep the delay short. The
+    /// stream is started if needed, and recording is unaffected.
+    ///
+    /// # Arguments
+    /// * `device` - The output device to listen on.
+    /// * `config` - Buffering, gain and feedback protection.
+    pub fn start_monitor(
+        &mut self,
+        device: &Device,
+        config: &MonitorConfig,
+    ) -> Result<(), anyhow::Error> {
+        let sink = CpalSink::new(device.clone())?.with_buffer_frames(MONITOR_BUFFER_FRAMES);
+        self.start_monitor_with_sink(sink, config)?;
+        self.monitor_device = Some(device.clone());
+        Ok(())
+    }
+
+    /// Starts playing the input on `sink` as it is captured.
+    pub fn start_monitor_with_sink(
+        &mut self,
+        sink: impl OutputSink + 'static,
+        config: &MonitorConfig,
+    ) -> Result<(), anyhow::Error> {
+        if self.monitor.is_some() {
+            return Err(anyhow!("the input is already being monitored"));
+        }
+        let monitor = Monitor::new(
+            sink,
+            self.config.sample_rate.0,
+            self.config.channels,
+            config,
+        )?;
+        *self.monitor_input.lock().unwrap() = Some(monitor.input());
+        self.monitor = Some(monitor);
+        self.stream.play()?;
+        Ok(())
+    }
+
+    /// Stops playing the input; the input stream keeps running.
+    pub fn stop_monitor(&mut self) {
+        self.monitor_input.lock().unwrap().take();
+        self.monitor = None;
+        self.monitor_device = None;
+    }
+
+    /// Measures the delay the input and monitor devices add outside their
+    /// callbacks, so [`MonitorLatency::round_trip`](monitor::MonitorLatency::round_trip)
+    /// can be reported.
+    ///
+    /// Hold the headphones to the microphone, or wire the output to the input,
+    /// first. The monitor stops playing the input while the test signal plays.
+    ///
+    /// # Arguments
+    /// * `config` - The test signal and how long a delay to look for.
+    ///
+    /// # Returns
+    /// * `LatencyMeasurement` - The delay found and how confident the match is.
+    pub fn calibrate_monitor(
+        &mut self,
+        config: &LatencyConfig,
+    ) -> Result<LatencyMeasurement, anyhow::Error> {
+        let (Some(monitor), Some(output)) = (&self.monitor, &self.monitor_device) else {
+            return Err(anyhow!("the input is not being monitored on a device"));
+        };
+        // The monitor would play the test signal back into the loopback.
+        let input = self.monitor_input.lock().unwrap().take();
+        let measured = measure_latency(&mut DeviceLoopback::new(&self.device, output), config);
+        *self.monitor_input.lock().unwrap() = input;
+        let measured = measured?;
+        monitor.set_device_latency(measured.latency);
+        Ok(measured)
+    }
+
+    /// Returns the measured latency and feedback state of the monitor, if the
+    /// input is being monitored.
+    pub fn monitor_status(&self) -> Option<MonitorStatus> {
+        self.monitor.as_ref().map(|monitor| monitor.status())
+    }
+
+    /// Unmutes the monitor after feedback was detected.
+    pub fn unmute_monitor(&self) {
+        if let Some(monitor) = &self.monitor {
+            monitor.unmute();
+        }
+    }
+
+    fn wav_spec(&self) -> WavSpec {
+        WavSpec {
+            channels: self.config.channels,
+            sample_rate: self.config.sample_rate.0,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        }
+    }
+
+    fn setup_input_stream(
+        device: &Device,
+        config: &StreamConfig,
+        latest_audio_data: Arc<Mutex<AllocRingBuffer<f32>>>,
+        capture: Arc<Capture>,
+        monitor_input: MonitorInputHandle,
+    ) -> Result<Stream, anyhow::Error> {
+        let channels = config.channels as usize;
+        let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+        let stream = device.build_input_stream(
+            config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                // Monitoring is the most sensitive to delay, so it goes first.
+                if let Some(monitor) = monitor_input.lock().unwrap().as_ref() {
+                    monitor.push(data);
+                }
+                Recorder::write_input_data(data, channels, &latest_audio_data, &capture);
+            },
+            err_fn,
+            None,
+        )?;
+        Ok(stream)
+    }
+
+    fn write_input_data(
+        input: &[f32],
+        channels: usize,
+        latest_audio_data: &Arc<Mutex<AllocRingBuffer<f32>>>,
+        capture: &Capture,
+    ) {
+        // The live buffer only holds mono data, so average each frame.
+        let mut buf = latest_audio_data.lock().unwrap();
+        for frame in input.chunks_exact(channels) {
+            buf.push(frame.iter().sum::<f32>() / channels as f32);
+        }
+        drop(buf);
+
+        // Queue the input for the writer thread; once it falls behind and
+        // input is dropped, the recording ends there.
+        let recording = capture.recording.load(Ordering::Acquire);
+        if recording && !capture.overflowed.load(Ordering::Relaxed) {
+            let queued = capture.ring.push(input, channels);
+            capture
+                .frames
+                .fetch_add((queued / channels) as u64, Ordering::Relaxed);
+            if queued < input.len() / channels * channels {
+                capture.overflowed.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    /// Starts the thread writing queued input to the recording.
+    fn spawn_writer(
+        capture: Arc<Capture>,
+        writer: SegmentWriterHandle,
+        channels: usize,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let mut buf = vec![0.0; 4096 * channels];
+            loop {
+                // Read the flags first, so an empty queue below means all
+                // input queued before they were set has been written.
+                let overflowed = capture.overflowed.load(Ordering::Acquire);
+                let stop = capture.stop.load(Ordering::Acquire);
+                let count = capture.ring.pop(&mut buf);
+                let mut guard = writer.lock().unwrap();
+                let Some(recording) = guard.as_mut() else {
+                    return;
+                };
+                if count > 0 {
+                    if recording.error.is_none() {
+                        if let Err(err) = recording.writer.write(&buf[..count]) {
+                            recording.error = Some(err);
+                        }
+                    }
+                    continue;
+                }
+                if overflowed && recording.error.is_none() {
+                    recording.error = Some(anyhow!("the disk could not keep up with the input"));
+                }
+                drop(guard);
+                if stop {
+                    return;
+                }
+                thread::sleep(WRITE_INTERVAL);
+            }
+        })
+    }
+
+    /// Stops queueing input and waits for the writer thread to write what is
+    /// queued.
+    fn finish_writing(&mut self) {
+        self.capture.recording.store(false, Ordering::Release);
+        self.capture.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.writer_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.finish_writing();
+    }
+}
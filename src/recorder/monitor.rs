@@ -0,0 +1,390 @@
+use super::ring::SampleRing;
+use crate::dsp::{ChannelMap, FeedbackConfig, FeedbackDetector, Resampler};
+use crate::player::OutputSink;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Output blocks requested from devices opened for monitoring.
+pub(super) const MONITOR_BUFFER_FRAMES: u32 = 128;
+
+/// Time taken to fade the output from full level to silence when feedback is
+/// detected.
+const MUTE_FADE: Duration = Duration::from_millis(10);
+
+/// Weight of the newest block in the running latency averages.
+const AVERAGE_WEIGHT: f64 = 0.05;
+
+/// Settings for listening to the input on an output device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonitorConfig {
+    /// Audio held back on top of one output block to ride out timing jitter
+    /// between the input and output devices. More means fewer dropouts but a
+    /// longer delay.
+    pub buffer: Duration,
+    /// Linear gain applied to the input; 1.0 leaves it unchanged.
+    pub gain: f32,
+    /// When to mute the output because of acoustic feedback, or `None` to
+    /// never mute.
+    pub feedback: Option<FeedbackConfig>,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        MonitorConfig {
+            buffer: Duration::from_millis(3),
+            gain: 1.0,
+            feedback: Some(FeedbackConfig::default()),
+        }
+    }
+}
+
+/// Measured delay from the input to the output, split by where it comes from.
+///
+/// The blocks and queue are running averages of what the callbacks actually
+/// did. What the devices add outside their callbacks is only known once it has
+/// been measured through a loopback, with
+/// [`Recorder::calibrate_monitor`](super::Recorder::calibrate_monitor) or
+/// [`Monitor::set_device_latency`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonitorLatency {
+    /// Length of the blocks delivered by the input device.
+    pub input: Duration,
+    /// Audio waiting between the input and output callbacks.
+    pub buffered: Duration,
+    /// Length of the blocks requested by the output device.
+    pub output: Duration,
+    /// Delay from handing a frame to the output to the input capturing it, as
+    /// measured through a loopback of the two devices: their converters and
+    /// driver buffers. `None` until measured.
+    pub devices: Option<Duration>,
+}
+
+impl MonitorLatency {
+    /// Returns the delay added by the blocks and queue between the two
+    /// callbacks.
+    pub fn buffering(&self) -> Duration {
+        self.input + self.buffered + self.output
+    }
+
+    /// Returns the delay from sound reaching the input to it leaving the
+    /// output, once the devices' share has been measured.
+    ///
+    /// A frame waits out the input block and the queue ahead of it; the
+    /// loopback measurement covers the rest, from the output callback on.
+    pub fn round_trip(&self) -> Option<Duration> {
+        self.devices
+            .map(|devices| devices + self.input + self.buffered)
+    }
+}
+
+/// What a [`Monitor`] has done so far.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorStatus {
+    /// Measured delay, once both devices have delivered audio.
+    pub latency: Option<MonitorLatency>,
+    /// Whether the output is muted because howling was detected.
+    pub muted: bool,
+    /// Frequency of the howl that muted the output, in Hz.
+    pub howl_frequency: Option<f32>,
+    /// Times the output ran out of input and played silence.
+    pub underruns: u64,
+    /// Input frames thrown away to keep the delay down.
+    pub dropped_frames: u64,
+}
+
+/// Input-side state, locked by the input callback and the controls but never
+/// by the output callback.
+struct MonitorInputState {
+    gain: f32,
+    resampler: Resampler,
+    map: ChannelMap,
+    detector: Option<FeedbackDetector>,
+    /// Gain actually applied, fading to silence while muted.
+    level: f32,
+    resampled: Vec<f32>,
+    mapped: Vec<f32>,
+    /// Running averages of the input block and of the audio queued ahead of
+    /// it, in output frames.
+    input_block: Option<f64>,
+    buffered: f64,
+    /// Delay the devices add outside their callbacks, once measured.
+    devices: Option<Duration>,
+}
+
+/// State shared between the input and output callbacks.
+///
+/// Audio passes from the input to the output through a lock-free ring, so a
+/// slow input callback can never make the output miss its deadline.
+struct MonitorShared {
+    output_rate: u32,
+    output_channels: usize,
+    buffer_frames: usize,
+    input: Mutex<MonitorInputState>,
+    /// Output frames waiting to be played.
+    ring: SampleRing,
+    /// Latest input block length in output frames, so the output can bound
+    /// the delay.
+    input_block: AtomicUsize,
+    /// Running average of the output block length in frames, as `f64` bits;
+    /// zero until the output has run.
+    output_block: AtomicU64,
+    underruns: AtomicU64,
+    dropped_frames: AtomicU64,
+}
+
+/// Output-side state, owned by the output callback.
+struct MonitorOutput {
+    shared: Arc<MonitorShared>,
+    /// Set once enough audio is queued to start playing.
+    primed: bool,
+    output_block: Option<f64>,
+}
+
+/// Plays input audio on an output sink as it is captured.
+///
+/// Input frames are converted to the sink's rate and channels and queued for
+/// as short a time as the two devices' timing allows: playback starts once one
+/// output block plus [`MonitorConfig::buffer`] is queued, and anything beyond
+/// that plus one input block is dropped, so the delay cannot creep up when the
+/// devices' clocks drift apart.
+///
+/// When a sustained howl is detected in the input, the output fades to silence
+/// and stays muted until [`Monitor::unmute`].
+pub struct Monitor {
+    _sink: Box<dyn OutputSink>,
+    shared: Arc<MonitorShared>,
+}
+
+impl Monitor {
+    /// Starts playing input with the given sample rate and channel count on `sink`.
+    ///
+    /// Feed the input with [`Monitor::input`].
+    pub fn new(
+        mut sink: impl OutputSink + 'static,
+        input_rate: u32,
+        input_channels: u16,
+        config: &MonitorConfig,
+    ) -> Result<Monitor, anyhow::Error> {
+        let output_rate = sink.sample_rate();
+        let output_channels = sink.channels();
+        let buffer_frames = (config.buffer.as_secs_f64() * output_rate as f64) as usize;
+        let shared = Arc::new(MonitorShared {
+            output_rate,
+            output_channels: output_channels as usize,
+            buffer_frames,
+            input: Mutex::new(MonitorInputState {
+                gain: config.gain.max(0.0),
+                resampler: Resampler::new(input_rate, output_rate, input_channels),
+                map: ChannelMap::auto(input_channels, output_channels),
+                detector: config
+                    .feedback
+                    .map(|feedback| FeedbackDetector::new(&feedback, input_rate, input_channels)),
+                level: config.gain.max(0.0),
+                resampled: Vec::new(),
+                mapped: Vec::new(),
+                input_block: None,
+                buffered: 0.0,
+                devices: None,
+            }),
+            // Room for a second of audio on top of the buffer, far more than
+            // the output is ever allowed to fall behind.
+            ring: SampleRing::new(
+                (buffer_frames + output_rate as usize) * output_channels as usize,
+            ),
+            input_block: AtomicUsize::new(0),
+            output_block: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+            dropped_frames: AtomicU64::new(0),
+        });
+
+        let mut output = MonitorOutput {
+            shared: shared.clone(),
+            primed: false,
+            output_block: None,
+        };
+        sink.start(Box::new(move |data: &mut [f32]| {
+            output.render(data);
+            true
+        }))?;
+
+        Ok(Monitor {
+            _sink: Box::new(sink),
+            shared,
+        })
+    }
+
+    /// Returns a handle that feeds captured frames to the monitor, for use in
+    /// an input callback.
+    pub fn input(&self) -> MonitorInput {
+        MonitorInput {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Returns the measured latency, once both devices have delivered audio.
+    pub fn latency(&self) -> Option<MonitorLatency> {
+        self.shared.latency(&self.shared.input.lock().unwrap())
+    }
+
+    /// Sets the delay the devices add outside their callbacks, so the round
+    /// trip can be reported.
+    ///
+    /// Measure it with [`measure_latency`](crate::audio_setup::measure_latency)
+    /// through a [`DeviceLoopback`](crate::audio_setup::DeviceLoopback) of the
+    /// same two devices.
+    pub fn set_device_latency(&self, latency: Duration) {
+        self.shared.input.lock().unwrap().devices = Some(latency);
+    }
+
+    /// Returns the latency, mute state and dropout counts.
+    pub fn status(&self) -> MonitorStatus {
+        let input = self.shared.input.lock().unwrap();
+        let howl_frequency = input.detector.as_ref().and_then(|d| d.frequency());
+        MonitorStatus {
+            latency: self.shared.latency(&input),
+            muted: howl_frequency.is_some(),
+            howl_frequency,
+            underruns: self.shared.underruns.load(Ordering::Relaxed),
+            dropped_frames: self.shared.dropped_frames.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns `true` while the output is muted because of feedback.
+    pub fn is_muted(&self) -> bool {
+        let input = self.shared.input.lock().unwrap();
+        input.detector.as_ref().is_some_and(|d| d.is_howling())
+    }
+
+    /// Unmutes the output after feedback, and starts listening for it again.
+    ///
+    /// Move the microphone away from the speaker or headphones first, or the
+    /// howl will come straight back.
+    pub fn unmute(&self) {
+        if let Some(detector) = self.shared.input.lock().unwrap().detector.as_mut() {
+            detector.reset();
+        }
+    }
+
+    /// Sets the linear gain applied to the input.
+    pub fn set_gain(&self, gain: f32) {
+        self.shared.input.lock().unwrap().gain = gain.max(0.0);
+    }
+}
+
+/// Feeds captured frames to a [`Monitor`].
+#[derive(Clone)]
+pub struct MonitorInput {
+    shared: Arc<MonitorShared>,
+}
+
+impl MonitorInput {
+    /// Queues interleaved input frames for playback.
+    pub fn push(&self, input: &[f32]) {
+        self.shared.push(input);
+    }
+}
+
+impl MonitorShared {
+    fn latency(&self, input: &MonitorInputState) -> Option<MonitorLatency> {
+        let secs = |frames: f64| Duration::from_secs_f64(frames / self.output_rate as f64);
+        let output_block = f64::from_bits(self.output_block.load(Ordering::Relaxed));
+        Some(MonitorLatency {
+            input: secs(input.input_block?),
+            buffered: secs(input.buffered),
+            output: secs((output_block > 0.0).then_some(output_block)?),
+            devices: input.devices,
+        })
+    }
+
+    fn push(&self, input: &[f32]) {
+        let channels = self.output_channels;
+        let mut state = self.input.lock().unwrap();
+        let state = &mut *state;
+
+        state.resampled.clear();
+        state.resampler.process(input, &mut state.resampled);
+        let frames = state.resampled.len() / state.map.inputs() as usize;
+        state.mapped.resize(frames * channels, 0.0);
+        state.map.apply(&state.resampled, &mut state.mapped);
+        let muted = match state.detector.as_mut() {
+            Some(detector) => detector.push(input),
+            None => false,
+        };
+
+        // The first frame of this block waits for everything already queued.
+        let queued = self.ring.len() / channels;
+        state.buffered = average(Some(state.buffered), queued as f64);
+        state.input_block = Some(average(state.input_block, frames as f64));
+        self.input_block.store(frames, Ordering::Relaxed);
+
+        let target = if muted { 0.0 } else { state.gain };
+        let step = 1.0 / (MUTE_FADE.as_secs_f32() * self.output_rate as f32);
+        for frame in state.mapped.chunks_exact_mut(channels) {
+            state.level = if state.level > target {
+                (state.level - step).max(target)
+            } else {
+                (state.level + step).min(target)
+            };
+            frame.iter_mut().for_each(|s| *s *= state.level);
+        }
+
+        let written = self.ring.push(&state.mapped, channels);
+        let lost = (state.mapped.len() - written) / channels;
+        if lost > 0 {
+            self.dropped_frames
+                .fetch_add(lost as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl MonitorOutput {
+    fn render(&mut self, output: &mut [f32]) {
+        let shared = &*self.shared;
+        let channels = shared.output_channels;
+        let frames = output.len() / channels;
+        let output_block = average(self.output_block, frames as f64);
+        self.output_block = Some(output_block);
+        shared
+            .output_block
+            .store(output_block.to_bits(), Ordering::Relaxed);
+
+        // Anything beyond the buffer and one block from each side only adds
+        // delay, so it is dropped before it can be played.
+        let queued = shared.ring.len() / channels;
+        let limit = shared.buffer_frames
+            + output_block.round() as usize
+            + shared.input_block.load(Ordering::Relaxed);
+        let excess = queued.saturating_sub(limit);
+        if excess > 0 {
+            shared.ring.skip(excess * channels);
+            shared
+                .dropped_frames
+                .fetch_add(excess as u64, Ordering::Relaxed);
+        }
+        let queued = queued - excess;
+
+        if !self.primed {
+            if queued < shared.buffer_frames + frames {
+                output.fill(0.0);
+                return;
+            }
+            self.primed = true;
+        }
+
+        let take = shared.ring.pop(&mut output[..frames * channels]) / channels;
+        if take < frames {
+            output[take * channels..].fill(0.0);
+            shared.underruns.fetch_add(1, Ordering::Relaxed);
+            self.primed = false;
+        }
+    }
+}
+
+/// Folds `value` into the running average `current`, starting it if needed.
+fn average(current: Option<f64>, value: f64) -> f64 {
+    match current {
+        Some(current) => current + (value - current) * AVERAGE_WEIGHT,
+        None => value,
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Fixed-size queue of samples handed from one thread to another without locking.
+///
+/// Only one thread may write and one read at a time: the writer calls
+/// [`SampleRing::push`], the reader [`SampleRing::pop`] and [`SampleRing::skip`].
+pub(super) struct SampleRing {
+    /// Samples stored as their bits, so both sides can share them safely.
+    samples: Box<[AtomicU32]>,
+    /// Total samples read and written so far; their difference is the fill.
+    read: AtomicUsize,
+    written: AtomicUsize,
+}
+
+impl SampleRing {
+    /// Creates a ring holding at least `capacity` samples.
+    pub fn new(capacity: usize) -> SampleRing {
+        let capacity = capacity.max(1).next_power_of_two();
+        SampleRing {
+            samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            read: AtomicUsize::new(0),
+            written: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of samples waiting to be read.
+    pub fn len(&self) -> usize {
+        let read = self.read.load(Ordering::Acquire);
+        self.written.load(Ordering::Acquire).wrapping_sub(read)
+    }
+
+    /// Appends as many whole chunks of `chunk` samples from `samples` as fit.
+    ///
+    /// # Returns
+    /// * `usize` - The number of samples written.
+    pub fn push(&self, samples: &[f32], chunk: usize) -> usize {
+        let mask = self.samples.len() - 1;
+        let written = self.written.load(Ordering::Relaxed);
+        let free = self.samples.len() - written.wrapping_sub(self.read.load(Ordering::Acquire));
+        let count = samples.len().min(free) / chunk.max(1) * chunk.max(1);
+        for (i, sample) in samples[..count].iter().enumerate() {
+            self.samples[written.wrapping_add(i) & mask].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written
+            .store(written.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Moves waiting samples into the start of `output`.
+    ///
+    /// # Returns
+    /// * `usize` - The number of samples read.
+    pub fn pop(&self, output: &mut [f32]) -> usize {
+        let mask = self.samples.len() - 1;
+        let read = self.read.load(Ordering::Relaxed);
+        let count = output.len().min(self.len());
+        for (i, out) in output[..count].iter_mut().enumerate() {
+            *out =
+                f32::from_bits(self.samples[read.wrapping_add(i) & mask].load(Ordering::Relaxed));
+        }
+        self.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Throws away up to `count` waiting samples.
+    pub fn skip(&self, count: usize) {
+        let count = count.min(self.len());
+        self.read.fetch_add(count, Ordering::Release);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::crypto::{FileWriter, Key};
+use crate::library::Marker;
+use hound::{WavSpec, WavWriter};
+use serde::{Deserialize, Serialize};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Returns the size of the header `hound` writes before the samples of a file
+/// with the given spec.
+///
+/// More than two channels or more than 16 bits per sample take the 68-byte
+/// `WAVE_FORMAT_EXTENSIBLE` header; anything else the canonical 44 bytes.
+fn wav_header_bytes(spec: &WavSpec) -> u64 {
+    if spec.channels > 2 || spec.bits_per_sample > 16 {
+        68
+    } else {
+        44
+    }
+}
+
+/// Describes when a recording session should roll over into a new segment file.
+///
+/// Both limits are optional; whichever is reached first closes the current segment.
+/// A policy with no limits writes a single segment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RolloverPolicy {
+    pub max_duration: Option<Duration>,
+    pub max_bytes: Option<u64>,
+}
+
+impl RolloverPolicy {
+    /// Creates a policy that rolls over after exactly `frames` frames of the given spec.
+    pub fn with_segment_frames(frames: u64, spec: &WavSpec) -> RolloverPolicy {
+        let bytes_per_frame = spec.channels as u64 * (spec.bits_per_sample as u64 / 8);
+        RolloverPolicy {
+            max_duration: None,
+            max_bytes: Some(wav_header_bytes(spec) + frames * bytes_per_frame),
+        }
+    }
+
+    /// Computes the maximum number of frames a single segment may hold for the given spec.
+    ///
+    /// # Returns
+    /// * `Option<u64>` - The frame limit, or `None` if the policy never rolls over.
+    pub fn max_frames(&self, spec: &WavSpec) -> Option<u64> {
+        let by_duration = self
+            .max_duration
+            .map(|d| (d.as_secs_f64() * spec.sample_rate as f64) as u64);
+        let bytes_per_frame = spec.channels as u64 * (spec.bits_per_sample as u64 / 8);
+        let by_size = self
+            .max_bytes
+            .map(|b| b.saturating_sub(wav_header_bytes(spec)) / bytes_per_frame);
+        match (by_duration, by_size) {
+            (Some(a), Some(b)) => Some(a.min(b).max(1)),
+            (a, b) => a.or(b).map(|n| n.max(1)),
+        }
+    }
+}
+
+/// A finished segment file and the number of frames it contains.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    pub file: String,
+    pub frames: u64,
+    /// Hex SHA-256 of the segment's audio data, set once the clip is finalized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Writes interleaved samples into numbered WAV segment files.
+///
+/// Segments are named `segment-000.wav`, `segment-001.wav`, ... inside the target
+/// directory. Boundaries always fall on a frame, so concatenating the segments in
+/// order yields exactly the samples passed to [`SegmentWriter::write`].
+pub struct SegmentWriter {
+    dir: PathBuf,
+    spec: WavSpec,
+    max_frames: Option<u64>,
+    key: Option<Key>,
+    writer: Option<WavWriter<BufWriter<FileWriter>>>,
+    segment_frames: u64,
+    total_frames: u64,
+    segments: Vec<SegmentInfo>,
+    partial: Vec<f32>,
+    markers: Vec<Marker>,
+}
+
+impl SegmentWriter {
+    /// Creates a new `SegmentWriter` and opens the first segment.
+    ///
+    /// # Arguments
+    /// * `dir` - The directory segments are written into. It is created if missing.
+    /// * `spec` - The WAV spec shared by all segments.
+    /// * `policy` - When to roll over into a new segment.
+    pub fn create(
+        dir: impl AsRef<Path>,
+        spec: WavSpec,
+        policy: RolloverPolicy,
+    ) -> Result<SegmentWriter, anyhow::Error> {
+        SegmentWriter::create_with_key(dir, spec, policy, None)
+    }
+
+    /// Creates a new `SegmentWriter` that encrypts segments with `key` as they are written.
+    ///
+    /// With no key this is the same as [`SegmentWriter::create`].
+    pub fn create_with_key(
+        dir: impl AsRef<Path>,
+        spec: WavSpec,
+        policy: RolloverPolicy,
+        key: Option<Key>,
+    ) -> Result<SegmentWriter, anyhow::Error> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let mut writer = SegmentWriter {
+            dir,
+            spec,
+            max_frames: policy.max_frames(&spec),
+            key,
+            writer: None,
+            segment_frames: 0,
+            total_frames: 0,
+            segments: Vec::new(),
+            partial: Vec::with_capacity(spec.channels as usize),
+            markers: Vec::new(),
+        };
+        writer.open_segment()?;
+        Ok(writer)
+    }
+
+    /// Returns the file name used for the segment with the given index.
+    pub fn segment_file_name(index: usize) -> String {
+        format!("segment-{:03}.wav", index)
+    }
+
+    /// Writes interleaved samples, rolling over to a new segment whenever the policy limit is hit.
+    ///
+    /// Samples that do not complete a frame are held back until the next call, so a
+    /// frame is never split across two segments.
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), anyhow::Error> {
+        let channels = self.spec.channels as usize;
+        let mut samples = samples;
+
+        if !self.partial.is_empty() {
+            let needed = (channels - self.partial.len()).min(samples.len());
+            self.partial.extend_from_slice(&samples[..needed]);
+            samples = &samples[needed..];
+            if self.partial.len() < channels {
+                return Ok(());
+            }
+            let frame = std::mem::take(&mut self.partial);
+            self.write_frames(&frame)?;
+        }
+
+        let whole = samples.len() - samples.len() % channels;
+        self.write_frames(&samples[..whole])?;
+        self.partial.extend_from_slice(&samples[whole..]);
+        Ok(())
+    }
+
+    /// Returns the total number of frames written across all segments.
+    pub fn frames_written(&self) -> u64 {
+        self.total_frames
+    }
+
+    /// Marks a position in the recording.
+    ///
+    /// # Arguments
+    /// * `position` - The marked frame, counted from the start of the recording.
+    /// * `label` - A short description of the marked spot.
+    ///
+    /// # Returns
+    /// * `u32` - The id of the new marker.
+    pub fn add_marker(&mut self, position: u64, label: impl Into<String>) -> u32 {
+        Marker::insert(&mut self.markers, position, None, label.into())
+    }
+
+    /// Marks a region of the recording, from frame `start` up to `end`.
+    ///
+    /// # Returns
+    /// * `u32` - The id of the new marker.
+    pub fn add_region(&mut self, start: u64, end: u64, label: impl Into<String>) -> u32 {
+        Marker::insert(&mut self.markers, start, Some(end.max(start)), label.into())
+    }
+
+    /// Returns the markers added so far, ordered by position.
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    /// Returns the WAV spec used for every segment.
+    pub fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    /// Returns the directory segments are written into.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Finalizes the current segment and returns the list of written segments.
+    ///
+    /// An incomplete trailing frame is discarded. An empty trailing segment is only
+    /// kept if it is the sole segment, so a clip always has at least one file.
+    pub fn finalize(mut self) -> Result<Vec<SegmentInfo>, anyhow::Error> {
+        self.close_segment()?;
+        if self.segments.len() > 1 && self.segments.last().map(|s| s.frames) == Some(0) {
+            let empty = self.segments.pop().unwrap();
+            std::fs::remove_file(self.dir.join(empty.file))?;
+        }
+        Ok(self.segments)
+    }
+
+    fn write_frames(&mut self, mut samples: &[f32]) -> Result<(), anyhow::Error> {
+        let channels = self.spec.channels as usize;
+        while !samples.is_empty() {
+            let frames = (samples.len() / channels) as u64;
+            let room = match self.max_frames {
+                Some(max) => max - self.segment_frames,
+                None => frames,
+            };
+            let take = frames.min(room);
+            let writer = self.writer.as_mut().expect("segment writer is open");
+            for &sample in &samples[..take as usize * channels] {
+                writer.write_sample(sample)?;
+            }
+            self.segment_frames += take;
+            self.total_frames += take;
+            samples = &samples[take as usize * channels..];
+
+            if Some(self.segment_frames) == self.max_frames {
+                self.close_segment()?;
+                self.open_segment()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn open_segment(&mut self) -> Result<(), anyhow::Error> {
+        let file = SegmentWriter::segment_file_name(self.segments.len());
+        let out = FileWriter::create(self.dir.join(&file), self.key.as_ref())?;
+        self.writer = Some(WavWriter::new(BufWriter::new(out), self.spec)?);
+        self.segments.push(SegmentInfo {
+            file,
+            frames: 0,
+            sha256: None,
+        });
+        self.segment_frames = 0;
+        Ok(())
+    }
+
+    fn close_segment(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+            if let Some(segment) = self.segments.last_mut() {
+                segment.frames = self.segment_frames;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use super::Chunk;
+use anyhow::anyhow;
+
+/// Size of the fixed part of a `bext` chunk, before the coding history.
+const BEXT_FIXED_BYTES: usize = 602;
+
+/// The Broadcast WAV (EBU Tech 3285) `bext` chunk.
+///
+/// Text fields longer than their slot in the chunk are truncated when encoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bext {
+    /// Free text description, up to 256 bytes.
+    pub description: String,
+    /// Name of the originating application, up to 32 bytes.
+    pub originator: String,
+    /// Unique reference for the recording, up to 32 bytes.
+    pub originator_reference: String,
+    /// Date the recording started, as `yyyy-mm-dd`.
+    pub origination_date: String,
+    /// Time the recording started, as `hh:mm:ss`.
+    pub origination_time: String,
+    /// Position of the first sample, counted in samples since midnight.
+    pub time_reference: u64,
+    pub coding_history: String,
+}
+
+/// Writes `text` into a field of `len` bytes, cut at the last whole character
+/// that fits and padded with zeros.
+fn put_text(out: &mut Vec<u8>, text: &str, len: usize) {
+    let mut take = text.len().min(len);
+    while !text.is_char_boundary(take) {
+        take -= 1;
+    }
+    out.extend_from_slice(&text.as_bytes()[..take]);
+    out.resize(out.len() + len - take, 0);
+}
+
+fn get_text(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim_end().to_string()
+}
+
+impl Bext {
+    /// Encodes the fields as a version 1 `bext` chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(BEXT_FIXED_BYTES + self.coding_history.len());
+        put_text(&mut data, &self.description, 256);
+        put_text(&mut data, &self.originator, 32);
+        put_text(&mut data, &self.originator_reference, 32);
+        put_text(&mut data, &self.origination_date, 10);
+        put_text(&mut data, &self.origination_time, 8);
+        data.extend_from_slice(&(self.time_reference as u32).to_le_bytes());
+        data.extend_from_slice(&((self.time_reference >> 32) as u32).to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        // UMID, the version 2 loudness fields and the reserved area are left zeroed.
+        data.resize(BEXT_FIXED_BYTES, 0);
+        data.extend_from_slice(self.coding_history.as_bytes());
+        Chunk { id: *b"bext", data }
+    }
+
+    /// Decodes a `bext` chunk.
+    pub fn from_chunk(chunk: &Chunk) -> Result<Bext, anyhow::Error> {
+        let data = &chunk.data;
+        if &chunk.id != b"bext" || data.len() < BEXT_FIXED_BYTES {
+            return Err(anyhow!("invalid bext chunk"));
+        }
+        let low = u32::from_le_bytes(data[338..342].try_into()?) as u64;
+        let high = u32::from_le_bytes(data[342..346].try_into()?) as u64;
+        Ok(Bext {
+            description: get_text(&data[0..256]),
+            originator: get_text(&data[256..288]),
+            originator_reference: get_text(&data[288..320]),
+            origination_date: get_text(&data[320..330]),
+            origination_time: get_text(&data[330..338]),
+            time_reference: high << 32 | low,
+            coding_history: get_text(&data[BEXT_FIXED_BYTES..]),
+        })
+    }
+}
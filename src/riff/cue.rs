@@ -0,0 +1,123 @@
+use super::Chunk;
+
+/// A cue point: a labelled sample position inside a WAV file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CuePoint {
+    pub id: u32,
+    /// Position in frames from the start of the file's audio.
+    pub position: u32,
+    /// Length in frames of the region starting at `position`; 0 for a single point.
+    pub length: u32,
+    pub label: String,
+}
+
+/// Encodes cue points as a `cue ` chunk and a `LIST adtl` chunk of `labl` entries,
+/// plus an `ltxt` entry giving the length of each region.
+pub fn encode_cue_points(points: &[CuePoint]) -> Vec<Chunk> {
+    let mut cue = Vec::with_capacity(4 + points.len() * 24);
+    cue.extend_from_slice(&(points.len() as u32).to_le_bytes());
+    for point in points {
+        cue.extend_from_slice(&point.id.to_le_bytes());
+        cue.extend_from_slice(&point.position.to_le_bytes());
+        cue.extend_from_slice(b"data");
+        cue.extend_from_slice(&0u32.to_le_bytes()); // chunk start
+        cue.extend_from_slice(&0u32.to_le_bytes()); // block start
+        cue.extend_from_slice(&point.position.to_le_bytes());
+    }
+
+    let mut list = b"adtl".to_vec();
+    for point in points {
+        let mut text = point.label.as_bytes().to_vec();
+        text.push(0);
+        list.extend_from_slice(b"labl");
+        list.extend_from_slice(&(4 + text.len() as u32).to_le_bytes());
+        list.extend_from_slice(&point.id.to_le_bytes());
+        list.extend_from_slice(&text);
+        if text.len() % 2 == 1 {
+            list.push(0);
+        }
+    }
+    for point in points.iter().filter(|p| p.length > 0) {
+        list.extend_from_slice(b"ltxt");
+        list.extend_from_slice(&20u32.to_le_bytes());
+        list.extend_from_slice(&point.id.to_le_bytes());
+        list.extend_from_slice(&point.length.to_le_bytes());
+        list.extend_from_slice(b"rgn ");
+        // Country, language, dialect and code page are left unspecified.
+        list.extend_from_slice(&[0u8; 8]);
+    }
+
+    vec![
+        Chunk {
+            id: *b"cue ",
+            data: cue,
+        },
+        Chunk {
+            id: *b"LIST",
+            data: list,
+        },
+    ]
+}
+
+/// Decodes cue points from `cue ` and `LIST adtl` chunks.
+///
+/// Chunks of other kinds are ignored, as are malformed entries. Cue points
+/// without a label get an empty one, and those without an `ltxt` entry a length of 0.
+pub fn decode_cue_points(chunks: &[Chunk]) -> Vec<CuePoint> {
+    let u32_at = |data: &[u8], at: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+    };
+
+    let mut points = Vec::new();
+    for chunk in chunks.iter().filter(|c| &c.id == b"cue ") {
+        // The count comes from the file, so never trust it past the chunk's end.
+        let count = u32_at(&chunk.data, 0).unwrap_or(0) as usize;
+        let count = count.min(chunk.data.len().saturating_sub(4) / 24);
+        for i in 0..count {
+            let base = 4 + i * 24;
+            if let (Some(id), Some(position)) =
+                (u32_at(&chunk.data, base), u32_at(&chunk.data, base + 20))
+            {
+                points.push(CuePoint {
+                    id,
+                    position,
+                    length: 0,
+                    label: String::new(),
+                });
+            }
+        }
+    }
+
+    for chunk in chunks
+        .iter()
+        .filter(|c| &c.id == b"LIST" && c.data.get(..4) == Some(b"adtl"))
+    {
+        let data = &chunk.data;
+        let mut pos = 4;
+        while pos + 8 <= data.len() {
+            let id = &data[pos..pos + 4];
+            let size = u32_at(data, pos + 4).unwrap_or(0) as usize;
+            let body = match data.get(pos + 8..pos + 8 + size) {
+                Some(body) => body,
+                None => break,
+            };
+            if id == b"labl" && body.len() >= 4 {
+                let cue_id = u32_at(body, 0).unwrap_or(0);
+                let text = &body[4..];
+                let text = text.split(|&b| b == 0).next().unwrap_or(&[]);
+                if let Some(point) = points.iter_mut().find(|p| p.id == cue_id) {
+                    point.label = String::from_utf8_lossy(text).into_owned();
+                }
+            } else if id == b"ltxt" && body.len() >= 8 {
+                let cue_id = u32_at(body, 0).unwrap_or(0);
+                if let Some(point) = points.iter_mut().find(|p| p.id == cue_id) {
+                    point.length = u32_at(body, 4).unwrap_or(0);
+                }
+            }
+            pos += 8 + size + (size & 1);
+        }
+    }
+
+    points.sort_by_key(|p| p.position);
+    points
+}
@@ -0,0 +1,92 @@
+use super::Chunk;
+
+/// Version of the iXML specification written into the chunk.
+const IXML_VERSION: &str = "2.10";
+
+/// The subset of an `iXML` chunk used for clip metadata.
+///
+/// The title is stored in the standard `NOTE` element, tags as `TAG` elements
+/// inside a `TAGS` element, which iXML readers that do not know it ignore.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IXml {
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Returns the text of every `<name>...</name>` element in `xml`, in order.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", name), format!("</{}>", name));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        match rest.find(&close) {
+            Some(end) => {
+                found.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+impl IXml {
+    /// Encodes the fields as an `iXML` chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<BWFXML>\n");
+        xml.push_str(&format!(
+            "  <IXML_VERSION>{}</IXML_VERSION>\n",
+            IXML_VERSION
+        ));
+        if let Some(title) = &self.title {
+            xml.push_str(&format!("  <NOTE>{}</NOTE>\n", escape(title)));
+        }
+        if !self.tags.is_empty() {
+            xml.push_str("  <TAGS>\n");
+            for tag in &self.tags {
+                xml.push_str(&format!("    <TAG>{}</TAG>\n", escape(tag)));
+            }
+            xml.push_str("  </TAGS>\n");
+        }
+        xml.push_str("</BWFXML>\n");
+        Chunk {
+            id: *b"iXML",
+            data: xml.into_bytes(),
+        }
+    }
+
+    /// Decodes an `iXML` chunk, ignoring elements other than those above.
+    pub fn from_chunk(chunk: &Chunk) -> IXml {
+        let xml = String::from_utf8_lossy(&chunk.data);
+        let first = |name: &str| {
+            elements(&xml, name)
+                .first()
+                .map(|text| unescape(text.trim()))
+        };
+        IXml {
+            title: first("NOTE"),
+            tags: elements(&xml, "TAGS")
+                .into_iter()
+                .flat_map(|tags| elements(tags, "TAG"))
+                .map(|tag| unescape(tag.trim()))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+mod bext;
+mod cue;
+mod ixml;
+
+pub use bext::Bext;
+pub use cue::{decode_cue_points, encode_cue_points, CuePoint};
+pub use ixml::IXml;
+
+use anyhow::anyhow;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// A RIFF chunk identified by its four character code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    /// Returns the list type of a `LIST` chunk, such as `INFO` or `adtl`.
+    pub fn list_type(&self) -> Option<&[u8]> {
+        if &self.id == b"LIST" {
+            self.data.get(..4)
+        } else {
+            None
+        }
+    }
+}
+
+/// Location of a chunk inside a file.
+#[derive(Debug, Clone, Copy)]
+struct ChunkHeader {
+    id: [u8; 4],
+    /// Offset of the chunk's data, just after its 8 byte header.
+    offset: u64,
+    size: u32,
+}
+
+impl ChunkHeader {
+    /// Offset of the next chunk; chunks are padded to an even length.
+    fn end(&self) -> u64 {
+        self.offset + self.size as u64 + (self.size as u64 & 1)
+    }
+}
+
+fn read_u32(file: &mut impl Read) -> Result<u32, anyhow::Error> {
+    let mut buf = [0u8; 4];
+    file.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Lists the top-level chunks of a RIFF/WAVE file.
+fn scan(file: &mut File) -> Result<Vec<ChunkHeader>, anyhow::Error> {
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(anyhow!("not a RIFF/WAVE file"));
+    }
+    let mut headers = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= len {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut id = [0u8; 4];
+        file.read_exact(&mut id)?;
+        let size = read_u32(file)?;
+        let header = ChunkHeader {
+            id,
+            offset: pos + 8,
+            size,
+        };
+        if header.offset + size as u64 > len {
+            return Err(anyhow!(
+                "chunk '{}' runs past the end of the file",
+                String::from_utf8_lossy(&id)
+            ));
+        }
+        headers.push(header);
+        pos = header.end();
+    }
+    Ok(headers)
+}
+
+/// Returns the byte range the `data` chunk of a WAV stream claims to occupy.
+///
+/// Unlike the other functions here this does not check the range against the
+/// stream's length, so it can be used to tell how much of a truncated file is left.
+pub fn data_range(file: &mut (impl Read + Seek)) -> Result<Range<u64>, anyhow::Error> {
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(anyhow!("not a RIFF/WAVE file"));
+    }
+    let mut pos = 12;
+    while pos + 8 <= len {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut id = [0u8; 4];
+        file.read_exact(&mut id)?;
+        let size = read_u32(file)? as u64;
+        if &id == b"data" {
+            return Ok(pos + 8..pos + 8 + size);
+        }
+        pos += 8 + size + (size & 1);
+    }
+    Err(anyhow!("WAV file has no data chunk"))
+}
+
+/// Reads every top-level chunk with one of the given ids, in file order.
+pub fn read_chunks(path: impl AsRef<Path>, ids: &[[u8; 4]]) -> Result<Vec<Chunk>, anyhow::Error> {
+    let mut file = File::open(path)?;
+    let mut chunks = Vec::new();
+    for header in scan(&mut file)? {
+        if ids.contains(&header.id) {
+            let mut data = vec![0u8; header.size as usize];
+            file.seek(SeekFrom::Start(header.offset))?;
+            file.read_exact(&mut data)?;
+            chunks.push(Chunk {
+                id: header.id,
+                data,
+            });
+        }
+    }
+    Ok(chunks)
+}
+
+/// Replaces the metadata chunks stored after the audio of a WAV file.
+///
+/// Every chunk following the `data` chunk for which `remove` returns `true` is
+/// dropped, then `chunks` are appended and the RIFF size is updated. The audio
+/// itself is never rewritten, so this is cheap even for long recordings.
+pub fn write_chunks(
+    path: impl AsRef<Path>,
+    remove: impl Fn(&Chunk) -> bool,
+    chunks: &[Chunk],
+) -> Result<(), anyhow::Error> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let headers = scan(&mut file)?;
+    let data_index = headers
+        .iter()
+        .position(|h| &h.id == b"data")
+        .ok_or_else(|| anyhow!("WAV file has no data chunk"))?;
+
+    let mut kept = Vec::new();
+    for header in &headers[data_index + 1..] {
+        let mut data = vec![0u8; header.size as usize];
+        file.seek(SeekFrom::Start(header.offset))?;
+        file.read_exact(&mut data)?;
+        let existing = Chunk {
+            id: header.id,
+            data,
+        };
+        if !remove(&existing) {
+            kept.push(existing);
+        }
+    }
+
+    let audio_end = headers[data_index].end();
+    file.set_len(audio_end)?;
+    file.seek(SeekFrom::Start(audio_end))?;
+    for chunk in kept.iter().chain(chunks) {
+        file.write_all(&chunk.id)?;
+        file.write_all(&(chunk.data.len() as u32).to_le_bytes())?;
+        file.write_all(&chunk.data)?;
+        if chunk.data.len() % 2 == 1 {
+            file.write_all(&[0])?;
+        }
+    }
+    let riff_size = file.stream_position()? - 8;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&(riff_size as u32).to_le_bytes())?;
+    file.flush()?;
+    Ok(())
+}